@@ -7,12 +7,14 @@ extern crate libc;
 #[cfg(target_os = "nacl")]
 extern crate libressl_pnacl_sys;
 
-use libc::{c_void, c_int, c_char, c_ulong, c_long, c_uint, c_uchar, size_t};
+use libc::{c_void, c_int, c_char, c_ulong, c_long, c_uint, c_uchar, size_t, time_t};
 use std::mem;
 use std::sync::{Mutex, MutexGuard};
 use std::sync::{Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
 
 pub type ASN1_INTEGER = c_void;
+pub type ASN1_OBJECT = c_void;
 pub type ASN1_STRING = c_void;
 pub type ASN1_TIME = c_void;
 pub type BIO = c_void;
@@ -20,23 +22,38 @@ pub type BIO_METHOD = c_void;
 pub type BN_CTX = c_void;
 pub type COMP_METHOD = c_void;
 pub type CRYPTO_EX_DATA = c_void;
+pub type EC_KEY = c_void;
 pub type ENGINE = c_void;
 pub type EVP_CIPHER = c_void;
 pub type EVP_CIPHER_CTX = c_void;
 pub type EVP_MD = c_void;
 pub type EVP_PKEY = c_void;
 pub type EVP_PKEY_CTX = c_void;
+pub type OCSP_BASICRESP = c_void;
+pub type OCSP_CERTID = c_void;
+pub type OCSP_RESPONSE = c_void;
 pub type RSA = c_void;
 pub type SSL = c_void;
+pub type SSL_CIPHER = c_void;
 pub type SSL_CTX = c_void;
 pub type SSL_METHOD = c_void;
+pub type SSL_SESSION = c_void;
 pub type X509 = c_void;
 pub type X509_CRL = c_void;
 pub type X509_EXTENSION = c_void;
 pub type X509_NAME = c_void;
 pub type X509_NAME_ENTRY = c_void;
 pub type X509_REQ = c_void;
+pub type X509_STORE = c_void;
+pub type X509_VERIFY_PARAM = c_void;
 pub type X509_STORE_CTX = c_void;
+pub type _STACK = c_void;
+
+#[repr(C)]
+pub struct GENERAL_NAME {
+    pub type_: c_int,
+    pub d: *mut c_void,
+}
 
 #[repr(C)]
 pub struct EVP_MD_CTX {
@@ -119,22 +136,55 @@ pub const BIO_C_SET_BUF_MEM_EOF_RETURN: c_int = 130;
 
 pub const CRYPTO_LOCK: c_int = 1;
 
+pub const DTLS1_COOKIE_LENGTH: c_uint = 255;
+
+pub const ERR_LIB_PEM: c_int = 9;
+
+pub const EVP_CTRL_GCM_GET_TAG: c_int = 0x10;
+pub const EVP_CTRL_GCM_SET_TAG: c_int = 0x11;
+
+pub const EVP_PKEY_RSA: c_int = 6;
+pub const EVP_PKEY_DSA: c_int = 116;
+pub const EVP_PKEY_DH: c_int = 28;
+pub const EVP_PKEY_EC: c_int = 408;
+pub const EVP_PKEY_ED25519: c_int = 1087;
+
 pub const MBSTRING_ASC:  c_int = MBSTRING_FLAG | 1;
 pub const MBSTRING_BMP:  c_int = MBSTRING_FLAG | 2;
 pub const MBSTRING_FLAG: c_int = 0x1000;
 pub const MBSTRING_UNIV: c_int = MBSTRING_FLAG | 4;
 pub const MBSTRING_UTF8: c_int = MBSTRING_FLAG;
 
+pub const NID_undef: c_int = 0;
 pub const NID_ext_key_usage: c_int = 126;
 pub const NID_key_usage:     c_int = 83;
+pub const NID_X9_62_prime256v1: c_int = 415;
+pub const NID_secp384r1: c_int = 715;
+pub const NID_secp521r1: c_int = 716;
+
+pub const OPENSSL_EC_NAMED_CURVE: c_int = 1;
+
+pub const OCSP_RESPONSE_STATUS_SUCCESSFUL: c_int = 0;
+
+pub const V_OCSP_CERTSTATUS_GOOD: c_int = 0;
+pub const V_OCSP_CERTSTATUS_REVOKED: c_int = 1;
+pub const V_OCSP_CERTSTATUS_UNKNOWN: c_int = 2;
+
+pub const PEM_R_NO_START_LINE: c_int = 108;
 
 pub const SSL_CTRL_OPTIONS: c_int = 32;
+pub const SSL_CTRL_MODE: c_int = 33;
+pub const SSL_CTRL_GET_SESSION_REUSED: c_int = 8;
+pub const SSL_CTRL_GET_TOTAL_RENEGOTIATIONS: c_int = 12;
 pub const SSL_CTRL_CLEAR_OPTIONS: c_int = 77;
+pub const SSL_CTRL_CLEAR_MODE: c_int = 78;
 
 pub const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
 pub const SSL_CTRL_EXTRA_CHAIN_CERT: c_int = 14;
 
 pub const SSL_CTRL_SET_READ_AHEAD: c_int = 41;
+pub const SSL_CTRL_SET_SESS_CACHE_MODE: c_int = 44;
+pub const SSL_CTRL_GET_SESS_CACHE_MODE: c_int = 45;
 pub const SSL_ERROR_NONE: c_int = 0;
 pub const SSL_ERROR_SSL: c_int = 1;
 pub const SSL_ERROR_SYSCALL: c_int = 5;
@@ -144,12 +194,54 @@ pub const SSL_ERROR_WANT_READ: c_int = 2;
 pub const SSL_ERROR_WANT_WRITE: c_int = 3;
 pub const SSL_ERROR_WANT_X509_LOOKUP: c_int = 4;
 pub const SSL_ERROR_ZERO_RETURN: c_int = 6;
+pub const SSL_MODE_ENABLE_PARTIAL_WRITE: c_long = 0x1;
+pub const SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER: c_long = 0x2;
+pub const SSL_MODE_AUTO_RETRY: c_long = 0x4;
+
+pub const SSL_SESS_CACHE_OFF: c_long = 0x0;
+pub const SSL_SESS_CACHE_CLIENT: c_long = 0x1;
+pub const SSL_SESS_CACHE_SERVER: c_long = 0x2;
+pub const SSL_SESS_CACHE_BOTH: c_long = SSL_SESS_CACHE_CLIENT | SSL_SESS_CACHE_SERVER;
+pub const SSL_SESS_CACHE_NO_AUTO_CLEAR: c_long = 0x80;
+pub const SSL_SESS_CACHE_NO_INTERNAL_LOOKUP: c_long = 0x100;
+pub const SSL_SESS_CACHE_NO_INTERNAL_STORE: c_long = 0x200;
+pub const SSL_SESS_CACHE_NO_INTERNAL: c_long = SSL_SESS_CACHE_NO_INTERNAL_LOOKUP |
+                                               SSL_SESS_CACHE_NO_INTERNAL_STORE;
+
+pub const SSL_ST_CONNECT: c_int = 0x1000;
+pub const SSL_ST_ACCEPT: c_int = 0x2000;
+pub const SSL_CB_LOOP: c_int = 0x01;
+pub const SSL_CB_EXIT: c_int = 0x02;
+pub const SSL_CB_READ: c_int = 0x04;
+pub const SSL_CB_WRITE: c_int = 0x08;
+pub const SSL_CB_ALERT: c_int = 0x4000;
+pub const SSL_CB_HANDSHAKE_START: c_int = 0x10;
+pub const SSL_CB_HANDSHAKE_DONE: c_int = 0x20;
+
+pub const SSL2_VERSION: c_int = 0x0002;
+pub const SSL3_VERSION: c_int = 0x0300;
+pub const TLS1_VERSION: c_int = 0x0301;
+pub const TLS1_1_VERSION: c_int = 0x0302;
+pub const TLS1_2_VERSION: c_int = 0x0303;
+pub const TLS1_3_VERSION: c_int = 0x0304;
+pub const DTLS1_VERSION: c_int = 0xFEFF;
+pub const DTLS1_2_VERSION: c_int = 0xFEFD;
+
+pub const SSL_SENT_SHUTDOWN: c_int = 1;
+pub const SSL_RECEIVED_SHUTDOWN: c_int = 2;
 pub const SSL_VERIFY_NONE: c_int = 0;
 pub const SSL_VERIFY_PEER: c_int = 1;
 pub const SSL_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 2;
 
 pub const TLSEXT_NAMETYPE_host_name: c_long = 0;
 
+pub const SSL_EARLY_DATA_NOT_SENT: c_int = 0;
+pub const SSL_EARLY_DATA_REJECTED: c_int = 1;
+pub const SSL_EARLY_DATA_ACCEPTED: c_int = 2;
+pub const SSL_READ_EARLY_DATA_ERROR: c_int = 0;
+pub const SSL_READ_EARLY_DATA_SUCCESS: c_int = 1;
+pub const SSL_READ_EARLY_DATA_FINISH: c_int = 2;
+
 pub const SSL_TLSEXT_ERR_OK: c_int = 0;
 pub const SSL_TLSEXT_ERR_ALERT_WARNING: c_int = 1;
 pub const SSL_TLSEXT_ERR_ALERT_FATAL: c_int = 2;
@@ -173,9 +265,23 @@ pub const OPENSSL_NPN_NO_OVERLAP: c_int = 2;
 pub const V_ASN1_GENERALIZEDTIME: c_int = 24;
 pub const V_ASN1_UTCTIME:         c_int = 23;
 
+pub const GEN_EMAIL: c_int = 1;
+pub const GEN_DNS: c_int = 2;
+pub const GEN_URI: c_int = 6;
+pub const GEN_IPADD: c_int = 7;
+
 pub const X509_FILETYPE_ASN1: c_int = 2;
 pub const X509_FILETYPE_DEFAULT: c_int = 3;
 pub const X509_FILETYPE_PEM: c_int = 1;
+
+pub const X509_PURPOSE_SSL_CLIENT: c_int = 1;
+pub const X509_PURPOSE_SSL_SERVER: c_int = 2;
+pub const X509_PURPOSE_NS_SSL_SERVER: c_int = 3;
+pub const X509_PURPOSE_SMIME_SIGN: c_int = 4;
+pub const X509_PURPOSE_SMIME_ENCRYPT: c_int = 5;
+pub const X509_PURPOSE_CRL_SIGN: c_int = 6;
+pub const X509_PURPOSE_ANY: c_int = 7;
+pub const X509_PURPOSE_OCSP_HELPER: c_int = 8;
 pub const X509_V_ERR_AKID_ISSUER_SERIAL_MISMATCH: c_int = 31;
 pub const X509_V_ERR_AKID_SKID_MISMATCH: c_int = 30;
 pub const X509_V_ERR_APPLICATION_VERIFICATION: c_int = 50;
@@ -192,12 +298,15 @@ pub const X509_V_ERR_CRL_PATH_VALIDATION_ERROR: c_int = 54;
 pub const X509_V_ERR_CRL_SIGNATURE_FAILURE: c_int = 8;
 pub const X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT: c_int = 18;
 pub const X509_V_ERR_DIFFERENT_CRL_SCOPE: c_int = 44;
+pub const X509_V_ERR_EMAIL_MISMATCH: c_int = 63;
 pub const X509_V_ERR_ERROR_IN_CERT_NOT_AFTER_FIELD: c_int = 14;
 pub const X509_V_ERR_ERROR_IN_CERT_NOT_BEFORE_FIELD: c_int = 13;
 pub const X509_V_ERR_ERROR_IN_CRL_LAST_UPDATE_FIELD: c_int = 15;
 pub const X509_V_ERR_ERROR_IN_CRL_NEXT_UPDATE_FIELD: c_int = 16;
 pub const X509_V_ERR_EXCLUDED_VIOLATION: c_int = 48;
+pub const X509_V_ERR_HOSTNAME_MISMATCH: c_int = 62;
 pub const X509_V_ERR_INVALID_CA: c_int = 24;
+pub const X509_V_ERR_IP_ADDRESS_MISMATCH: c_int = 64;
 pub const X509_V_ERR_INVALID_EXTENSION: c_int = 41;
 pub const X509_V_ERR_INVALID_NON_CA: c_int = 37;
 pub const X509_V_ERR_INVALID_POLICY_EXTENSION: c_int = 42;
@@ -231,6 +340,26 @@ pub const X509_V_ERR_UNSUPPORTED_EXTENSION_FEATURE: c_int = 45;
 pub const X509_V_ERR_UNSUPPORTED_NAME_SYNTAX: c_int = 53;
 pub const X509_V_OK: c_int = 0;
 
+pub const X509_V_FLAG_CB_ISSUER_CHECK: c_ulong = 0x1;
+pub const X509_V_FLAG_USE_CHECK_TIME: c_ulong = 0x2;
+pub const X509_V_FLAG_CRL_CHECK: c_ulong = 0x4;
+pub const X509_V_FLAG_CRL_CHECK_ALL: c_ulong = 0x8;
+pub const X509_V_FLAG_IGNORE_CRITICAL: c_ulong = 0x10;
+pub const X509_V_FLAG_X509_STRICT: c_ulong = 0x20;
+pub const X509_V_FLAG_ALLOW_PROXY_CERTS: c_ulong = 0x40;
+pub const X509_V_FLAG_POLICY_CHECK: c_ulong = 0x80;
+pub const X509_V_FLAG_EXPLICIT_POLICY: c_ulong = 0x100;
+pub const X509_V_FLAG_INHIBIT_ANY: c_ulong = 0x200;
+pub const X509_V_FLAG_INHIBIT_MAP: c_ulong = 0x400;
+pub const X509_V_FLAG_NOTIFY_POLICY: c_ulong = 0x800;
+pub const X509_V_FLAG_EXTENDED_CRL_SUPPORT: c_ulong = 0x1000;
+pub const X509_V_FLAG_USE_DELTAS: c_ulong = 0x2000;
+pub const X509_V_FLAG_CHECK_SS_SIGNATURE: c_ulong = 0x4000;
+pub const X509_V_FLAG_TRUSTED_FIRST: c_ulong = 0x8000;
+pub const X509_V_FLAG_PARTIAL_CHAIN: c_ulong = 0x80000;
+
+pub const X509_CHECK_FLAG_NO_PARTIAL_WILDCARDS: c_uint = 0x4;
+
 static mut MUTEXES: *mut Vec<Mutex<()>> = 0 as *mut Vec<Mutex<()>>;
 static mut GUARDS: *mut Vec<Option<MutexGuard<'static, ()>>> = 0 as *mut Vec<Option<MutexGuard<'static, ()>>>;
 
@@ -247,6 +376,8 @@ extern fn locking_function(mode: c_int, n: c_int, _file: *const c_char,
     }
 }
 
+static THREAD_SETUP: AtomicBool = ATOMIC_BOOL_INIT;
+
 pub fn init() {
     static mut INIT: Once = ONCE_INIT;
 
@@ -255,21 +386,33 @@ pub fn init() {
             SSL_library_init();
             SSL_load_error_strings();
 
+            // OpenSSL 1.1.0 and newer lock internally, and report no locks
             let num_locks = CRYPTO_num_locks();
-            let mut mutexes = Box::new(Vec::new());
-            for _ in 0..num_locks {
-                mutexes.push(Mutex::new(()));
+            if num_locks > 0 {
+                let mut mutexes = Box::new(Vec::new());
+                for _ in 0..num_locks {
+                    mutexes.push(Mutex::new(()));
+                }
+                MUTEXES = mem::transmute(mutexes);
+                let guards: Box<Vec<Option<MutexGuard<()>>>> =
+                    Box::new((0..num_locks).map(|_| None).collect());
+                GUARDS = mem::transmute(guards);
+
+                CRYPTO_set_locking_callback(locking_function);
             }
-            MUTEXES = mem::transmute(mutexes);
-            let guards: Box<Vec<Option<MutexGuard<()>>>> =
-                Box::new((0..num_locks).map(|_| None).collect());
-            GUARDS = mem::transmute(guards);
 
-            CRYPTO_set_locking_callback(locking_function);
+            THREAD_SETUP.store(true, Ordering::SeqCst);
         })
     }
 }
 
+/// Returns true once `init` has made OpenSSL safe to use from multiple
+/// threads, either by installing locking callbacks or because the library
+/// handles locking itself.
+pub fn thread_setup_complete() -> bool {
+    THREAD_SETUP.load(Ordering::SeqCst)
+}
+
 pub unsafe fn SSL_CTX_set_options(ssl: *mut SSL_CTX, op: u64) -> u64 {
     rust_openssl_ssl_ctx_options_c_to_rust(SSL_CTX_set_options_shim(ssl, rust_openssl_ssl_ctx_options_rust_to_c(op)))
 }
@@ -288,10 +431,16 @@ extern "C" {
     fn rust_openssl_ssl_ctx_options_c_to_rust(cval: c_long) -> u64;
 
     pub fn ASN1_INTEGER_set(dest: *mut ASN1_INTEGER, value: c_long) -> c_int;
+    pub fn ASN1_OBJECT_free(a: *mut ASN1_OBJECT);
+    pub fn ASN1_STRING_free(a: *mut ASN1_STRING);
+    pub fn ASN1_STRING_data(x: *mut ASN1_STRING) -> *mut c_uchar;
+    pub fn ASN1_STRING_length(x: *const ASN1_STRING) -> c_int;
+    pub fn ASN1_STRING_type(x: *const ASN1_STRING) -> c_int;
     pub fn ASN1_STRING_type_new(ty: c_int) -> *mut ASN1_STRING;
     pub fn ASN1_TIME_free(tm: *mut ASN1_TIME);
 
     pub fn BIO_ctrl(b: *mut BIO, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    pub fn BIO_ctrl_pending(b: *mut BIO) -> size_t;
     pub fn BIO_free_all(b: *mut BIO);
     pub fn BIO_new(type_: *const BIO_METHOD) -> *mut BIO;
     pub fn BIO_new_socket(sock: c_int, close_flag: c_int) -> *mut BIO;
@@ -368,16 +517,34 @@ extern "C" {
     pub fn BN_hex2bn(a: *const *mut BIGNUM, s: *const c_char) -> c_int;
     pub fn BN_bn2hex(a: *mut BIGNUM) -> *const c_char;
 
-    pub fn CRYPTO_num_locks() -> c_int;
-    pub fn CRYPTO_set_locking_callback(func: extern "C" fn(mode: c_int,
-                                                           n: c_int,
-                                                           file: *const c_char,
-                                                           line: c_int));
     pub fn CRYPTO_free(buf: *mut c_void);
     pub fn CRYPTO_memcmp(a: *const c_void, b: *const c_void,
                          len: size_t) -> c_int;
 
+    pub fn EC_KEY_new_by_curve_name(nid: c_int) -> *mut EC_KEY;
+    pub fn EC_KEY_generate_key(key: *mut EC_KEY) -> c_int;
+    pub fn EC_KEY_set_asn1_flag(key: *mut EC_KEY, flag: c_int);
+    pub fn EC_KEY_free(key: *mut EC_KEY);
+
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_load_builtin_engines();
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_by_id(id: *const c_char) -> *mut ENGINE;
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_init(e: *mut ENGINE) -> c_int;
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_finish(e: *mut ENGINE) -> c_int;
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_free(e: *mut ENGINE) -> c_int;
+    #[cfg(feature = "engine")]
+    pub fn ENGINE_load_private_key(e: *mut ENGINE, key_id: *const c_char,
+                                   ui_method: *mut c_void, callback_data: *mut c_void)
+                                   -> *mut EVP_PKEY;
+
     pub fn ERR_get_error() -> c_ulong;
+    pub fn ERR_peek_last_error() -> c_ulong;
+    pub fn ERR_clear_error();
+    pub fn ERR_error_string_n(e: c_ulong, buf: *mut c_char, len: size_t);
 
     pub fn ERR_lib_error_string(err: c_ulong) -> *const c_char;
     pub fn ERR_func_error_string(err: c_ulong) -> *const c_char;
@@ -398,18 +565,22 @@ extern "C" {
     #[cfg(feature = "aes_xts")]
     pub fn EVP_aes_128_xts() -> *const EVP_CIPHER;
     // fn EVP_aes_128_ctr() -> EVP_CIPHER;
-    // fn EVP_aes_128_gcm() -> EVP_CIPHER;
+    #[cfg(feature = "aes_gcm")]
+    pub fn EVP_aes_128_gcm() -> *const EVP_CIPHER;
     pub fn EVP_aes_256_cbc() -> *const EVP_CIPHER;
     pub fn EVP_aes_256_ecb() -> *const EVP_CIPHER;
     #[cfg(feature = "aes_xts")]
     pub fn EVP_aes_256_xts() -> *const EVP_CIPHER;
     // fn EVP_aes_256_ctr() -> EVP_CIPHER;
-    // fn EVP_aes_256_gcm() -> EVP_CIPHER;
+    #[cfg(feature = "aes_gcm")]
+    pub fn EVP_aes_256_gcm() -> *const EVP_CIPHER;
     pub fn EVP_rc4() -> *const EVP_CIPHER;
 
     pub fn EVP_CIPHER_CTX_new() -> *mut EVP_CIPHER_CTX;
     pub fn EVP_CIPHER_CTX_set_padding(ctx: *mut EVP_CIPHER_CTX, padding: c_int) -> c_int;
     pub fn EVP_CIPHER_CTX_free(ctx: *mut EVP_CIPHER_CTX);
+    pub fn EVP_CIPHER_CTX_ctrl(ctx: *mut EVP_CIPHER_CTX, type_: c_int, arg: c_int,
+                               ptr: *mut c_void) -> c_int;
 
     pub fn EVP_CipherInit(ctx: *mut EVP_CIPHER_CTX, evp: *const EVP_CIPHER,
                           key: *const u8, iv: *const u8, mode: c_int) -> c_int;
@@ -461,6 +632,10 @@ extern "C" {
                              user_data: *mut c_void) -> *mut X509;
     pub fn PEM_read_bio_X509_REQ(bio: *mut BIO, out: *mut *mut X509_REQ, callback: Option<PasswordCallback>,
                              user_data: *mut c_void) -> *mut X509_REQ;
+    pub fn OBJ_obj2nid(o: *const ASN1_OBJECT) -> c_int;
+    pub fn OBJ_nid2ln(n: c_int) -> *const c_char;
+    pub fn OBJ_nid2sn(n: c_int) -> *const c_char;
+
     pub fn PEM_read_bio_PrivateKey(bio: *mut BIO, out: *mut *mut EVP_PKEY, callback: Option<PasswordCallback>,
                              user_data: *mut c_void) -> *mut X509;
 
@@ -469,17 +644,25 @@ extern "C" {
                                     callback: Option<PasswordCallback>,
                                     user_data: *mut c_void) -> c_int;
     pub fn PEM_write_bio_X509(bio: *mut BIO, x509: *mut X509) -> c_int;
+    pub fn X509_print(bio: *mut BIO, x509: *mut X509) -> c_int;
     pub fn PEM_write_bio_X509_REQ(bio: *mut BIO, x509: *mut X509_REQ) -> c_int;
 
     pub fn PKCS5_PBKDF2_HMAC_SHA1(pass: *const u8, passlen: c_int,
                                   salt: *const u8, saltlen: c_int,
                                   iter: c_int, keylen: c_int,
                                   out: *mut u8) -> c_int;
+    pub fn PKCS5_PBKDF2_HMAC(pass: *const u8, passlen: c_int,
+                             salt: *const u8, saltlen: c_int,
+                             iter: c_int, digest: *const EVP_MD, keylen: c_int,
+                             out: *mut u8) -> c_int;
 
 
     pub fn RAND_bytes(buf: *mut u8, num: c_int) -> c_int;
+    pub fn RAND_seed(buf: *const c_void, num: c_int);
+    pub fn RAND_status() -> c_int;
 
     pub fn RSA_generate_key(modsz: c_int, e: c_ulong, cb: *const c_void, cbarg: *const c_void) -> *mut RSA;
+    pub fn RSA_free(rsa: *mut RSA);
     pub fn RSA_private_decrypt(flen: c_int, from: *const u8, to: *mut u8, k: *mut RSA,
                                pad: c_int) -> c_int;
     pub fn RSA_public_encrypt(flen: c_int, from: *const u8, to: *mut u8, k: *mut RSA,
@@ -490,6 +673,12 @@ extern "C" {
     pub fn RSA_verify(t: c_int, m: *const u8, mlen: c_uint, sig: *const u8, siglen: c_uint,
                       k: *mut RSA) -> c_int;
 
+    pub fn sk_num(st: *const _STACK) -> c_int;
+    pub fn sk_value(st: *const _STACK, i: c_int) -> *mut c_void;
+    pub fn sk_new_null() -> *mut _STACK;
+    pub fn sk_push(st: *mut _STACK, data: *mut c_void) -> c_int;
+    pub fn sk_free(st: *mut _STACK);
+
     pub fn SSL_library_init() -> c_int;
 
     pub fn SSL_load_error_strings();
@@ -502,6 +691,8 @@ extern "C" {
     pub fn TLSv1_1_method() -> *const SSL_METHOD;
     #[cfg(feature = "tlsv1_2")]
     pub fn TLSv1_2_method() -> *const SSL_METHOD;
+    #[cfg(feature = "tlsv1_3")]
+    pub fn TLS_method() -> *const SSL_METHOD;
     #[cfg(feature = "dtlsv1")]
     pub fn DTLSv1_method() -> *const SSL_METHOD;
     #[cfg(feature = "dtlsv1_2")]
@@ -511,30 +702,110 @@ extern "C" {
     pub fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
     pub fn SSL_pending(ssl: *const SSL) -> c_int;
     pub fn SSL_free(ssl: *mut SSL);
+    pub fn SSL_get_cipher_list(ssl: *const SSL, n: c_int) -> *const c_char;
+    pub fn SSL_get_ciphers(ssl: *const SSL) -> *mut _STACK;
     pub fn SSL_set_bio(ssl: *mut SSL, rbio: *mut BIO, wbio: *mut BIO);
     pub fn SSL_get_rbio(ssl: *mut SSL) -> *mut BIO;
     pub fn SSL_get_wbio(ssl: *mut SSL) -> *mut BIO;
     pub fn SSL_accept(ssl: *mut SSL) -> c_int;
     pub fn SSL_connect(ssl: *mut SSL) -> c_int;
+    pub fn SSL_do_handshake(ssl: *mut SSL) -> c_int;
+    pub fn SSL_renegotiate(ssl: *mut SSL) -> c_int;
+    pub fn SSL_set_connect_state(ssl: *mut SSL);
+    pub fn SSL_set_accept_state(ssl: *mut SSL);
     pub fn SSL_ctrl(ssl: *mut SSL, cmd: c_int, larg: c_long,
                     parg: *mut c_void) -> c_long;
     pub fn SSL_get_error(ssl: *mut SSL, ret: c_int) -> c_int;
     pub fn SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
+    pub fn SSL_peek(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
     pub fn SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int;
     pub fn SSL_get_ex_data_X509_STORE_CTX_idx() -> c_int;
+    pub fn SSL_get_ex_new_index(argl: c_long, argp: *const c_void,
+                                new_func: Option<CRYPTO_EX_new>,
+                                dup_func: Option<CRYPTO_EX_dup>,
+                                free_func: Option<CRYPTO_EX_free>)
+                                -> c_int;
+    pub fn SSL_set_ex_data(ssl: *mut SSL, idx: c_int, data: *mut c_void) -> c_int;
+    pub fn SSL_get_ex_data(ssl: *mut SSL, idx: c_int) -> *mut c_void;
+    pub fn SSL_set_verify(ssl: *mut SSL, mode: c_int,
+                          verify_callback: Option<extern fn(c_int, *mut X509_STORE_CTX) -> c_int>);
+    pub fn SSL_get_verify_mode(ssl: *const SSL) -> c_int;
+    pub fn SSL_get0_param(ssl: *mut SSL) -> *mut X509_VERIFY_PARAM;
     pub fn SSL_get_SSL_CTX(ssl: *mut SSL) -> *mut SSL_CTX;
     pub fn SSL_get_current_compression(ssl: *mut SSL) -> *const COMP_METHOD;
     pub fn SSL_get_peer_certificate(ssl: *mut SSL) -> *mut X509;
+    pub fn SSL_get_shutdown(ssl: *const SSL) -> c_int;
+    pub fn SSL_version(ssl: *const SSL) -> c_int;
+    pub fn SSL_get_current_cipher(ssl: *const SSL) -> *const SSL_CIPHER;
+    pub fn SSL_get1_session(ssl: *mut SSL) -> *mut SSL_SESSION;
+    pub fn SSL_get_session(ssl: *const SSL) -> *mut SSL_SESSION;
+    pub fn SSL_set_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int;
+
+    pub fn SSL_SESSION_free(session: *mut SSL_SESSION);
+    pub fn i2d_SSL_SESSION(session: *mut SSL_SESSION, pp: *mut *mut c_uchar) -> c_int;
+    pub fn d2i_SSL_SESSION(a: *mut *mut SSL_SESSION, pp: *mut *const c_uchar,
+                           length: c_long) -> *mut SSL_SESSION;
 
     pub fn SSL_COMP_get_name(comp: *const COMP_METHOD) -> *const c_char;
 
+    pub fn SSL_CIPHER_get_name(cipher: *const SSL_CIPHER) -> *const c_char;
+    pub fn SSL_CIPHER_get_bits(cipher: *const SSL_CIPHER, alg_bits: *mut c_int) -> c_int;
+    pub fn SSL_CIPHER_get_version(cipher: *const SSL_CIPHER) -> *const c_char;
+    pub fn SSL_CIPHER_description(cipher: *const SSL_CIPHER, buf: *mut c_char,
+                                  size: c_int) -> *mut c_char;
+
     pub fn SSL_CTX_new(method: *const SSL_METHOD) -> *mut SSL_CTX;
     pub fn SSL_CTX_free(ctx: *mut SSL_CTX);
     pub fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int,
                               verify_callback: Option<extern fn(c_int, *mut X509_STORE_CTX) -> c_int>);
     pub fn SSL_CTX_set_verify_depth(ctx: *mut SSL_CTX, depth: c_int);
+    pub fn SSL_CTX_get_verify_mode(ctx: *const SSL_CTX) -> c_int;
+    pub fn SSL_CTX_set_quiet_shutdown(ctx: *mut SSL_CTX, mode: c_int);
+    pub fn SSL_CTX_get_quiet_shutdown(ctx: *const SSL_CTX) -> c_int;
+    #[cfg(feature = "keylog")]
+    pub fn SSL_CTX_set_keylog_callback(ctx: *mut SSL_CTX,
+                                       cb: extern "C" fn(ssl: *const SSL, line: *const c_char));
+    #[cfg(feature = "dane")]
+    pub fn SSL_CTX_dane_enable(ctx: *mut SSL_CTX) -> c_int;
+    #[cfg(feature = "dane")]
+    pub fn SSL_dane_enable(ssl: *mut SSL, basedomain: *const c_char) -> c_int;
+    #[cfg(feature = "dane")]
+    pub fn SSL_dane_tlsa_add(ssl: *mut SSL, usage: u8, selector: u8, mtype: u8,
+                             data: *const c_uchar, dlen: size_t) -> c_int;
+    #[cfg(feature = "post_handshake_auth")]
+    pub fn SSL_set_post_handshake_auth(ssl: *mut SSL, val: c_int);
+    #[cfg(feature = "post_handshake_auth")]
+    pub fn SSL_verify_client_post_handshake(ssl: *mut SSL) -> c_int;
+    #[cfg(feature = "early_data")]
+    pub fn SSL_CTX_set_max_early_data(ctx: *mut SSL_CTX, max_early_data: u32) -> c_int;
+    #[cfg(feature = "early_data")]
+    pub fn SSL_SESSION_get_max_early_data(s: *const SSL_SESSION) -> u32;
+    #[cfg(feature = "early_data")]
+    pub fn SSL_write_early_data(ssl: *mut SSL, buf: *const c_void, num: size_t,
+                                written: *mut size_t) -> c_int;
+    #[cfg(feature = "early_data")]
+    pub fn SSL_read_early_data(ssl: *mut SSL, buf: *mut c_void, num: size_t,
+                               readbytes: *mut size_t) -> c_int;
+    #[cfg(feature = "early_data")]
+    pub fn SSL_get_early_data_status(ssl: *const SSL) -> c_int;
+    pub fn SSL_CTX_set_info_callback(ctx: *mut SSL_CTX,
+                                     cb: extern "C" fn(ssl: *const SSL, where_: c_int, ret: c_int));
+    pub fn SSL_CTX_set_purpose(ctx: *mut SSL_CTX, purpose: c_int) -> c_int;
+    pub fn SSL_CTX_get_cert_store(ctx: *const SSL_CTX) -> *mut X509_STORE;
+    pub fn SSL_CTX_set_client_CA_list(ctx: *mut SSL_CTX, list: *mut _STACK);
+    pub fn SSL_CTX_get_client_CA_list(ctx: *const SSL_CTX) -> *mut _STACK;
+    pub fn SSL_get_client_CA_list(ssl: *const SSL) -> *mut _STACK;
+    pub fn SSL_CTX_set_client_cert_cb(ctx: *mut SSL_CTX,
+                                      cb: extern "C" fn(ssl: *mut SSL, x509: *mut *mut X509,
+                                                        pkey: *mut *mut EVP_PKEY) -> c_int);
+    pub fn SSL_load_client_CA_file(file: *const c_char) -> *mut _STACK;
+    #[cfg(feature = "security_level")]
+    pub fn SSL_CTX_set_security_level(ctx: *mut SSL_CTX, level: c_int);
+    #[cfg(feature = "security_level")]
+    pub fn SSL_CTX_get_security_level(ctx: *const SSL_CTX) -> c_int;
     pub fn SSL_CTX_load_verify_locations(ctx: *mut SSL_CTX, CAfile: *const c_char,
                                          CApath: *const c_char) -> c_int;
+    pub fn SSL_CTX_set_default_verify_paths(ctx: *mut SSL_CTX) -> c_int;
     pub fn SSL_CTX_get_ex_new_index(argl: c_long, argp: *const c_void,
                                     new_func: Option<CRYPTO_EX_new>,
                                     dup_func: Option<CRYPTO_EX_dup>,
@@ -548,9 +819,13 @@ extern "C" {
     pub fn SSL_CTX_use_certificate(ctx: *mut SSL_CTX, cert: *mut X509) -> c_int;
 
     pub fn SSL_CTX_use_PrivateKey_file(ctx: *mut SSL_CTX, key_file: *const c_char, file_type: c_int) -> c_int;
+    pub fn SSL_CTX_set_default_passwd_cb(ctx: *mut SSL_CTX, cb: Option<PasswordCallback>);
+    pub fn SSL_CTX_set_default_passwd_cb_userdata(ctx: *mut SSL_CTX, u: *mut c_void);
     pub fn SSL_CTX_use_PrivateKey(ctx: *mut SSL_CTX, key: *mut EVP_PKEY) -> c_int;
     pub fn SSL_CTX_check_private_key(ctx: *mut SSL_CTX) -> c_int;
 
+    #[cfg(feature = "tlsv1_3")]
+    pub fn SSL_CTX_set_ciphersuites(ctx: *mut SSL_CTX, str: *const c_char) -> c_int;
     pub fn SSL_CTX_set_cipher_list(ssl: *mut SSL_CTX, s: *const c_char) -> c_int;
 
     pub fn SSL_CTX_ctrl(ssl: *mut SSL_CTX, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
@@ -595,12 +870,52 @@ extern "C" {
     #[cfg(feature = "alpn")]
     pub fn SSL_get0_alpn_selected(s: *const SSL, data: *mut *const c_uchar, len: *mut c_uint);
 
+    #[cfg(any(feature = "dtlsv1", feature = "dtlsv1_2"))]
+    pub fn SSL_CTX_set_cookie_generate_cb(ssl: *mut SSL_CTX,
+                                          cb: extern "C" fn(ssl: *mut SSL,
+                                                            cookie: *mut c_uchar,
+                                                            cookie_len: *mut c_uint) -> c_int);
+    #[cfg(any(feature = "dtlsv1", feature = "dtlsv1_2"))]
+    pub fn SSL_CTX_set_cookie_verify_cb(ssl: *mut SSL_CTX,
+                                        cb: extern "C" fn(ssl: *mut SSL,
+                                                          cookie: *const c_uchar,
+                                                          cookie_len: c_uint) -> c_int);
+
+    #[cfg(feature = "psk")]
+    pub fn SSL_CTX_set_psk_client_callback(ctx: *mut SSL_CTX,
+                                           cb: extern "C" fn(ssl: *mut SSL,
+                                                             hint: *const c_char,
+                                                             identity: *mut c_char,
+                                                             max_identity_len: c_uint,
+                                                             psk: *mut c_uchar,
+                                                             max_psk_len: c_uint) -> c_uint);
+    #[cfg(feature = "psk")]
+    pub fn SSL_CTX_set_psk_server_callback(ctx: *mut SSL_CTX,
+                                           cb: extern "C" fn(ssl: *mut SSL,
+                                                             identity: *const c_char,
+                                                             psk: *mut c_uchar,
+                                                             max_psk_len: c_uint) -> c_uint);
+    #[cfg(feature = "psk")]
+    pub fn SSL_CTX_use_psk_identity_hint(ctx: *mut SSL_CTX, hint: *const c_char) -> c_int;
+
     pub fn X509_add_ext(x: *mut X509, ext: *mut X509_EXTENSION, loc: c_int) -> c_int;
     pub fn X509_digest(x: *mut X509, digest: *const EVP_MD, buf: *mut c_char, len: *mut c_uint) -> c_int;
     pub fn X509_free(x: *mut X509);
+    pub fn X509_REQ_new() -> *mut X509_REQ;
     pub fn X509_REQ_free(x: *mut X509_REQ);
+    pub fn X509_REQ_set_version(x: *mut X509_REQ, version: c_long) -> c_int;
+    pub fn X509_REQ_set_subject_name(x: *mut X509_REQ, name: *mut X509_NAME) -> c_int;
+    pub fn X509_REQ_set_pubkey(x: *mut X509_REQ, pkey: *mut EVP_PKEY) -> c_int;
+    pub fn X509_REQ_get_pubkey(x: *mut X509_REQ) -> *mut EVP_PKEY;
+    pub fn X509_REQ_sign(x: *mut X509_REQ, pkey: *mut EVP_PKEY, md: *const EVP_MD) -> c_int;
+    pub fn X509_REQ_verify(x: *mut X509_REQ, pkey: *mut EVP_PKEY) -> c_int;
     pub fn X509_get_serialNumber(x: *mut X509) -> *mut ASN1_INTEGER;
+    pub fn X509_get_ext_d2i(x: *mut X509, nid: c_int, crit: *mut c_int, idx: *mut c_int) -> *mut c_void;
+    pub fn GENERAL_NAME_free(name: *mut GENERAL_NAME);
+    pub fn X509_get_issuer_name(x: *mut X509) -> *mut X509_NAME;
     pub fn X509_get_subject_name(x: *mut X509) -> *mut X509_NAME;
+    pub fn X509_check_ca(x: *mut X509) -> c_int;
+    pub fn X509_cmp_time(s: *const ASN1_TIME, t: *mut time_t) -> c_int;
     pub fn X509_gmtime_adj(time: *mut ASN1_TIME, adj: c_long) -> *mut ASN1_TIME;
     pub fn X509_new() -> *mut X509;
     pub fn X509_set_issuer_name(x: *mut X509, name: *mut X509_NAME) -> c_int;
@@ -608,28 +923,83 @@ extern "C" {
     pub fn X509_set_notBefore(x: *mut X509, tm: *const ASN1_TIME) -> c_int;
     pub fn X509_set_version(x: *mut X509, version: c_ulong) -> c_int;
     pub fn X509_set_pubkey(x: *mut X509, pkey: *mut EVP_PKEY) -> c_int;
+    pub fn X509_verify_cert(ctx: *mut X509_STORE_CTX) -> c_int;
     pub fn X509_sign(x: *mut X509, pkey: *mut EVP_PKEY, md: *const EVP_MD) -> c_int;
     pub fn X509_get_pubkey(x: *mut X509) -> *mut EVP_PKEY;
+    pub fn X509_get_default_cert_file() -> *const c_char;
+    pub fn X509_get_default_cert_dir() -> *const c_char;
     pub fn X509_to_X509_REQ(x: *mut X509, pkey: *mut EVP_PKEY, md: *const EVP_MD) -> *mut X509_REQ;
 
     pub fn X509_EXTENSION_free(ext: *mut X509_EXTENSION);
 
     pub fn X509_NAME_add_entry_by_txt(x: *mut X509, field: *const c_char, ty: c_int, bytes: *const c_char, len: c_int, loc: c_int, set: c_int) -> c_int;
+    pub fn X509_NAME_new() -> *mut X509_NAME;
+    pub fn X509_NAME_dup(n: *mut X509_NAME) -> *mut X509_NAME;
+    pub fn X509_NAME_free(n: *mut X509_NAME);
     pub fn X509_NAME_get_index_by_NID(n: *mut X509_NAME, nid: c_int, last_pos: c_int) ->c_int;
     pub fn X509_NAME_get_entry(n: *mut X509_NAME, loc: c_int) -> *mut X509_NAME_ENTRY;
     pub fn X509_NAME_ENTRY_get_data(ne: *mut X509_NAME_ENTRY) -> *mut ASN1_STRING;
+    pub fn X509_NAME_ENTRY_get_object(ne: *mut X509_NAME_ENTRY) -> *mut ASN1_OBJECT;
+    pub fn X509_NAME_entry_count(n: *mut X509_NAME) -> c_int;
 
     pub fn ASN1_STRING_to_UTF8(out: *mut *mut c_char, s: *mut ASN1_STRING) -> c_int;
 
+    pub fn X509_STORE_add_cert(store: *mut X509_STORE, x: *mut X509) -> c_int;
+    pub fn X509_STORE_set_default_paths(store: *mut X509_STORE) -> c_int;
+    pub fn X509_STORE_set_flags(store: *mut X509_STORE, flags: c_ulong) -> c_int;
+    pub fn X509_VERIFY_PARAM_set_time(param: *mut X509_VERIFY_PARAM, t: time_t);
+    pub fn X509_VERIFY_PARAM_set1_host(param: *mut X509_VERIFY_PARAM, name: *const c_char,
+                                       namelen: size_t) -> c_int;
+    pub fn X509_VERIFY_PARAM_set1_ip(param: *mut X509_VERIFY_PARAM, ip: *const c_uchar,
+                                     iplen: size_t) -> c_int;
+    pub fn X509_VERIFY_PARAM_set1_email(param: *mut X509_VERIFY_PARAM, email: *const c_char,
+                                        emaillen: size_t) -> c_int;
+    pub fn X509_check_host(x: *mut X509, chk: *const c_char, chklen: size_t, flags: c_uint,
+                           peername: *mut *mut c_char) -> c_int;
+    pub fn X509_check_ip_asc(x: *mut X509, ipasc: *const c_char, flags: c_uint) -> c_int;
+
+    pub fn X509_STORE_CTX_new() -> *mut X509_STORE_CTX;
+    pub fn X509_STORE_CTX_free(ctx: *mut X509_STORE_CTX);
+    pub fn X509_STORE_CTX_init(ctx: *mut X509_STORE_CTX, store: *mut X509_STORE, x509: *mut X509,
+                               chain: *mut _STACK) -> c_int;
+    pub fn X509_STORE_CTX_set_default(ctx: *mut X509_STORE_CTX, name: *const c_char) -> c_int;
     pub fn X509_STORE_CTX_get_current_cert(ct: *mut X509_STORE_CTX) -> *mut X509;
+    pub fn X509_STORE_CTX_get1_chain(ctx: *mut X509_STORE_CTX) -> *mut _STACK;
     pub fn X509_STORE_CTX_get_error(ctx: *mut X509_STORE_CTX) -> c_int;
+    pub fn X509_STORE_CTX_set_error(ctx: *mut X509_STORE_CTX, error: c_int);
+    pub fn X509_STORE_CTX_get_error_depth(ctx: *mut X509_STORE_CTX) -> c_int;
     pub fn X509_STORE_CTX_get_ex_data(ctx: *mut X509_STORE_CTX, idx: c_int) -> *mut c_void;
 
+    pub fn OCSP_RESPONSE_free(resp: *mut OCSP_RESPONSE);
+    pub fn OCSP_response_status(resp: *mut OCSP_RESPONSE) -> c_int;
+    pub fn OCSP_response_get1_basic(resp: *mut OCSP_RESPONSE) -> *mut OCSP_BASICRESP;
+    pub fn OCSP_BASICRESP_free(bs: *mut OCSP_BASICRESP);
+    pub fn OCSP_basic_verify(bs: *mut OCSP_BASICRESP, certs: *mut _STACK, st: *mut X509_STORE,
+                             flags: c_ulong) -> c_int;
+    pub fn OCSP_cert_to_id(dgst: *const EVP_MD, subject: *mut X509,
+                           issuer: *mut X509) -> *mut OCSP_CERTID;
+    pub fn OCSP_CERTID_free(id: *mut OCSP_CERTID);
+    pub fn OCSP_resp_find_status(bs: *mut OCSP_BASICRESP, id: *mut OCSP_CERTID, status: *mut c_int,
+                                 reason: *mut c_int, revtime: *mut *mut ASN1_TIME,
+                                 thisupd: *mut *mut ASN1_TIME,
+                                 nextupd: *mut *mut ASN1_TIME) -> c_int;
+    pub fn OCSP_check_validity(thisupd: *mut ASN1_TIME, nextupd: *mut ASN1_TIME, sec: c_long,
+                               maxsec: c_long) -> c_int;
+
     pub fn X509V3_EXT_conf_nid(conf: *mut c_void, ctx: *mut X509V3_CTX, ext_nid: c_int, value: *mut c_char) -> *mut X509_EXTENSION;
     pub fn X509V3_EXT_conf(conf: *mut c_void, ctx: *mut X509V3_CTX, name: *mut c_char, value: *mut c_char) -> *mut X509_EXTENSION;
     pub fn X509V3_set_ctx(ctx: *mut X509V3_CTX, issuer: *mut X509, subject: *mut X509, req: *mut X509_REQ, crl: *mut X509_CRL, flags: c_int);
 
+    pub fn d2i_X509(a: *mut *mut X509, pp: *mut *const c_uchar, length: c_long) -> *mut X509;
+    pub fn d2i_OCSP_RESPONSE(a: *mut *mut OCSP_RESPONSE, pp: *mut *const c_uchar,
+                             length: c_long) -> *mut OCSP_RESPONSE;
+    pub fn i2d_X509(x: *mut X509, buf: *mut *mut u8) -> c_int;
+    pub fn i2d_X509_NAME(n: *mut X509_NAME, buf: *mut *mut u8) -> c_int;
+
     pub fn i2d_RSA_PUBKEY(k: *mut RSA, buf: *const *mut u8) -> c_int;
+    pub fn d2i_AutoPrivateKey(a: *mut *mut EVP_PKEY, pp: *mut *const c_uchar, length: c_long) -> *mut EVP_PKEY;
+    pub fn d2i_PKCS8PrivateKey_bio(bp: *mut BIO, x: *mut *mut EVP_PKEY, cb: Option<PasswordCallback>,
+                                   u: *mut c_void) -> *mut EVP_PKEY;
     pub fn d2i_RSA_PUBKEY(k: *const *mut RSA, buf: *const *const u8, len: c_uint) -> *mut RSA;
     pub fn i2d_RSAPrivateKey(k: *mut RSA, buf: *const *mut u8) -> c_int;
     pub fn d2i_RSAPrivateKey(k: *const *mut RSA, buf: *const *const u8, len: c_uint) -> *mut RSA;
@@ -639,13 +1009,78 @@ extern "C" {
     pub fn BIO_eof(b: *mut BIO) -> c_int;
     #[link_name = "BIO_set_mem_eof_return_shim"]
     pub fn BIO_set_mem_eof_return(b: *mut BIO, v: c_int);
+    #[link_name = "SSL_CTX_set_tlsext_ticket_key_cb_shim"]
+    pub fn SSL_CTX_set_tlsext_ticket_key_cb(ctx: *mut SSL_CTX,
+                                            cb: extern "C" fn(ssl: *mut SSL,
+                                                              key_name: *mut c_uchar,
+                                                              iv: *mut c_uchar,
+                                                              cipher_ctx: *mut EVP_CIPHER_CTX,
+                                                              hmac_ctx: *mut HMAC_CTX,
+                                                              enc: c_int) -> c_int)
+                                            -> c_long;
+    #[link_name = "BIO_get_mem_data_shim"]
+    pub fn BIO_get_mem_data(b: *mut BIO, pp: *mut *mut c_char) -> c_long;
     pub fn SSL_CTX_set_options_shim(ctx: *mut SSL_CTX, options: c_long) -> c_long;
     pub fn SSL_CTX_get_options_shim(ctx: *mut SSL_CTX) -> c_long;
     pub fn SSL_CTX_clear_options_shim(ctx: *mut SSL_CTX, options: c_long) -> c_long;
     #[link_name = "SSL_CTX_add_extra_chain_cert_shim"]
     pub fn SSL_CTX_add_extra_chain_cert(ctx: *mut SSL_CTX, x509: *mut X509) -> c_long;
+    #[link_name = "SSL_CTX_set_mode_shim"]
+    pub fn SSL_CTX_set_mode(ctx: *mut SSL_CTX, mode: c_long) -> c_long;
+    #[link_name = "SSL_CTX_get_mode_shim"]
+    pub fn SSL_CTX_get_mode(ctx: *mut SSL_CTX) -> c_long;
     #[link_name = "SSL_CTX_set_read_ahead_shim"]
     pub fn SSL_CTX_set_read_ahead(ctx: *mut SSL_CTX, m: c_long) -> c_long;
+    #[link_name = "SSL_CTX_set_ecdh_auto_shim"]
+    pub fn SSL_CTX_set_ecdh_auto(ctx: *mut SSL_CTX, onoff: c_int) -> c_long;
+    #[link_name = "SSL_CTX_set_max_send_fragment_shim"]
+    pub fn SSL_CTX_set_max_send_fragment(ctx: *mut SSL_CTX, m: c_long) -> c_long;
+    #[link_name = "SSL_CTX_set_proto_version_range_shim"]
+    pub fn SSL_CTX_set_proto_version_range(ctx: *mut SSL_CTX, min: c_int, max: c_int) -> c_int;
+    // Returns -1 rather than 0 for failures which set errno, like SSL_read
+    #[link_name = "SSL_read_ex_shim"]
+    pub fn SSL_read_ex(ssl: *mut SSL, buf: *mut c_void, num: size_t,
+                       readbytes: *mut size_t) -> c_int;
+    #[link_name = "SSL_SESSION_get_master_key_shim"]
+    pub fn SSL_SESSION_get_master_key(s: *const SSL_SESSION, out: *mut c_uchar,
+                                      outlen: size_t) -> size_t;
+    #[link_name = "SSL_get_peer_signature_type_nid_shim"]
+    pub fn SSL_get_peer_signature_type_nid(s: *mut SSL) -> c_int;
+    #[link_name = "SSL_get_negotiated_group_shim"]
+    pub fn SSL_get_negotiated_group(s: *mut SSL) -> c_int;
+    #[link_name = "X509_STORE_get0_param_shim"]
+    pub fn X509_STORE_get0_param(store: *mut X509_STORE) -> *mut X509_VERIFY_PARAM;
+    #[link_name = "EVP_PKEY_base_id_shim"]
+    pub fn EVP_PKEY_base_id(pkey: *const EVP_PKEY) -> c_int;
+    #[link_name = "EVP_PKEY_size_shim"]
+    pub fn EVP_PKEY_size(pkey: *const EVP_PKEY) -> c_int;
+    #[link_name = "X509_up_ref_shim"]
+    pub fn X509_up_ref(x: *mut X509);
+    #[link_name = "X509_get_notBefore_shim"]
+    pub fn X509_get_notBefore(x: *mut X509) -> *mut ASN1_TIME;
+    #[link_name = "X509_get_notAfter_shim"]
+    pub fn X509_get_notAfter(x: *mut X509) -> *mut ASN1_TIME;
+    #[link_name = "X509_get_version_shim"]
+    pub fn X509_get_version(x: *mut X509) -> c_long;
+    #[link_name = "X509_get_signature_nid_shim"]
+    pub fn X509_get_signature_nid(x: *mut X509) -> c_int;
+    #[cfg(feature = "curves_list")]
+    #[link_name = "SSL_CTX_set1_curves_list_shim"]
+    pub fn SSL_CTX_set1_curves_list(ctx: *mut SSL_CTX, curves: *const c_char) -> c_long;
+    #[cfg(feature = "sigalgs_list")]
+    #[link_name = "SSL_CTX_set1_sigalgs_list_shim"]
+    pub fn SSL_CTX_set1_sigalgs_list(ctx: *mut SSL_CTX, sigalgs: *const c_char) -> c_long;
+    #[link_name = "CRYPTO_num_locks_shim"]
+    pub fn CRYPTO_num_locks() -> c_int;
+    #[link_name = "CRYPTO_set_locking_callback_shim"]
+    pub fn CRYPTO_set_locking_callback(func: extern "C" fn(mode: c_int,
+                                                           n: c_int,
+                                                           file: *const c_char,
+                                                           line: c_int));
+    #[link_name = "ERR_GET_LIB_shim"]
+    pub fn ERR_GET_LIB(e: c_ulong) -> c_int;
+    #[link_name = "ERR_GET_REASON_shim"]
+    pub fn ERR_GET_REASON(e: c_ulong) -> c_int;
     #[link_name = "SSL_set_tlsext_host_name_shim"]
     pub fn SSL_set_tlsext_host_name(s: *mut SSL, name: *const c_char) -> c_long;
 }
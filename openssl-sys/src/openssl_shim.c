@@ -1,5 +1,10 @@
+#include <openssl/ec.h>
+#include <openssl/err.h>
 #include <openssl/hmac.h>
 #include <openssl/ssl.h>
+#include <errno.h>
+#include <limits.h>
+#include <string.h>
 
 #if OPENSSL_VERSION_NUMBER < 0x1000000L
 // Copied from openssl crypto/hmac/hmac.c
@@ -51,6 +56,29 @@ int HMAC_Final_shim(HMAC_CTX *ctx, unsigned char *md, unsigned int *len) {
 
 // shims for OpenSSL macros
 
+int CRYPTO_num_locks_shim(void) {
+#if OPENSSL_VERSION_NUMBER >= 0x10100000L
+    // Locking is handled internally
+    return 0;
+#else
+    return CRYPTO_num_locks();
+#endif
+}
+
+void CRYPTO_set_locking_callback_shim(void (*func)(int, int, const char *, int)) {
+#if OPENSSL_VERSION_NUMBER < 0x10100000L
+    CRYPTO_set_locking_callback(func);
+#endif
+}
+
+int ERR_GET_LIB_shim(unsigned long e) {
+    return ERR_GET_LIB(e);
+}
+
+int ERR_GET_REASON_shim(unsigned long e) {
+    return ERR_GET_REASON(e);
+}
+
 int BIO_eof_shim(BIO *b) {
     return BIO_eof(b);
 }
@@ -59,6 +87,10 @@ void BIO_set_mem_eof_return_shim(BIO *b, int v) {
     BIO_set_mem_eof_return(b, v);
 }
 
+long BIO_get_mem_data_shim(BIO *b, char **pp) {
+    return BIO_get_mem_data(b, pp);
+}
+
 long SSL_CTX_set_options_shim(SSL_CTX *ctx, long options) {
     return SSL_CTX_set_options(ctx, options);
 }
@@ -75,10 +107,220 @@ long SSL_CTX_add_extra_chain_cert_shim(SSL_CTX *ctx, X509 *x509) {
     return SSL_CTX_add_extra_chain_cert(ctx, x509);
 }
 
+long SSL_CTX_set_mode_shim(SSL_CTX *ctx, long mode) {
+    return SSL_CTX_set_mode(ctx, mode);
+}
+
+long SSL_CTX_get_mode_shim(SSL_CTX *ctx) {
+    return SSL_CTX_get_mode(ctx);
+}
+
 long SSL_CTX_set_read_ahead_shim(SSL_CTX *ctx, long m) {
     return SSL_CTX_set_read_ahead(ctx, m);
 }
 
+long SSL_CTX_set_max_send_fragment_shim(SSL_CTX *ctx, long m) {
+    return SSL_CTX_set_max_send_fragment(ctx, m);
+}
+
+// A version of 0 leaves that end of the range open
+int SSL_CTX_set_proto_version_range_shim(SSL_CTX *ctx, int min, int max) {
+#if OPENSSL_VERSION_NUMBER >= 0x10100000L
+    return SSL_CTX_set_min_proto_version(ctx, min) && SSL_CTX_set_max_proto_version(ctx, max);
+#else
+    // Older versions can only disable individual protocols
+    static const struct {
+        int version;
+        long op;
+    } versions[] = {
+        { SSL3_VERSION, SSL_OP_NO_SSLv3 },
+        { TLS1_VERSION, SSL_OP_NO_TLSv1 },
+#ifdef SSL_OP_NO_TLSv1_1
+        { TLS1_1_VERSION, SSL_OP_NO_TLSv1_1 },
+        { TLS1_2_VERSION, SSL_OP_NO_TLSv1_2 },
+#endif
+    };
+    size_t count = sizeof(versions) / sizeof(versions[0]);
+    int min_found = min == 0, max_found = max == 0;
+    long all = 0, ops = 0;
+    size_t i;
+
+    for (i = 0; i < count; i++) {
+        min_found |= versions[i].version == min;
+        max_found |= versions[i].version == max;
+        all |= versions[i].op;
+        if ((min != 0 && versions[i].version < min) || (max != 0 && versions[i].version > max)) {
+            ops |= versions[i].op;
+        }
+    }
+    if (!min_found || !max_found) {
+        return 0;
+    }
+
+    SSL_CTX_clear_options(ctx, all);
+    SSL_CTX_set_options(ctx, ops);
+    return 1;
+#endif
+}
+
+#if OPENSSL_VERSION_NUMBER >= 0x10002000L
+long SSL_CTX_set1_curves_list_shim(SSL_CTX *ctx, char *curves) {
+    return SSL_CTX_set1_curves_list(ctx, curves);
+}
+
+long SSL_CTX_set1_sigalgs_list_shim(SSL_CTX *ctx, char *sigalgs) {
+    return SSL_CTX_set1_sigalgs_list(ctx, sigalgs);
+}
+#endif
+
+#if OPENSSL_VERSION_NUMBER < 0x10002000L
+// Picks the curve of the context's certificate if it has an EC key, and P-256
+// otherwise, which every client supporting ECDHE implements
+static int ecdh_auto_curve(SSL_CTX *ctx) {
+    int nid = NID_X9_62_prime256v1;
+    SSL *ssl = SSL_new(ctx);
+    if (ssl != NULL) {
+        X509 *cert = SSL_get_certificate(ssl);
+        EVP_PKEY *pkey = cert != NULL ? X509_get_pubkey(cert) : NULL;
+        EC_KEY *ec = pkey != NULL ? EVP_PKEY_get1_EC_KEY(pkey) : NULL;
+        if (ec != NULL) {
+            int cert_nid = EC_GROUP_get_curve_name(EC_KEY_get0_group(ec));
+            if (cert_nid != NID_undef) {
+                nid = cert_nid;
+            }
+            EC_KEY_free(ec);
+        }
+        EVP_PKEY_free(pkey);
+        SSL_free(ssl);
+    }
+    ERR_clear_error();
+    return nid;
+}
+#endif
+
+long SSL_CTX_set_ecdh_auto_shim(SSL_CTX *ctx, int onoff) {
+#if OPENSSL_VERSION_NUMBER >= 0x10100000L
+    // Automatic curve selection is always enabled
+    return 1;
+#elif OPENSSL_VERSION_NUMBER >= 0x10002000L
+    return SSL_CTX_set_ecdh_auto(ctx, onoff);
+#else
+    EC_KEY *key;
+    long ret;
+
+    if (!onoff) {
+        return 1;
+    }
+    key = EC_KEY_new_by_curve_name(ecdh_auto_curve(ctx));
+    if (key == NULL) {
+        return 0;
+    }
+    ret = SSL_CTX_set_tmp_ecdh(ctx, key);
+    EC_KEY_free(key);
+    return ret;
+#endif
+}
+
+long SSL_CTX_set_tlsext_ticket_key_cb_shim(SSL_CTX *ctx,
+                                           int (*cb)(SSL *, unsigned char *, unsigned char *,
+                                                     EVP_CIPHER_CTX *, HMAC_CTX *, int)) {
+    return SSL_CTX_set_tlsext_ticket_key_cb(ctx, cb);
+}
+
 long SSL_set_tlsext_host_name_shim(SSL *s, char *name) {
     return SSL_set_tlsext_host_name(s, name);
 }
+
+ASN1_TIME *X509_get_notBefore_shim(X509 *x) {
+    return X509_get_notBefore(x);
+}
+
+ASN1_TIME *X509_get_notAfter_shim(X509 *x) {
+    return X509_get_notAfter(x);
+}
+
+long X509_get_version_shim(X509 *x) {
+    return X509_get_version(x);
+}
+
+int X509_get_signature_nid_shim(X509 *x) {
+#if OPENSSL_VERSION_NUMBER < 0x10002000L
+    return OBJ_obj2nid(x->sig_alg->algorithm);
+#else
+    return X509_get_signature_nid(x);
+#endif
+}
+
+int SSL_read_ex_shim(SSL *s, void *buf, size_t num, size_t *readbytes) {
+#if OPENSSL_VERSION_NUMBER >= 0x10101000L
+    // SSL_read_ex returns 0 for every failure. Callers tell an unexpected
+    // EOF from a socket error by SSL_read's 0 and -1, so a failure which set
+    // errno is turned into -1.
+    int ret;
+    errno = 0;
+    ret = SSL_read_ex(s, buf, num, readbytes);
+    if (ret == 0 && errno != 0)
+        return -1;
+    return ret;
+#else
+    int ret = SSL_read(s, buf, num > INT_MAX ? INT_MAX : (int)num);
+    *readbytes = ret > 0 ? ret : 0;
+    return ret;
+#endif
+}
+
+size_t SSL_SESSION_get_master_key_shim(const SSL_SESSION *s, unsigned char *out,
+                                       size_t outlen) {
+#if OPENSSL_VERSION_NUMBER < 0x10100000L
+    size_t len = s->master_key_length;
+    if (outlen == 0)
+        return len;
+    if (outlen > len)
+        outlen = len;
+    memcpy(out, s->master_key, outlen);
+    return outlen;
+#else
+    return SSL_SESSION_get_master_key(s, out, outlen);
+#endif
+}
+
+int SSL_get_peer_signature_type_nid_shim(SSL *s) {
+#if OPENSSL_VERSION_NUMBER >= 0x10101000L
+    int nid;
+    if (SSL_get_peer_signature_type_nid(s, &nid))
+        return nid;
+#endif
+    return NID_undef;
+}
+
+int SSL_get_negotiated_group_shim(SSL *s) {
+#if OPENSSL_VERSION_NUMBER >= 0x30000000L
+    return SSL_get_negotiated_group(s);
+#else
+    return NID_undef;
+#endif
+}
+
+X509_VERIFY_PARAM *X509_STORE_get0_param_shim(X509_STORE *store) {
+#if OPENSSL_VERSION_NUMBER < 0x10100000L
+    return store->param;
+#else
+    return X509_STORE_get0_param(store);
+#endif
+}
+
+int EVP_PKEY_base_id_shim(const EVP_PKEY *pkey) {
+    return EVP_PKEY_base_id(pkey);
+}
+
+int EVP_PKEY_size_shim(const EVP_PKEY *pkey) {
+    return EVP_PKEY_size(pkey);
+}
+
+void X509_up_ref_shim(X509 *x) {
+#if OPENSSL_VERSION_NUMBER < 0x10100000L
+    CRYPTO_add(&x->references, 1, CRYPTO_LOCK_X509);
+#else
+    X509_up_ref(x);
+#endif
+}
@@ -41,6 +41,9 @@ SSL_OP_MSIE_SSLV2_RSA_PADDING                  0x200000000
 SSL_OP_PKCS1_CHECK_1                           0x400000000
 SSL_OP_PKCS1_CHECK_2                           0x800000000
 
+// The following values are only defined by newer OpenSSL
+SSL_OP_NO_RENEGOTIATION                        0x1000000000
+
 // The following values were redefined to 0 for security reasons
 SSL_OP_EPHEMERAL_RSA                           0x0
 }
@@ -1,5 +1,8 @@
 use libc::{c_long};
+use std::marker::PhantomData;
 use std::ptr;
+use std::slice;
+use std::str;
 
 use ffi;
 use ssl::error::{SslError};
@@ -38,6 +41,150 @@ impl Asn1Time {
     pub unsafe fn get_handle(&self) -> *mut ffi::ASN1_TIME {
         return self.handle
     }
+
+    /// Returns the time broken down into its components
+    pub fn to_tm(&self) -> Option<Tm> {
+        unsafe { time_to_tm(self.handle) }
+    }
+}
+
+/// A time borrowed from another object, such as a certificate
+pub struct Asn1TimeRef<'a> {
+    handle: *mut ffi::ASN1_TIME,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> Asn1TimeRef<'a> {
+    /// Wraps existing ASN1_TIME without taking ownership
+    pub fn new(handle: *mut ffi::ASN1_TIME) -> Asn1TimeRef<'a> {
+        Asn1TimeRef {
+            handle: handle,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns raw handle
+    pub unsafe fn get_handle(&self) -> *mut ffi::ASN1_TIME {
+        self.handle
+    }
+
+    /// Returns the time broken down into its components
+    pub fn to_tm(&self) -> Option<Tm> {
+        unsafe { time_to_tm(self.handle) }
+    }
+}
+
+/// A UTC time broken down into its components
+///
+/// Fields are ordered from most to least significant, so comparing two
+/// values compares the points in time they represent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tm {
+    /// The full year, e.g. 2015
+    pub year: i32,
+    /// The month, from 1 to 12
+    pub month: u32,
+    /// The day of the month, from 1 to 31
+    pub day: u32,
+    /// The hour, from 0 to 23
+    pub hour: u32,
+    /// The minute, from 0 to 59
+    pub minute: u32,
+    /// The second, from 0 to 60 to allow for leap seconds
+    pub second: u32,
+}
+
+unsafe fn time_to_tm(handle: *mut ffi::ASN1_TIME) -> Option<Tm> {
+    let generalized = match ffi::ASN1_STRING_type(handle) {
+        ffi::V_ASN1_UTCTIME => false,
+        ffi::V_ASN1_GENERALIZEDTIME => true,
+        _ => return None,
+    };
+    let data = slice::from_raw_parts(ffi::ASN1_STRING_data(handle),
+                                     ffi::ASN1_STRING_length(handle) as usize);
+    parse_time(data, generalized)
+}
+
+/// Parses the contents of a UTCTime (`YYMMDDHHMM[SS]Z`) or GeneralizedTime
+/// (`YYYYMMDDHHMM[SS[.fff]]Z`).
+///
+/// Only times in UTC are accepted, as required for certificates by RFC 5280.
+fn parse_time(data: &[u8], generalized: bool) -> Option<Tm> {
+    fn num(data: &[u8]) -> Option<u32> {
+        if !data.iter().all(|b| b'0' <= *b && *b <= b'9') {
+            return None;
+        }
+        str::from_utf8(data).ok().and_then(|s| s.parse().ok())
+    }
+
+    let (year, rest) = if generalized {
+        if data.len() < 4 {
+            return None;
+        }
+        match num(&data[..4]) {
+            Some(year) => (year as i32, &data[4..]),
+            None => return None,
+        }
+    } else {
+        if data.len() < 2 {
+            return None;
+        }
+        // RFC 5280 maps two digit years 50-99 to 19xx and 00-49 to 20xx
+        match num(&data[..2]) {
+            Some(year) if year >= 50 => (1900 + year as i32, &data[2..]),
+            Some(year) => (2000 + year as i32, &data[2..]),
+            None => return None,
+        }
+    };
+
+    if rest.len() < 9 || rest[rest.len() - 1] != b'Z' {
+        return None;
+    }
+    let rest = &rest[..rest.len() - 1];
+
+    let mut fields = [0; 5];
+    for (i, field) in fields.iter_mut().enumerate() {
+        let start = i * 2;
+        if start >= rest.len() {
+            // Seconds may be omitted
+            if i == 4 {
+                break;
+            }
+            return None;
+        }
+        if start + 2 > rest.len() {
+            return None;
+        }
+        *field = match num(&rest[start..start + 2]) {
+            Some(n) => n,
+            None => return None,
+        };
+    }
+
+    let tail = if rest.len() > 10 { &rest[10..] } else { &[][..] };
+    if !tail.is_empty() {
+        // Fractional seconds are only allowed in GeneralizedTime and are
+        // dropped
+        if !generalized || tail[0] != b'.' || num(&tail[1..]).is_none() {
+            return None;
+        }
+    }
+
+    let tm = Tm {
+        year: year,
+        month: fields[0],
+        day: fields[1],
+        hour: fields[2],
+        minute: fields[3],
+        second: fields[4],
+    };
+
+    if tm.month < 1 || tm.month > 12 || tm.day < 1 || tm.day > 31 || tm.hour > 23 ||
+       tm.minute > 59 || tm.second > 60 {
+        return None;
+    }
+
+    Some(tm)
 }
 
 impl Drop for Asn1Time {
@@ -47,3 +194,32 @@ impl Drop for Asn1Time {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_time, Tm};
+
+    fn tm(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Tm {
+        Tm { year: year, month: month, day: day, hour: hour, minute: minute, second: second }
+    }
+
+    #[test]
+    fn test_parse_utc_time() {
+        assert_eq!(parse_time(b"150511172418Z", false), Some(tm(2015, 5, 11, 17, 24, 18)));
+        assert_eq!(parse_time(b"991231235959Z", false), Some(tm(1999, 12, 31, 23, 59, 59)));
+        assert_eq!(parse_time(b"4901010000Z", false), Some(tm(2049, 1, 1, 0, 0, 0)));
+        assert_eq!(parse_time(b"150511172418", false), None);
+        assert_eq!(parse_time(b"150511172418+0100", false), None);
+        assert_eq!(parse_time(b"151311172418Z", false), None);
+        assert_eq!(parse_time(b"20150511172418Z", false), None);
+    }
+
+    #[test]
+    fn test_parse_generalized_time() {
+        assert_eq!(parse_time(b"20500101000000Z", true), Some(tm(2050, 1, 1, 0, 0, 0)));
+        assert_eq!(parse_time(b"19700101000000.123Z", true), Some(tm(1970, 1, 1, 0, 0, 0)));
+        assert_eq!(parse_time(b"205001010000Z", true), Some(tm(2050, 1, 1, 0, 0, 0)));
+        assert_eq!(parse_time(b"20500101000000.Z", true), None);
+        assert_eq!(parse_time(b"150511172418Z", true), None);
+    }
+}
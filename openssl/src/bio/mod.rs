@@ -3,10 +3,16 @@ use std::io;
 use std::io::prelude::*;
 use std::ptr;
 use std::cmp;
+use std::slice;
 
 use ffi;
 use ssl::error::{SslError};
 
+/// An in-memory BIO
+///
+/// Data written to the BIO is buffered until it is read back out. This is
+/// the buffer used throughout the crate to move PEM and DER encoded data in
+/// and out of OpenSSL.
 pub struct MemBio {
     bio: *mut ffi::BIO,
     owned: bool
@@ -57,6 +63,19 @@ impl MemBio {
         self.bio
     }
 
+    /// Returns a copy of the data currently buffered in the BIO without
+    /// consuming it.
+    pub fn get_contents(&self) -> Vec<u8> {
+        unsafe {
+            let mut ptr = ptr::null_mut();
+            let len = ffi::BIO_get_mem_data(self.bio, &mut ptr);
+            if ptr.is_null() || len <= 0 {
+                return vec![];
+            }
+            slice::from_raw_parts(ptr as *const u8, len as usize).to_vec()
+        }
+    }
+
     /// Sets the BIO's EOF state.
     pub fn set_eof(&self, eof: bool) {
         let v = if eof { 0 } else { -1 };
@@ -102,3 +121,24 @@ impl Write for MemBio {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use super::MemBio;
+
+    #[test]
+    fn test_get_contents() {
+        let mut bio = MemBio::new().unwrap();
+        assert_eq!(bio.get_contents(), b"");
+
+        bio.write_all(b"hello world").unwrap();
+        assert_eq!(bio.get_contents(), b"hello world");
+
+        let mut buf = [0; 6];
+        bio.read(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello ");
+        assert_eq!(bio.get_contents(), b"world");
+    }
+}
@@ -0,0 +1,72 @@
+//! Access to OpenSSL engines, such as those backed by hardware security
+//! modules.
+
+use std::ffi::CString;
+use std::ptr;
+
+use crypto::pkey::{PKey, Parts};
+use ffi;
+use ssl::error::SslError;
+
+/// An initialized OpenSSL engine.
+pub struct Engine {
+    e: *mut ffi::ENGINE,
+}
+
+impl Engine {
+    /// Looks up the engine named `id` and initializes it.
+    ///
+    /// The built-in engines, including `dynamic` which loads engines from
+    /// shared libraries, are registered first.
+    pub fn by_id(id: &str) -> Result<Engine, SslError> {
+        let id = CString::new(id).unwrap();
+        unsafe {
+            ffi::init();
+            ffi::ENGINE_load_builtin_engines();
+
+            let e = try_ssl_null!(ffi::ENGINE_by_id(id.as_ptr()));
+            if ffi::ENGINE_init(e) <= 0 {
+                ffi::ENGINE_free(e);
+                return Err(SslError::get());
+            }
+            Ok(Engine { e: e })
+        }
+    }
+
+    /// Returns the underlying `ENGINE`, which is still owned and released by
+    /// this `Engine`.
+    pub unsafe fn get_handle(&self) -> *mut ffi::ENGINE {
+        self.e
+    }
+
+    /// Loads the private key identified by `key_id`, whose format is specific
+    /// to the engine.
+    pub fn load_private_key(&self, key_id: &str) -> Result<PKey, SslError> {
+        let key_id = CString::new(key_id).unwrap();
+        unsafe {
+            let key = try_ssl_null!(ffi::ENGINE_load_private_key(self.e, key_id.as_ptr(),
+                                                                 ptr::null_mut(),
+                                                                 ptr::null_mut()));
+            Ok(PKey::from_handle(key, Parts::Both))
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::ENGINE_finish(self.e);
+            ffi::ENGINE_free(self.e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+
+    #[test]
+    fn test_unknown_engine() {
+        assert!(Engine::by_id("no-such-engine").is_err());
+    }
+}
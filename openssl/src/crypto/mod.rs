@@ -15,6 +15,8 @@
  * limitations under the License.
  */
 
+#[cfg(feature = "engine")]
+pub mod engine;
 pub mod hash;
 pub mod hmac;
 pub mod pkcs5;
@@ -1,4 +1,5 @@
 use libc::c_int;
+use crypto::hash;
 use ffi;
 
 /// Derives a key from a password and salt using the PBKDF2-HMAC-SHA1 algorithm.
@@ -25,6 +26,32 @@ pub fn pbkdf2_hmac_sha1(pass: &str, salt: &[u8], iter: usize, keylen: usize) ->
     }
 }
 
+/// Derives a key from a password and salt using the PBKDF2-HMAC algorithm
+/// with the given hash function.
+pub fn pbkdf2_hmac(pass: &[u8], salt: &[u8], iter: usize, hash: hash::Type,
+                   keylen: usize) -> Vec<u8> {
+    unsafe {
+        assert!(iter >= 1);
+        assert!(keylen >= 1);
+
+        let mut out = Vec::with_capacity(keylen);
+
+        ffi::init();
+
+        let r = ffi::PKCS5_PBKDF2_HMAC(
+                pass.as_ptr(), pass.len() as c_int,
+                salt.as_ptr(), salt.len() as c_int,
+                iter as c_int, hash.evp_md(), keylen as c_int,
+                out.as_mut_ptr());
+
+        if r != 1 { panic!(); }
+
+        out.set_len(keylen);
+
+        out
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // Test vectors from
@@ -116,4 +143,67 @@ mod tests {
             )
         );
     }
+
+    // Test vectors from
+    // https://stackoverflow.com/questions/5130513/pbkdf2-hmac-sha2-test-vectors
+    #[test]
+    fn test_pbkdf2_hmac() {
+        use crypto::hash;
+
+        assert_eq!(
+            super::pbkdf2_hmac(
+                b"password",
+                b"salt",
+                1,
+                hash::Type::SHA256,
+                32
+            ),
+            vec!(
+                0x12_u8, 0x0f_u8, 0xb6_u8, 0xcf_u8, 0xfc_u8, 0xf8_u8, 0xb3_u8,
+                0x2c_u8, 0x43_u8, 0xe7_u8, 0x22_u8, 0x52_u8, 0x56_u8, 0xc4_u8,
+                0xf8_u8, 0x37_u8, 0xa8_u8, 0x65_u8, 0x48_u8, 0xc9_u8, 0x2c_u8,
+                0xcc_u8, 0x35_u8, 0x48_u8, 0x08_u8, 0x05_u8, 0x98_u8, 0x7c_u8,
+                0xb7_u8, 0x0b_u8, 0xe1_u8, 0x7b_u8
+            )
+        );
+
+        assert_eq!(
+            super::pbkdf2_hmac(
+                b"password",
+                b"salt",
+                4096,
+                hash::Type::SHA256,
+                32
+            ),
+            vec!(
+                0xc5_u8, 0xe4_u8, 0x78_u8, 0xd5_u8, 0x92_u8, 0x88_u8, 0xc8_u8,
+                0x41_u8, 0xaa_u8, 0x53_u8, 0x0d_u8, 0xb6_u8, 0x84_u8, 0x5c_u8,
+                0x4c_u8, 0x8d_u8, 0x96_u8, 0x28_u8, 0x93_u8, 0xa0_u8, 0x01_u8,
+                0xce_u8, 0x4e_u8, 0x11_u8, 0xa4_u8, 0x96_u8, 0x38_u8, 0x73_u8,
+                0xaa_u8, 0x98_u8, 0x13_u8, 0x4a_u8
+            )
+        );
+
+        assert_eq!(
+            super::pbkdf2_hmac(
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                hash::Type::SHA512,
+                64
+            ),
+            vec!(
+                0x8c_u8, 0x05_u8, 0x11_u8, 0xf4_u8, 0xc6_u8, 0xe5_u8, 0x97_u8,
+                0xc6_u8, 0xac_u8, 0x63_u8, 0x15_u8, 0xd8_u8, 0xf0_u8, 0x36_u8,
+                0x2e_u8, 0x22_u8, 0x5f_u8, 0x3c_u8, 0x50_u8, 0x14_u8, 0x95_u8,
+                0xba_u8, 0x23_u8, 0xb8_u8, 0x68_u8, 0xc0_u8, 0x05_u8, 0x17_u8,
+                0x4d_u8, 0xc4_u8, 0xee_u8, 0x71_u8, 0x11_u8, 0x5b_u8, 0x59_u8,
+                0xf9_u8, 0xe6_u8, 0x0c_u8, 0xd9_u8, 0x53_u8, 0x2f_u8, 0xa3_u8,
+                0x3e_u8, 0x0f_u8, 0x75_u8, 0xae_u8, 0xfe_u8, 0x30_u8, 0x22_u8,
+                0x5c_u8, 0x58_u8, 0x3a_u8, 0x18_u8, 0x6c_u8, 0xd8_u8, 0x2b_u8,
+                0xd4_u8, 0xda_u8, 0xea_u8, 0x97_u8, 0x24_u8, 0xa3_u8, 0xd3_u8,
+                0xb8_u8
+            )
+        );
+    }
 }
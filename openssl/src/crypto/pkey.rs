@@ -1,4 +1,5 @@
-use libc::{c_int, c_uint, c_ulong};
+use libc::{c_int, c_long, c_uint, c_ulong, c_void};
+use std::ffi::CString;
 use std::io;
 use std::io::prelude::*;
 use std::iter::repeat;
@@ -26,6 +27,18 @@ pub enum Role {
     Verify
 }
 
+/// The algorithm of a key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    Rsa,
+    Dsa,
+    Dh,
+    Ec,
+    Ed25519,
+    /// Any other algorithm, identified by its NID.
+    Other(i32),
+}
+
 /// Type of encryption padding to use.
 #[derive(Copy, Clone)]
 pub enum EncryptionPadding {
@@ -81,6 +94,10 @@ impl PKey {
     }
 
     /// Reads private key from PEM, takes ownership of handle
+    ///
+    /// Keys of any algorithm supported by OpenSSL are accepted, in either
+    /// PKCS#8 or the traditional format for the algorithm, such as
+    /// `BEGIN EC PRIVATE KEY`. `key_type` tells which algorithm was found.
     pub fn private_key_from_pem<R>(reader: &mut R) -> Result<PKey, SslError> where R: Read {
         let mut mem_bio = try!(MemBio::new());
         try!(io::copy(reader, &mut mem_bio).map_err(StreamError));
@@ -96,6 +113,43 @@ impl PKey {
         }
     }
 
+    /// Reads a DER encoded private key, takes ownership of handle
+    ///
+    /// Both the traditional format for the key's algorithm and unencrypted
+    /// PKCS#8 are accepted.
+    pub fn private_key_from_der(der: &[u8]) -> Result<PKey, SslError> {
+        ffi::init();
+
+        unsafe {
+            let mut ptr = der.as_ptr();
+            let evp = try_ssl_null!(ffi::d2i_AutoPrivateKey(ptr::null_mut(), &mut ptr,
+                                                            der.len() as c_long));
+            Ok(PKey {
+                evp:   evp,
+                parts: Parts::Both,
+            })
+        }
+    }
+
+    /// Reads a DER encoded, encrypted PKCS#8 private key, takes ownership of
+    /// handle
+    pub fn private_key_from_pkcs8(der: &[u8], passphrase: &str) -> Result<PKey, SslError> {
+        let mut mem_bio = try!(MemBio::new());
+        try!(mem_bio.write_all(der).map_err(StreamError));
+        let passphrase = CString::new(passphrase).unwrap();
+
+        unsafe {
+            // Without a callback the passphrase is passed through as a string
+            let evp = try_ssl_null!(ffi::d2i_PKCS8PrivateKey_bio(mem_bio.get_handle(),
+                                                                 ptr::null_mut(), None,
+                                                                 passphrase.as_ptr() as *mut _));
+            Ok(PKey {
+                evp:   evp,
+                parts: Parts::Both,
+            })
+        }
+    }
+
     fn _tostr(&self, f: unsafe extern "C" fn(*mut ffi::RSA, *const *mut u8) -> c_int) -> Vec<u8> {
         unsafe {
             let rsa = ffi::EVP_PKEY_get1_RSA(self.evp);
@@ -137,6 +191,56 @@ impl PKey {
         }
     }
 
+    /// Generates a new RSA key of `bits` bits, with a public exponent of
+    /// 65537.
+    pub fn generate_rsa(bits: u32) -> Result<PKey, SslError> {
+        unsafe {
+            ffi::init();
+
+            let rsa = try_ssl_null!(ffi::RSA_generate_key(bits as c_int, 65537 as c_ulong,
+                                                          ptr::null(), ptr::null()));
+            PKey::assign(ffi::EVP_PKEY_RSA, rsa, ffi::RSA_free)
+        }
+    }
+
+    /// Generates a new EC key on the curve identified by `curve_nid`, such as
+    /// `ffi::NID_X9_62_prime256v1` for P-256.
+    pub fn generate_ec(curve_nid: i32) -> Result<PKey, SslError> {
+        unsafe {
+            ffi::init();
+
+            let ec = try_ssl_null!(ffi::EC_KEY_new_by_curve_name(curve_nid as c_int));
+            // Refer to the curve by name when the key is encoded, rather than
+            // spelling out its parameters, which many peers reject
+            ffi::EC_KEY_set_asn1_flag(ec, ffi::OPENSSL_EC_NAMED_CURVE);
+            if ffi::EC_KEY_generate_key(ec) <= 0 {
+                ffi::EC_KEY_free(ec);
+                return Err(SslError::get());
+            }
+            PKey::assign(ffi::EVP_PKEY_EC, ec, ffi::EC_KEY_free)
+        }
+    }
+
+    /// Wraps `key` of type `evp_type` in a new `PKey`, freeing it with
+    /// `free` on failure.
+    unsafe fn assign(evp_type: c_int, key: *mut c_void,
+                     free: unsafe extern "C" fn(*mut c_void)) -> Result<PKey, SslError> {
+        let evp = ffi::EVP_PKEY_new();
+        if evp.is_null() {
+            free(key);
+            return Err(SslError::get());
+        }
+        if ffi::EVP_PKEY_assign(evp, evp_type, key) <= 0 {
+            free(key);
+            ffi::EVP_PKEY_free(evp);
+            return Err(SslError::get());
+        }
+        Ok(PKey {
+            evp:   evp,
+            parts: Parts::Both,
+        })
+    }
+
     /**
      * Returns a serialized form of the public key, suitable for load_pub().
      */
@@ -184,10 +288,24 @@ impl PKey {
 
     /**
      * Returns the size of the public key modulus.
+     *
+     * For keys other than RSA keys, this is the maximum size of a signature.
      */
     pub fn size(&self) -> usize {
         unsafe {
-            ffi::RSA_size(ffi::EVP_PKEY_get1_RSA(self.evp)) as usize
+            ffi::EVP_PKEY_size(self.evp) as usize
+        }
+    }
+
+    /// Returns the algorithm of the key.
+    pub fn key_type(&self) -> KeyType {
+        match unsafe { ffi::EVP_PKEY_base_id(self.evp) } {
+            ffi::EVP_PKEY_RSA => KeyType::Rsa,
+            ffi::EVP_PKEY_DSA => KeyType::Dsa,
+            ffi::EVP_PKEY_DH => KeyType::Dh,
+            ffi::EVP_PKEY_EC => KeyType::Ec,
+            ffi::EVP_PKEY_ED25519 => KeyType::Ed25519,
+            id => KeyType::Other(id),
         }
     }
 
@@ -375,6 +493,7 @@ impl Drop for PKey {
 
 #[cfg(test)]
 mod tests {
+    use std::io::prelude::*;
     use std::path::Path;
     use std::fs::File;
     use crypto::hash::Type::{MD5, SHA1};
@@ -417,6 +536,28 @@ mod tests {
         assert!(k1.can(super::Role::Sign));
     }
 
+    #[test]
+    fn test_generate_rsa() {
+        let key = super::PKey::generate_rsa(1024).unwrap();
+        assert_eq!(key.key_type(), super::KeyType::Rsa);
+        assert_eq!(key.size(), 128);
+
+        let msg = vec!(0xdeu8, 0xadu8, 0xd0u8, 0x0du8);
+        let sig = key.sign(&msg);
+        assert!(key.verify(&msg, &sig));
+    }
+
+    #[test]
+    fn test_generate_ec() {
+        use ffi;
+
+        let key = super::PKey::generate_ec(ffi::NID_secp384r1).unwrap();
+        assert_eq!(key.key_type(), super::KeyType::Ec);
+        assert!(!key.public_eq(&super::PKey::generate_ec(ffi::NID_secp384r1).unwrap()));
+
+        assert!(super::PKey::generate_ec(ffi::NID_undef).is_err());
+    }
+
     #[test]
     fn test_private_key_from_pem() {
         let key_path = Path::new("test/key.pem");
@@ -424,7 +565,44 @@ mod tests {
             .ok()
             .expect("Failed to open `test/key.pem`");
 
-        super::PKey::private_key_from_pem(&mut file).unwrap();
+        let key = super::PKey::private_key_from_pem(&mut file).unwrap();
+        assert_eq!(key.key_type(), super::KeyType::Rsa);
+    }
+
+    #[test]
+    fn test_ec_private_key_from_pem() {
+        let mut file = File::open("test/ec_key.pem").unwrap();
+        let key = super::PKey::private_key_from_pem(&mut file).unwrap();
+        assert_eq!(key.key_type(), super::KeyType::Ec);
+        // The maximum size of a DER encoded P-256 signature
+        assert_eq!(key.size(), 72);
+    }
+
+    #[test]
+    #[cfg(feature = "tlsv1_3")]
+    fn test_ed25519_private_key_from_pem() {
+        let mut file = File::open("test/ed25519_key.pem").unwrap();
+        let key = super::PKey::private_key_from_pem(&mut file).unwrap();
+        assert_eq!(key.key_type(), super::KeyType::Ed25519);
+    }
+
+    #[test]
+    fn test_private_key_from_der() {
+        let mut der = vec![];
+        File::open(&Path::new("test/key.der")).unwrap().read_to_end(&mut der).unwrap();
+
+        let key = super::PKey::private_key_from_der(&der).unwrap();
+        assert!(key.can(super::Role::Sign));
+    }
+
+    #[test]
+    fn test_private_key_from_pkcs8() {
+        let mut der = vec![];
+        File::open(&Path::new("test/key.pk8.der")).unwrap().read_to_end(&mut der).unwrap();
+
+        let key = super::PKey::private_key_from_pkcs8(&der, "mypass").unwrap();
+        assert!(key.can(super::Role::Sign));
+        assert!(super::PKey::private_key_from_pkcs8(&der, "wrong").is_err());
     }
 
     #[test]
@@ -1,4 +1,4 @@
-use libc::c_int;
+use libc::{c_int, c_void};
 use ffi;
 
 pub fn rand_bytes(len: usize) -> Vec<u8> {
@@ -15,13 +15,39 @@ pub fn rand_bytes(len: usize) -> Vec<u8> {
     }
 }
 
+/// Mixes `buf` into the state of the random number generator.
+///
+/// This is only needed on platforms where OpenSSL cannot seed itself, and
+/// `buf` should come from a source of real entropy.
+pub fn seed(buf: &[u8]) {
+    unsafe {
+        ffi::init();
+        ffi::RAND_seed(buf.as_ptr() as *const c_void, buf.len() as c_int);
+    }
+}
+
+/// Returns whether the random number generator has been seeded with enough
+/// entropy to be used safely.
+pub fn rand_status() -> bool {
+    unsafe {
+        ffi::init();
+        ffi::RAND_status() == 1
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::rand_bytes;
+    use super::{rand_bytes, rand_status, seed};
 
     #[test]
     fn test_rand_bytes() {
         let bytes = rand_bytes(32);
         println!("{:?}", bytes);
     }
+
+    #[test]
+    fn test_seed() {
+        seed(&[0; 32]);
+        assert!(rand_status());
+    }
 }
@@ -1,8 +1,14 @@
+use std::io;
 use std::iter::repeat;
 use std::convert::AsRef;
 use libc::{c_int};
+#[cfg(feature = "aes_gcm")]
+use libc::c_void;
+#[cfg(feature = "aes_gcm")]
+use std::ptr;
 
 use ffi;
+use ssl::error::{SslError, StreamError};
 
 #[derive(Copy, Clone)]
 pub enum Mode {
@@ -19,7 +25,9 @@ pub enum Type {
     #[cfg(feature = "aes_xts")]
     AES_128_XTS,
     // AES_128_CTR,
-    //AES_128_GCM,
+    /// Requires the `aes_gcm` feature
+    #[cfg(feature = "aes_gcm")]
+    AES_128_GCM,
 
     AES_256_ECB,
     AES_256_CBC,
@@ -27,39 +35,53 @@ pub enum Type {
     #[cfg(feature = "aes_xts")]
     AES_256_XTS,
     // AES_256_CTR,
-    //AES_256_GCM,
+    /// Requires the `aes_gcm` feature
+    #[cfg(feature = "aes_gcm")]
+    AES_256_GCM,
 
     RC4_128,
 }
 
-fn evpc(t: Type) -> (*const ffi::EVP_CIPHER, u32, u32) {
+/// Returns the cipher with its key, block and IV lengths
+fn evpc(t: Type) -> (*const ffi::EVP_CIPHER, u32, u32, u32) {
     unsafe {
         match t {
-            Type::AES_128_ECB => (ffi::EVP_aes_128_ecb(), 16, 16),
-            Type::AES_128_CBC => (ffi::EVP_aes_128_cbc(), 16, 16),
+            Type::AES_128_ECB => (ffi::EVP_aes_128_ecb(), 16, 16, 0),
+            Type::AES_128_CBC => (ffi::EVP_aes_128_cbc(), 16, 16, 16),
             #[cfg(feature = "aes_xts")]
-            Type::AES_128_XTS => (ffi::EVP_aes_128_xts(), 32, 16),
-            // AES_128_CTR => (EVP_aes_128_ctr(), 16, 0),
-            //AES_128_GCM => (EVP_aes_128_gcm(), 16, 16),
+            Type::AES_128_XTS => (ffi::EVP_aes_128_xts(), 32, 16, 16),
+            // AES_128_CTR => (EVP_aes_128_ctr(), 16, 0, 16),
+            #[cfg(feature = "aes_gcm")]
+            Type::AES_128_GCM => (ffi::EVP_aes_128_gcm(), 16, 16, GCM_IV_LEN),
 
-            Type::AES_256_ECB => (ffi::EVP_aes_256_ecb(), 32, 16),
-            Type::AES_256_CBC => (ffi::EVP_aes_256_cbc(), 32, 16),
+            Type::AES_256_ECB => (ffi::EVP_aes_256_ecb(), 32, 16, 0),
+            Type::AES_256_CBC => (ffi::EVP_aes_256_cbc(), 32, 16, 16),
             #[cfg(feature = "aes_xts")]
-            Type::AES_256_XTS => (ffi::EVP_aes_256_xts(), 64, 16),
-            // AES_256_CTR => (EVP_aes_256_ctr(), 32, 0),
-            //AES_256_GCM => (EVP_aes_256_gcm(), 32, 16),
+            Type::AES_256_XTS => (ffi::EVP_aes_256_xts(), 64, 16, 16),
+            // AES_256_CTR => (EVP_aes_256_ctr(), 32, 0, 16),
+            #[cfg(feature = "aes_gcm")]
+            Type::AES_256_GCM => (ffi::EVP_aes_256_gcm(), 32, 16, GCM_IV_LEN),
 
-            Type::RC4_128 => (ffi::EVP_rc4(), 16, 0),
+            Type::RC4_128 => (ffi::EVP_rc4(), 16, 0, 0),
         }
     }
 }
 
+/// The IV length OpenSSL uses for GCM unless told otherwise
+#[cfg(feature = "aes_gcm")]
+const GCM_IV_LEN: u32 = 12;
+
+/// The length of the tags produced by `encrypt_aead`
+#[cfg(feature = "aes_gcm")]
+const GCM_TAG_LEN: usize = 16;
+
 /// Represents a symmetric cipher context.
 pub struct Crypter {
     evp: *const ffi::EVP_CIPHER,
     ctx: *mut ffi::EVP_CIPHER_CTX,
     keylen: u32,
     blocksize: u32,
+    ivlen: u32,
 }
 
 impl Crypter {
@@ -67,8 +89,8 @@ impl Crypter {
         ffi::init();
 
         let ctx = unsafe { ffi::EVP_CIPHER_CTX_new() };
-        let (evp, keylen, blocksz) = evpc(t);
-        Crypter { evp: evp, ctx: ctx, keylen: keylen, blocksize: blocksz }
+        let (evp, keylen, blocksz, ivlen) = evpc(t);
+        Crypter { evp: evp, ctx: ctx, keylen: keylen, blocksize: blocksz, ivlen: ivlen }
     }
 
     /**
@@ -86,6 +108,9 @@ impl Crypter {
 
     /**
      * Initializes this crypter.
+     *
+     * Panics if `key` has the wrong length or `iv` is too short for the
+     * cipher. GCM ciphers need an `iv` of exactly 12 bytes.
      */
     pub fn init<T: AsRef<[u8]>>(&self, mode: Mode, key: &[u8], iv: T) {
         unsafe {
@@ -93,13 +118,16 @@ impl Crypter {
                 Mode::Encrypt => 1 as c_int,
                 Mode::Decrypt => 0 as c_int,
             };
+            let iv = iv.as_ref();
             assert_eq!(key.len(), self.keylen as usize);
+            // OpenSSL reads the cipher's full IV length whatever the slice's
+            assert!(iv.len() >= self.ivlen as usize);
 
             ffi::EVP_CipherInit(
                 self.ctx,
                 self.evp,
                 key.as_ptr(),
-                iv.as_ref().as_ptr(),
+                iv.as_ptr(),
                 mode
             );
         }
@@ -128,20 +156,96 @@ impl Crypter {
         }
     }
 
+    /**
+     * Adds additional authenticated data, which is covered by the tag but not
+     * encrypted. This must be called before any data is passed to `update`.
+     *
+     * Requires the `aes_gcm` feature.
+     */
+    #[cfg(feature = "aes_gcm")]
+    pub fn aad_update(&self, aad: &[u8]) -> Result<(), SslError> {
+        unsafe {
+            let mut outlen = 0 as c_int;
+
+            try_ssl_if!(ffi::EVP_CipherUpdate(
+                self.ctx,
+                ptr::null_mut(),
+                &mut outlen,
+                aad.as_ptr(),
+                aad.len() as c_int
+            ) <= 0);
+        }
+        Ok(())
+    }
+
+    /**
+     * Sets the expected authentication tag when decrypting. This must be
+     * called before `try_finalize`, which fails if the tag doesn't match.
+     *
+     * Requires the `aes_gcm` feature.
+     */
+    #[cfg(feature = "aes_gcm")]
+    pub fn set_tag(&self, tag: &[u8]) -> Result<(), SslError> {
+        unsafe {
+            try_ssl_if!(ffi::EVP_CIPHER_CTX_ctrl(self.ctx, ffi::EVP_CTRL_GCM_SET_TAG,
+                                                 tag.len() as c_int,
+                                                 tag.as_ptr() as *mut c_void) <= 0);
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns the first `len` bytes of the authentication tag after
+     * encryption has been finished. GCM tags are at most 16 bytes long.
+     *
+     * Requires the `aes_gcm` feature.
+     */
+    #[cfg(feature = "aes_gcm")]
+    pub fn get_tag(&self, len: usize) -> Result<Vec<u8>, SslError> {
+        unsafe {
+            let mut tag = repeat(0u8).take(len).collect::<Vec<_>>();
+
+            try_ssl_if!(ffi::EVP_CIPHER_CTX_ctrl(self.ctx, ffi::EVP_CTRL_GCM_GET_TAG,
+                                                 tag.len() as c_int,
+                                                 tag.as_mut_ptr() as *mut c_void) <= 0);
+            Ok(tag)
+        }
+    }
+
     /**
      * Finish crypting. Returns the remaining partial block of output, if any.
+     *
+     * Failures are ignored, use `try_finalize` to detect them.
      */
     pub fn finalize(&self) -> Vec<u8> {
+        self.try_finalize().unwrap_or(vec![])
+    }
+
+    /**
+     * Finish crypting. Returns the remaining partial block of output, if any,
+     * or an error if the data was incorrectly padded or, for authenticated
+     * ciphers, its tag doesn't match.
+     */
+    pub fn try_finalize(&self) -> Result<Vec<u8>, SslError> {
         unsafe {
             let mut res = repeat(0u8).take(self.blocksize as usize).collect::<Vec<_>>();
             let mut reslen = self.blocksize as c_int;
 
-            ffi::EVP_CipherFinal(self.ctx,
-                                       res.as_mut_ptr(),
-                                       &mut reslen);
+            if ffi::EVP_CipherFinal(self.ctx,
+                                    res.as_mut_ptr(),
+                                    &mut reslen) <= 0 {
+                // A tag mismatch doesn't leave anything in the error queue
+                return match SslError::get() {
+                    SslError::OpenSslErrors(ref errs) if errs.is_empty() => {
+                        Err(StreamError(io::Error::new(io::ErrorKind::InvalidData,
+                                                       "unable to finalize cipher")))
+                    }
+                    err => Err(err),
+                };
+            }
 
             res.truncate(reslen as usize);
-            res
+            Ok(res)
         }
     }
 }
@@ -180,6 +284,59 @@ pub fn decrypt<T: AsRef<[u8]>>(t: Type, key: &[u8], iv: T, data: &[u8]) -> Vec<u
     r
 }
 
+/**
+ * Encrypts data with an authenticated cipher, using the specified crypter
+ * type with the specified key and iv. `aad` is authenticated along with the
+ * data without being encrypted. Returns the encrypted data and the 16 byte
+ * tag.
+ *
+ * Requires the `aes_gcm` feature.
+ */
+#[cfg(feature = "aes_gcm")]
+pub fn encrypt_aead<T: AsRef<[u8]>>(t: Type, key: &[u8], iv: T, aad: &[u8],
+                                    data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), SslError> {
+    let c = Crypter::new(t);
+    try!(check_aead_iv(&c, iv.as_ref()));
+    c.init(Mode::Encrypt, key, iv);
+    try!(c.aad_update(aad));
+    let mut r = c.update(data);
+    let rest = try!(c.try_finalize());
+    r.extend(rest.into_iter());
+    Ok((r, try!(c.get_tag(GCM_TAG_LEN))))
+}
+
+/**
+ * Decrypts data with an authenticated cipher, using the specified crypter
+ * type with the specified key and iv. Returns the decrypted data, or an error
+ * if `tag` doesn't match the data and `aad`.
+ *
+ * Requires the `aes_gcm` feature.
+ */
+#[cfg(feature = "aes_gcm")]
+pub fn decrypt_aead<T: AsRef<[u8]>>(t: Type, key: &[u8], iv: T, aad: &[u8], data: &[u8],
+                                    tag: &[u8]) -> Result<Vec<u8>, SslError> {
+    let c = Crypter::new(t);
+    try!(check_aead_iv(&c, iv.as_ref()));
+    c.init(Mode::Decrypt, key, iv);
+    try!(c.aad_update(aad));
+    let mut r = c.update(data);
+    try!(c.set_tag(tag));
+    let rest = try!(c.try_finalize());
+    r.extend(rest.into_iter());
+    Ok(r)
+}
+
+/// OpenSSL would only use part of a longer IV, so anything but the exact
+/// length is refused
+#[cfg(feature = "aes_gcm")]
+fn check_aead_iv(c: &Crypter, iv: &[u8]) -> Result<(), SslError> {
+    if iv.len() != c.ivlen as usize {
+        return Err(StreamError(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "the IV has the wrong length")));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use serialize::hex::FromHex;
@@ -243,6 +400,36 @@ mod tests {
         assert_eq!(&unciphered_data_1, expected_unciphered_data);
     }
 
+    #[test]
+    fn test_aes_128_cbc_streaming() {
+        let key = [0x2bu8; 16];
+        let iv = [0xf0u8; 16];
+        let data = (0..3 * 1024 * 1024 + 5).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        // Chunk sizes which don't line up with the block size, so partial
+        // blocks are carried over between calls
+        let chunks = [1, 15, 16, 17, 4099];
+
+        let c = super::Crypter::new(super::Type::AES_128_CBC);
+        c.init(super::Mode::Encrypt, &key, &iv);
+        let mut encrypted = vec![];
+        for (chunk, &size) in data.chunks(4099).zip(chunks.iter().cycle()) {
+            for part in chunk.chunks(size) {
+                encrypted.extend(c.update(part).into_iter());
+            }
+        }
+        encrypted.extend(c.finalize().into_iter());
+        assert_eq!(encrypted, super::encrypt(super::Type::AES_128_CBC, &key, &iv, &data));
+
+        c.init(super::Mode::Decrypt, &key, &iv);
+        let mut decrypted = vec![];
+        for chunk in encrypted.chunks(1000) {
+            decrypted.extend(c.update(chunk).into_iter());
+        }
+        decrypted.extend(c.finalize().into_iter());
+        assert!(decrypted == data);
+    }
+
     fn cipher_test(ciphertype: super::Type, pt: &str, ct: &str, key: &str, iv: &str) {
         use serialize::hex::ToHex;
 
@@ -299,14 +486,45 @@ mod tests {
         cipher_test(super::AES_128_CTR, pt, ct, key, iv);
     }*/
 
-    /*#[test]
+    #[test]
+    #[cfg(feature = "aes_gcm")]
     fn test_aes128_gcm() {
-        // Test case 3 in GCM spec
-        let pt = ~"d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255";
-        let ct = ~"42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091473f59854d5c2af327cd64a62cf35abd2ba6fab4";
-        let key = ~"feffe9928665731c6d6a8f9467308308";
-        let iv = ~"cafebabefacedbaddecaf888";
-
-        cipher_test(super::AES_128_GCM, pt, ct, key, iv);
-    }*/
+        // Test case 4 in GCM spec
+        let pt = "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39".from_hex().unwrap();
+        let ct = "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091".from_hex().unwrap();
+        let aad = "feedfacedeadbeeffeedfacedeadbeefabaddad2".from_hex().unwrap();
+        let tag = "5bc94fbc3221a5db94fae95ae7121a47".from_hex().unwrap();
+        let key = "feffe9928665731c6d6a8f9467308308".from_hex().unwrap();
+        let iv = "cafebabefacedbaddecaf888".from_hex().unwrap();
+
+        let (computed, computed_tag) = super::encrypt_aead(super::Type::AES_128_GCM, &key, &iv,
+                                                           &aad, &pt).unwrap();
+        assert!(computed == ct);
+        assert!(computed_tag == tag);
+
+        let decrypted = super::decrypt_aead(super::Type::AES_128_GCM, &key, &iv, &aad, &ct,
+                                            &tag).unwrap();
+        assert!(decrypted == pt);
+
+        let mut bad_tag = tag.clone();
+        bad_tag[0] ^= 1;
+        assert!(super::decrypt_aead(super::Type::AES_128_GCM, &key, &iv, &aad, &ct,
+                                    &bad_tag).is_err());
+        assert!(super::decrypt_aead(super::Type::AES_128_GCM, &key, &iv, &[], &ct,
+                                    &tag).is_err());
+
+        // A 16 byte IV would be cut short, and a tag longer than 16 bytes
+        // doesn't exist
+        assert!(super::encrypt_aead(super::Type::AES_128_GCM, &key, &[0; 16], &aad,
+                                    &pt).is_err());
+        assert!(super::decrypt_aead(super::Type::AES_128_GCM, &key, &[0; 16], &aad, &ct,
+                                    &tag).is_err());
+        let c = super::Crypter::new(super::Type::AES_128_GCM);
+        c.init(super::Mode::Encrypt, &key, &iv);
+        c.aad_update(&aad).unwrap();
+        c.update(&pt);
+        c.try_finalize().unwrap();
+        assert!(c.get_tag(17).is_err());
+        assert!(c.get_tag(12).unwrap() == &tag[..12]);
+    }
 }
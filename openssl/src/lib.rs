@@ -23,3 +23,6 @@ pub mod crypto;
 pub mod ssl;
 pub mod x509;
 pub mod nid;
+pub mod ocsp;
+
+pub use ffi::thread_setup_complete;
@@ -1,3 +1,6 @@
+use libc::c_int;
+use std::mem;
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
 #[repr(usize)]
@@ -171,3 +174,34 @@ pub enum Nid {
     RC2_64_CBC,
     SMIMECaps
 }
+
+impl Nid {
+    /// Returns the `Nid` with the given numeric value, if it is one of the
+    /// values known to this crate.
+    ///
+    /// `NID_undef` (0), which OpenSSL uses for unknown objects, gives `None`.
+    pub fn from_raw(raw: c_int) -> Option<Nid> {
+        // Every value in this range has a variant, except for the gap left
+        // before RSA_RIPEMD160
+        if raw > 0 && raw <= Nid::SMIMECaps as c_int && raw != 118 {
+            Some(unsafe { mem::transmute(raw as usize) })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Nid;
+
+    #[test]
+    fn test_from_raw() {
+        assert!(Nid::from_raw(0).is_none());
+        assert!(Nid::from_raw(13) == Some(Nid::CN));
+        assert!(Nid::from_raw(118).is_none());
+        assert!(Nid::from_raw(119) == Some(Nid::RSA_RIPEMD160));
+        assert!(Nid::from_raw(Nid::SMIMECaps as i32 + 1).is_none());
+        assert!(Nid::from_raw(-1).is_none());
+    }
+}
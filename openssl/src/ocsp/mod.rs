@@ -0,0 +1,138 @@
+//! Checking of OCSP responses, such as those stapled by a server.
+
+use libc::{c_long, c_void};
+use std::io;
+use std::ptr;
+
+use ffi;
+use ssl::SslContext;
+use ssl::error::{SslError, StreamError};
+use x509::X509Ref;
+
+/// The amount of clock skew, in seconds, tolerated when checking the
+/// validity period of a response.
+const MAX_SKEW: c_long = 300;
+
+/// The revocation status of a certificate reported by an OCSP responder.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OcspStatus {
+    Good,
+    Revoked,
+    /// The responder doesn't know about the certificate, or the response
+    /// doesn't cover it.
+    Unknown,
+}
+
+/// Checks the status of `cert` in a DER-encoded OCSP response.
+///
+/// `issuer` is the certificate which issued `cert`. The signature of the
+/// response is verified against the trusted certificates of `ctx`, and its
+/// validity period is checked against the current time.
+///
+/// An error is returned if the response cannot be parsed or verified, or if
+/// the responder reported an error instead of a status.
+pub fn check_response(resp: &[u8], cert: &X509Ref, issuer: &X509Ref,
+                      ctx: &SslContext) -> Result<OcspStatus, SslError> {
+    unsafe {
+        let mut ptr = resp.as_ptr();
+        let resp = try_ssl_null!(ffi::d2i_OCSP_RESPONSE(ptr::null_mut(), &mut ptr,
+                                                        resp.len() as c_long));
+
+        let status = ffi::OCSP_response_status(resp);
+        if status != ffi::OCSP_RESPONSE_STATUS_SUCCESSFUL {
+            ffi::OCSP_RESPONSE_free(resp);
+            return Err(StreamError(io::Error::new(io::ErrorKind::Other,
+                                                  format!("OCSP responder returned \
+                                                           status {}", status))));
+        }
+
+        let basic = ffi::OCSP_response_get1_basic(resp);
+        ffi::OCSP_RESPONSE_free(resp);
+        if basic.is_null() {
+            return Err(SslError::get());
+        }
+
+        let ret = check_basic(basic, cert, issuer, ctx);
+        ffi::OCSP_BASICRESP_free(basic);
+        ret
+    }
+}
+
+unsafe fn check_basic(basic: *mut ffi::OCSP_BASICRESP, cert: &X509Ref, issuer: &X509Ref,
+                      ctx: &SslContext) -> Result<OcspStatus, SslError> {
+    // Responses are usually signed by the issuer itself, which the response
+    // need not include
+    let certs = try_ssl_null!(ffi::sk_new_null());
+    if ffi::sk_push(certs, issuer.get_handle() as *mut c_void) == 0 {
+        ffi::sk_free(certs);
+        return Err(SslError::get());
+    }
+    let store = ctx.cert_store().get_handle();
+    let verified = ffi::OCSP_basic_verify(basic, certs, store, 0);
+    // the stack does not own the certificate in it
+    ffi::sk_free(certs);
+    if verified <= 0 {
+        return Err(SslError::get());
+    }
+
+    let id = try_ssl_null!(ffi::OCSP_cert_to_id(ptr::null(), cert.get_handle(),
+                                                issuer.get_handle()));
+    let mut status = 0;
+    let mut reason = 0;
+    let mut revtime = ptr::null_mut();
+    let mut thisupd = ptr::null_mut();
+    let mut nextupd = ptr::null_mut();
+    let found = ffi::OCSP_resp_find_status(basic, id, &mut status, &mut reason, &mut revtime,
+                                           &mut thisupd, &mut nextupd);
+    ffi::OCSP_CERTID_free(id);
+    if found == 0 {
+        return Ok(OcspStatus::Unknown);
+    }
+
+    if ffi::OCSP_check_validity(thisupd, nextupd, MAX_SKEW, -1) == 0 {
+        return Err(SslError::get());
+    }
+
+    Ok(match status {
+        ffi::V_OCSP_CERTSTATUS_GOOD => OcspStatus::Good,
+        ffi::V_OCSP_CERTSTATUS_REVOKED => OcspStatus::Revoked,
+        _ => OcspStatus::Unknown,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    use ssl::SslContext;
+    use ssl::SslMethod::Sslv23;
+    use x509::X509;
+    use super::{check_response, OcspStatus};
+
+    fn load(path: &str) -> Vec<u8> {
+        let mut buf = vec![];
+        File::open(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_check_response() {
+        let ca = X509::from_pem(&mut File::open("test/ocsp_ca.pem").unwrap()).unwrap();
+        let leaf = X509::from_pem(&mut File::open("test/ocsp_leaf.pem").unwrap()).unwrap();
+        let good = load("test/ocsp_good.der");
+        let revoked = load("test/ocsp_revoked.der");
+
+        // The responder isn't trusted yet
+        let mut ctx = SslContext::new(Sslv23).unwrap();
+        assert!(check_response(&good, &leaf, &ca, &ctx).is_err());
+
+        ctx.add_CA_cert(&ca).unwrap();
+        assert_eq!(check_response(&good, &leaf, &ca, &ctx).unwrap(), OcspStatus::Good);
+        assert_eq!(check_response(&revoked, &leaf, &ca, &ctx).unwrap(), OcspStatus::Revoked);
+        // The responses only cover the leaf certificate
+        assert_eq!(check_response(&good, &ca, &ca, &ctx).unwrap(), OcspStatus::Unknown);
+
+        assert!(check_response(&good[..100], &leaf, &ca, &ctx).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 pub use self::SslError::*;
 pub use self::OpensslError::*;
 
-use libc::c_ulong;
+use libc::{c_char, c_ulong, size_t};
 use std::error;
 use std::fmt;
 use std::ffi::CStr;
@@ -59,6 +59,55 @@ impl error::Error for SslError {
     }
 }
 
+/// An error from an operation which cannot complete without more I/O
+#[derive(Debug)]
+pub enum NonblockingSslError {
+    /// A regular SSL error
+    SslError(SslError),
+    /// More encrypted data from the peer is needed before the operation can
+    /// be retried
+    WantRead,
+    /// Encrypted data queued for the peer must be sent before the operation
+    /// can be retried
+    WantWrite,
+    /// A client certificate lookup is in progress, and the operation should
+    /// be retried once it has completed
+    WantX509Lookup,
+}
+
+impl fmt::Display for NonblockingSslError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NonblockingSslError::SslError(ref e) => fmt::Display::fmt(e, fmt),
+            _ => fmt.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for NonblockingSslError {
+    fn description(&self) -> &str {
+        match *self {
+            NonblockingSslError::SslError(ref e) => e.description(),
+            NonblockingSslError::WantRead => "The operation needs more data from the peer",
+            NonblockingSslError::WantWrite => "The operation needs data to be sent to the peer",
+            NonblockingSslError::WantX509Lookup => "The operation needs a certificate lookup to complete",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            NonblockingSslError::SslError(ref e) => e.cause(),
+            _ => None,
+        }
+    }
+}
+
+impl From<SslError> for NonblockingSslError {
+    fn from(e: SslError) -> NonblockingSslError {
+        NonblockingSslError::SslError(e)
+    }
+}
+
 /// An error from the OpenSSL library
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OpensslError {
@@ -73,25 +122,28 @@ pub enum OpensslError {
     }
 }
 
-fn get_lib(err: c_ulong) -> String {
+// The error string functions return NULL for codes without a registered
+// string, which is common for errors from the system library
+fn error_string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
     unsafe {
-        let bytes = CStr::from_ptr(ffi::ERR_lib_error_string(err)).to_bytes().to_vec();
-        String::from_utf8(bytes).unwrap()
+        let bytes = CStr::from_ptr(s).to_bytes();
+        String::from_utf8_lossy(bytes).into_owned()
     }
 }
 
+fn get_lib(err: c_ulong) -> String {
+    error_string(unsafe { ffi::ERR_lib_error_string(err) })
+}
+
 fn get_func(err: c_ulong) -> String {
-    unsafe {
-        let bytes = CStr::from_ptr(ffi::ERR_func_error_string(err)).to_bytes().to_vec();
-        String::from_utf8(bytes).unwrap()
-    }
+    error_string(unsafe { ffi::ERR_func_error_string(err) })
 }
 
 fn get_reason(err: c_ulong) -> String {
-    unsafe {
-        let bytes = CStr::from_ptr(ffi::ERR_reason_error_string(err)).to_bytes().to_vec();
-        String::from_utf8(bytes).unwrap()
-    }
+    error_string(unsafe { ffi::ERR_reason_error_string(err) })
 }
 
 impl SslError {
@@ -123,6 +175,54 @@ impl SslError {
     }
 }
 
+/// Discards all entries in the current thread's OpenSSL error queue.
+///
+/// Entries left behind by an earlier operation would otherwise be reported
+/// as part of the next `SslError::get()`.
+pub fn clear_error_stack() {
+    unsafe { ffi::ERR_clear_error() }
+}
+
+/// Removes all entries from the current thread's OpenSSL error queue and
+/// formats them one per line, for logging.
+///
+/// Each line has OpenSSL's usual `error:<code>:<library>:<function>:<reason>`
+/// form. An empty string is returned if the queue is empty.
+pub fn last_error_string() -> String {
+    ffi::init();
+    let mut out = String::new();
+    loop {
+        let err = unsafe { ffi::ERR_get_error() };
+        if err == 0 {
+            break;
+        }
+
+        let mut buf = [0 as c_char; 256];
+        unsafe { ffi::ERR_error_string_n(err, buf.as_mut_ptr(), buf.len() as size_t) };
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&error_string(buf.as_ptr()));
+    }
+    out
+}
+
+#[test]
+fn test_clear_error_stack() {
+    use std::ptr;
+
+    let der = [0u8; 4];
+    let mut p = der.as_ptr();
+    let x509 = unsafe { ffi::d2i_X509(ptr::null_mut(), &mut p, der.len() as ::libc::c_long) };
+    assert!(x509.is_null());
+
+    clear_error_stack();
+    match SslError::get() {
+        OpenSslErrors(errs) => assert!(errs.is_empty()),
+        err => panic!("Unexpected error {:?}", err),
+    }
+}
+
 #[test]
 fn test_uknown_error_should_have_correct_messages() {
     let errs = match SslError::from_error(336032784) {
@@ -136,3 +236,21 @@ fn test_uknown_error_should_have_correct_messages() {
     assert_eq!(&function[..], "SSL23_GET_SERVER_HELLO");
     assert_eq!(&reason[..], "sslv3 alert handshake failure");
 }
+
+#[test]
+fn test_last_error_string() {
+    use std::ptr;
+
+    clear_error_stack();
+    assert_eq!(last_error_string(), "");
+
+    let der = [0u8; 4];
+    let mut p = der.as_ptr();
+    let x509 = unsafe { ffi::d2i_X509(ptr::null_mut(), &mut p, der.len() as ::libc::c_long) };
+    assert!(x509.is_null());
+
+    let s = last_error_string();
+    assert!(s.starts_with("error:"));
+    assert!(s.lines().all(|line| line.starts_with("error:")));
+    assert_eq!(last_error_string(), "");
+}
@@ -1,34 +1,45 @@
-use libc::{c_int, c_void, c_long};
+use libc::{c_int, c_void, c_long, c_uchar};
 use std::any::TypeId;
 use std::collections::HashMap;
+use std::env;
 use std::ffi::{CStr, CString};
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::marker::PhantomData;
 use std::mem;
 use std::net;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::sync::{Once, ONCE_INIT, Arc, Mutex};
+use std::sync::{Once, ONCE_INIT, Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::ops::{Deref, DerefMut};
 use std::cmp;
 use std::any::Any;
-#[cfg(any(feature = "npn", feature = "alpn"))]
-use libc::{c_uchar, c_uint};
-#[cfg(any(feature = "npn", feature = "alpn"))]
+#[cfg(any(feature = "npn", feature = "alpn", feature = "dtlsv1", feature = "dtlsv1_2",
+          feature = "psk"))]
+use libc::c_uint;
+use libc::c_char;
+use libc::size_t;
+#[cfg(any(feature = "npn", feature = "alpn", feature = "dtlsv1", feature = "dtlsv1_2"))]
 use std::slice;
 
 use bio::{MemBio};
 use ffi;
-use ssl::error::{SslError, SslSessionClosed, StreamError, OpenSslErrors};
-use x509::{X509StoreContext, X509FileType, X509};
+use ssl::error::{SslError, SslSessionClosed, StreamError, OpenSslErrors, NonblockingSslError};
+use x509::{X509StoreContext, X509FileType, X509, X509Ref, X509StoreRef, X509StoreRefMut, X509VerifyFlags, X509_V_FLAG_TRUSTED_FIRST, X509Purpose, X509ValidationError};
 use crypto::pkey::PKey;
+#[cfg(feature = "engine")]
+use crypto::engine::Engine;
 
 pub mod error;
+mod roots;
 #[cfg(test)]
 mod tests;
 
 static mut VERIFY_IDX: c_int = -1;
+static mut SSL_VERIFY_IDX: c_int = -1;
 
 fn init() {
     static mut INIT: Once = ONCE_INIT;
@@ -41,6 +52,11 @@ fn init() {
                                                            None, None);
             assert!(verify_idx >= 0);
             VERIFY_IDX = verify_idx;
+
+            let ssl_verify_idx = ffi::SSL_get_ex_new_index(0, ptr::null(), None,
+                                                           None, None);
+            assert!(ssl_verify_idx >= 0);
+            SSL_VERIFY_IDX = ssl_verify_idx;
         });
     }
 }
@@ -84,6 +100,7 @@ bitflags! {
         const SSL_OP_PKCS1_CHECK_1                            = ffi::SSL_OP_PKCS1_CHECK_1,
         const SSL_OP_PKCS1_CHECK_2                            = ffi::SSL_OP_PKCS1_CHECK_2,
         const SSL_OP_EPHEMERAL_RSA                            = ffi::SSL_OP_EPHEMERAL_RSA,
+        const SSL_OP_NO_RENEGOTIATION                         = ffi::SSL_OP_NO_RENEGOTIATION,
         const SSL_OP_ALL         = SSL_OP_MICROSOFT_SESS_ID_BUG.bits|SSL_OP_NETSCAPE_CHALLENGE_BUG.bits
                                   |SSL_OP_LEGACY_SERVER_CONNECT.bits|SSL_OP_NETSCAPE_REUSE_CIPHER_CHANGE_BUG.bits
                                   |SSL_OP_TLSEXT_PADDING.bits|SSL_OP_MICROSOFT_BIG_SSLV3_BUFFER.bits
@@ -114,6 +131,10 @@ pub enum SslMethod {
     #[cfg(feature = "tlsv1_2")]
     /// Support TLSv1.2 protocol, requires the `tlsv1_2` feature.
     Tlsv1_2,
+    #[cfg(feature = "tlsv1_3")]
+    /// Support every TLS version up to TLSv1.3, negotiating the highest one
+    /// the peer supports, requires the `tlsv1_3` feature.
+    Tls,
     #[cfg(feature = "dtlsv1")]
     /// Support DTLSv1 protocol, requires the `dtlsv1` feature.
     Dtlsv1,
@@ -122,6 +143,81 @@ pub enum SslMethod {
     Dtlsv1_2,
 }
 
+/// A protocol version negotiated for a connection
+///
+/// TLS versions compare in order of release, so `version < SslVersion::Tlsv1_2`
+/// checks for a connection using an older protocol. DTLS versions compare as
+/// greater than all TLS versions.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SslVersion {
+    Sslv2,
+    Sslv3,
+    Tlsv1,
+    Tlsv1_1,
+    Tlsv1_2,
+    Tlsv1_3,
+    Dtlsv1,
+    Dtlsv1_2,
+}
+
+impl SslVersion {
+    fn from_raw(version: c_int) -> Option<SslVersion> {
+        match version {
+            ffi::SSL2_VERSION => Some(SslVersion::Sslv2),
+            ffi::SSL3_VERSION => Some(SslVersion::Sslv3),
+            ffi::TLS1_VERSION => Some(SslVersion::Tlsv1),
+            ffi::TLS1_1_VERSION => Some(SslVersion::Tlsv1_1),
+            ffi::TLS1_2_VERSION => Some(SslVersion::Tlsv1_2),
+            ffi::TLS1_3_VERSION => Some(SslVersion::Tlsv1_3),
+            ffi::DTLS1_VERSION => Some(SslVersion::Dtlsv1),
+            ffi::DTLS1_2_VERSION => Some(SslVersion::Dtlsv1_2),
+            _ => None,
+        }
+    }
+
+    fn to_raw(&self) -> c_int {
+        match *self {
+            SslVersion::Sslv2 => ffi::SSL2_VERSION,
+            SslVersion::Sslv3 => ffi::SSL3_VERSION,
+            SslVersion::Tlsv1 => ffi::TLS1_VERSION,
+            SslVersion::Tlsv1_1 => ffi::TLS1_1_VERSION,
+            SslVersion::Tlsv1_2 => ffi::TLS1_2_VERSION,
+            SslVersion::Tlsv1_3 => ffi::TLS1_3_VERSION,
+            SslVersion::Dtlsv1 => ffi::DTLS1_VERSION,
+            SslVersion::Dtlsv1_2 => ffi::DTLS1_2_VERSION,
+        }
+    }
+}
+
+/// Whether early data sent by a client was accepted by the server.
+#[cfg(feature = "early_data")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EarlyDataStatus {
+    /// No early data was sent.
+    NotSent,
+    /// The server refused the early data, which has to be sent again now
+    /// that the handshake is done.
+    Rejected,
+    /// The server processed the early data.
+    Accepted,
+}
+
+/// The outcome of ALPN (application layer protocol negotiation) on a client
+/// connection.
+#[cfg(feature = "alpn")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlpnStatus<'a> {
+    /// No protocols were offered to the server.
+    NotOffered,
+    /// The server did not answer with a protocol, either because it doesn't
+    /// support ALPN or because it supports none of the offered protocols.
+    NotAcknowledged,
+    /// The server selected one of the offered protocols.
+    Selected(&'a [u8]),
+    /// The server selected a protocol which was not offered.
+    Unexpected(&'a [u8]),
+}
+
 impl SslMethod {
     unsafe fn to_raw(&self) -> *const ffi::SSL_METHOD {
         match *self {
@@ -134,6 +230,8 @@ impl SslMethod {
             SslMethod::Tlsv1_1 => ffi::TLSv1_1_method(),
             #[cfg(feature = "tlsv1_2")]
             SslMethod::Tlsv1_2 => ffi::TLSv1_2_method(),
+            #[cfg(feature = "tlsv1_3")]
+            SslMethod::Tls => ffi::TLS_method(),
             #[cfg(feature = "dtlsv1")]
             SslMethod::Dtlsv1 => ffi::DTLSv1_method(),
             #[cfg(feature = "dtlsv1_2")]
@@ -141,6 +239,28 @@ impl SslMethod {
         }
     }
 
+    /// Returns the methods enabled by the features this crate was built
+    /// with.
+    pub fn available() -> Vec<SslMethod> {
+        let mut methods = vec![];
+        #[cfg(feature = "sslv2")]
+        methods.push(SslMethod::Sslv2);
+        methods.push(SslMethod::Sslv23);
+        methods.push(SslMethod::Sslv3);
+        methods.push(SslMethod::Tlsv1);
+        #[cfg(feature = "tlsv1_1")]
+        methods.push(SslMethod::Tlsv1_1);
+        #[cfg(feature = "tlsv1_2")]
+        methods.push(SslMethod::Tlsv1_2);
+        #[cfg(feature = "tlsv1_3")]
+        methods.push(SslMethod::Tls);
+        #[cfg(feature = "dtlsv1")]
+        methods.push(SslMethod::Dtlsv1);
+        #[cfg(feature = "dtlsv1_2")]
+        methods.push(SslMethod::Dtlsv1_2);
+        methods
+    }
+
     #[cfg(feature = "dtlsv1")]
     pub fn is_dtlsv1(&self) -> bool {
         *self == SslMethod::Dtlsv1
@@ -179,6 +299,82 @@ bitflags! {
     }
 }
 
+bitflags! {
+    flags SslContextMode: c_long {
+        /// Allow `SSL_write` to return after writing a single record rather
+        /// than waiting for the entire buffer to be written.
+        const SSL_MODE_ENABLE_PARTIAL_WRITE = ffi::SSL_MODE_ENABLE_PARTIAL_WRITE,
+        /// Allow a retried write to be passed a different buffer holding the
+        /// same data.
+        const SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER = ffi::SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER,
+        /// Retry reads and writes on a blocking transport internally when
+        /// handshake messages such as a renegotiation are processed, instead
+        /// of returning a spurious want-read error.
+        ///
+        /// Streams created by `SslStream::connect` and `SslStream::accept`
+        /// always have this mode set.
+        const SSL_MODE_AUTO_RETRY = ffi::SSL_MODE_AUTO_RETRY,
+    }
+}
+
+bitflags! {
+    /// Which sessions are cached by a context
+    flags SslSessionCacheMode: c_long {
+        /// Don't cache any sessions.
+        const SSL_SESS_CACHE_OFF = ffi::SSL_SESS_CACHE_OFF,
+        /// Cache sessions of client connections.
+        const SSL_SESS_CACHE_CLIENT = ffi::SSL_SESS_CACHE_CLIENT,
+        /// Cache sessions of server connections, which is the default.
+        const SSL_SESS_CACHE_SERVER = ffi::SSL_SESS_CACHE_SERVER,
+        /// Cache sessions of both client and server connections.
+        const SSL_SESS_CACHE_BOTH = ffi::SSL_SESS_CACHE_BOTH,
+        /// Don't periodically remove expired sessions from the cache.
+        const SSL_SESS_CACHE_NO_AUTO_CLEAR = ffi::SSL_SESS_CACHE_NO_AUTO_CLEAR,
+        /// Don't look up sessions in the internal cache.
+        const SSL_SESS_CACHE_NO_INTERNAL_LOOKUP = ffi::SSL_SESS_CACHE_NO_INTERNAL_LOOKUP,
+        /// Don't store sessions in the internal cache.
+        const SSL_SESS_CACHE_NO_INTERNAL_STORE = ffi::SSL_SESS_CACHE_NO_INTERNAL_STORE,
+        /// Don't use the internal cache at all.
+        const SSL_SESS_CACHE_NO_INTERNAL = ffi::SSL_SESS_CACHE_NO_INTERNAL,
+    }
+}
+
+bitflags! {
+    /// The state reported to an info callback
+    flags SslInfoState: i32 {
+        /// The connection is acting as a client.
+        const SSL_ST_CONNECT = ffi::SSL_ST_CONNECT,
+        /// The connection is acting as a server.
+        const SSL_ST_ACCEPT = ffi::SSL_ST_ACCEPT,
+        /// The handshake state machine has advanced.
+        const SSL_CB_LOOP = ffi::SSL_CB_LOOP,
+        /// A handshake function has returned, either successfully or with
+        /// an error.
+        const SSL_CB_EXIT = ffi::SSL_CB_EXIT,
+        /// Set alongside `SSL_CB_ALERT` for alerts which were received.
+        const SSL_CB_READ = ffi::SSL_CB_READ,
+        /// Set alongside `SSL_CB_ALERT` for alerts which were sent.
+        const SSL_CB_WRITE = ffi::SSL_CB_WRITE,
+        /// An alert has been sent or received. The alert is encoded in the
+        /// `ret` value.
+        const SSL_CB_ALERT = ffi::SSL_CB_ALERT,
+        /// A handshake has started.
+        const SSL_CB_HANDSHAKE_START = ffi::SSL_CB_HANDSHAKE_START,
+        /// A handshake has completed.
+        const SSL_CB_HANDSHAKE_DONE = ffi::SSL_CB_HANDSHAKE_DONE,
+    }
+}
+
+bitflags! {
+    /// The progress of the TLS shutdown of a connection
+    flags SslShutdownState: i32 {
+        /// A close_notify alert has been sent to the peer.
+        const SSL_SENT_SHUTDOWN = ffi::SSL_SENT_SHUTDOWN,
+        /// A close_notify alert has been received from the peer.
+        const SSL_RECEIVED_SHUTDOWN = ffi::SSL_RECEIVED_SHUTDOWN,
+    }
+}
+
 lazy_static! {
     static ref INDEXES: Mutex<HashMap<TypeId, c_int>> = Mutex::new(HashMap::new());
 }
@@ -201,6 +397,10 @@ lazy_static! {
     static ref ALPN_PROTOS_IDX: c_int = get_new_idx::<Vec<u8>>();
 }
 
+lazy_static! {
+    static ref PASSWORD_IDX: c_int = get_new_idx::<CString>();
+}
+
 /// Determine a new index to use for SSL CTX ex data.
 /// Registers a destruct for the data which will be called by openssl when the context is freed.
 fn get_new_idx<T>() -> c_int {
@@ -221,6 +421,44 @@ fn get_new_idx<T>() -> c_int {
     }
 }
 
+/// Determine a new index to use for SSL ex data.
+/// Registers a destructor for the data which will be called by openssl when the SSL is freed.
+fn get_ssl_new_idx<T>() -> c_int {
+    extern fn free_data_box<T>(_parent: *mut c_void, ptr: *mut c_void,
+                            _ad: *mut ffi::CRYPTO_EX_DATA, _idx: c_int,
+                            _argl: c_long, _argp: *mut c_void) {
+        if !ptr.is_null() {
+            let _: Box<T> = unsafe { mem::transmute(ptr) };
+        }
+    }
+
+    unsafe {
+        let f: ffi::CRYPTO_EX_free = free_data_box::<T>;
+        let idx = ffi::SSL_get_ex_new_index(0, ptr::null(), None,
+                                            None, Some(f));
+        assert!(idx >= 0);
+        idx
+    }
+}
+
+lazy_static! {
+    static ref HANDSHAKE_ERROR_IDX: c_int = get_ssl_new_idx::<SslError>();
+}
+
+/// Records `err` as the reason the handshake of `ssl` failed.
+///
+/// This is used by `X509StoreContext::set_handshake_error`, and isn't meant
+/// to be called directly.
+#[doc(hidden)]
+pub unsafe fn set_handshake_error_raw(ssl: *mut ffi::SSL, err: SslError) {
+    let old = ffi::SSL_get_ex_data(ssl, *HANDSHAKE_ERROR_IDX);
+    let err: Box<SslError> = Box::new(err);
+    ffi::SSL_set_ex_data(ssl, *HANDSHAKE_ERROR_IDX, mem::transmute(err));
+    if !old.is_null() {
+        let _: Box<SslError> = mem::transmute(old);
+    }
+}
+
 extern fn raw_verify(preverify_ok: c_int, x509_ctx: *mut ffi::X509_STORE_CTX)
         -> c_int {
     unsafe {
@@ -239,6 +477,23 @@ extern fn raw_verify(preverify_ok: c_int, x509_ctx: *mut ffi::X509_STORE_CTX)
     }
 }
 
+extern fn raw_ssl_verify(preverify_ok: c_int, x509_ctx: *mut ffi::X509_STORE_CTX)
+        -> c_int {
+    unsafe {
+        let idx = ffi::SSL_get_ex_data_X509_STORE_CTX_idx();
+        let ssl = ffi::X509_STORE_CTX_get_ex_data(x509_ctx, idx);
+        let verify = ffi::SSL_get_ex_data(ssl, SSL_VERIFY_IDX);
+        let verify: Option<VerifyCallback> = mem::transmute(verify);
+
+        let ctx = X509StoreContext::new(x509_ctx);
+
+        match verify {
+            None => preverify_ok,
+            Some(verify) => verify(preverify_ok != 0, &ctx) as c_int
+        }
+    }
+}
+
 extern fn raw_verify_with_data<T>(preverify_ok: c_int,
                                   x509_ctx: *mut ffi::X509_STORE_CTX) -> c_int
                                   where T: Any + 'static {
@@ -269,6 +524,127 @@ extern fn raw_verify_with_data<T>(preverify_ok: c_int,
     }
 }
 
+extern fn raw_password(buf: *mut c_char, size: c_int, _rwflag: c_int,
+                       user_data: *mut c_void) -> c_int {
+    unsafe {
+        let password = CStr::from_ptr(user_data as *const c_char).to_bytes();
+        if password.len() > size as usize {
+            return 0;
+        }
+        ptr::copy_nonoverlapping(password.as_ptr() as *const c_char, buf, password.len());
+        password.len() as c_int
+    }
+}
+
+/// Calls `f` with an `Ssl` wrapping a handle which is owned by OpenSSL, such as
+/// the one passed to a callback, without freeing the handle afterwards.
+fn with_borrowed_ssl<T, F>(ssl: *mut ffi::SSL, f: F) -> T where F: FnOnce(&Ssl) -> T {
+    let ssl = Ssl { ssl: ssl };
+    let ret = f(&ssl);
+    mem::forget(ssl);
+    ret
+}
+
+#[cfg(any(feature = "dtlsv1", feature = "dtlsv1_2"))]
+extern fn raw_cookie_generate<F>(ssl: *mut ffi::SSL, cookie: *mut c_uchar,
+                                 cookie_len: *mut c_uint) -> c_int
+                                 where F: Fn(&Ssl) -> Vec<u8> + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let generated = with_borrowed_ssl(ssl, |ssl| callback(ssl));
+        // OpenSSL only provides room for a cookie of DTLS1_COOKIE_LENGTH bytes
+        if generated.is_empty() || generated.len() > ffi::DTLS1_COOKIE_LENGTH as usize {
+            return 0;
+        }
+
+        ptr::copy_nonoverlapping(generated.as_ptr(), cookie, generated.len());
+        *cookie_len = generated.len() as c_uint;
+        1
+    }
+}
+
+#[cfg(any(feature = "dtlsv1", feature = "dtlsv1_2"))]
+extern fn raw_cookie_verify<F>(ssl: *mut ffi::SSL, cookie: *const c_uchar,
+                               cookie_len: c_uint) -> c_int
+                               where F: Fn(&Ssl, &[u8]) -> bool + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let cookie = slice::from_raw_parts(cookie, cookie_len as usize);
+        with_borrowed_ssl(ssl, |ssl| callback(ssl, cookie)) as c_int
+    }
+}
+
+extern fn raw_client_cert<F>(ssl: *mut ffi::SSL, x509: *mut *mut ffi::X509,
+                             pkey: *mut *mut ffi::EVP_PKEY) -> c_int
+                             where F: Fn(&Ssl) -> ClientCertLookup + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        match with_borrowed_ssl(ssl, |ssl| callback(ssl)) {
+            ClientCertLookup::Found(cert, key) => {
+                // OpenSSL takes ownership of both
+                *x509 = cert.get_handle();
+                *pkey = key.get_handle();
+                mem::forget(cert);
+                mem::forget(key);
+                1
+            }
+            ClientCertLookup::NotFound => 0,
+            ClientCertLookup::Pending => -1,
+        }
+    }
+}
+
+extern fn raw_ticket_key<F>(ssl: *mut ffi::SSL, key_name: *mut c_uchar, iv: *mut c_uchar,
+                            cipher_ctx: *mut ffi::EVP_CIPHER_CTX, hmac_ctx: *mut ffi::HMAC_CTX,
+                            enc: c_int) -> c_int
+                            where F: Fn(&Ssl, TicketKeyRequest) -> Option<TicketKey>
+                                     + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let key_name = &mut *(key_name as *mut [u8; 16]);
+        let request = if enc == 1 {
+            TicketKeyRequest::Encrypt
+        } else {
+            TicketKeyRequest::Decrypt(key_name)
+        };
+
+        // Returning 0 skips issuing a ticket, or falls back to a full
+        // handshake when decrypting
+        let key = match with_borrowed_ssl(ssl, |ssl| callback(ssl, request)) {
+            Some(key) => key,
+            None => return 0,
+        };
+
+        if enc == 1 {
+            *key_name = key.name;
+            if ffi::RAND_bytes(iv, 16) <= 0 {
+                return -1;
+            }
+        }
+
+        if ffi::HMAC_Init_ex(hmac_ctx, key.hmac_key.as_ptr(), key.hmac_key.len() as c_int,
+                             ffi::EVP_sha256(), ptr::null()) <= 0 ||
+           ffi::EVP_CipherInit(cipher_ctx, ffi::EVP_aes_128_cbc(), key.aes_key.as_ptr(), iv,
+                               enc) <= 0 {
+            return -1;
+        }
+
+        if enc == 0 && key.renew { 2 } else { 1 }
+    }
+}
+
 #[cfg(any(feature = "npn", feature = "alpn"))]
 unsafe fn select_proto_using(ssl: *mut ffi::SSL,
                       out: *mut *mut c_uchar, outlen: *mut c_uchar,
@@ -315,6 +691,49 @@ extern fn raw_alpn_select_cb(ssl: *mut ffi::SSL,
     }
 }
 
+#[cfg(feature = "alpn")]
+extern fn raw_alpn_select<F>(ssl: *mut ffi::SSL,
+                             out: *mut *mut c_uchar, outlen: *mut c_uchar,
+                             inbuf: *const c_uchar, inlen: c_uint,
+                             _arg: *mut c_void) -> c_int
+                             where F: Fn(&Ssl, &[&[u8]]) -> Option<Vec<u8>>
+                                      + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        // The client's list is a series of length-prefixed byte strings
+        let inbuf = slice::from_raw_parts(inbuf, inlen as usize);
+        let mut offered = vec![];
+        let mut rest = inbuf;
+        while !rest.is_empty() {
+            let len = rest[0] as usize;
+            if rest.len() < len + 1 {
+                return ffi::SSL_TLSEXT_ERR_ALERT_FATAL;
+            }
+            offered.push(&rest[1..len + 1]);
+            rest = &rest[len + 1..];
+        }
+
+        let selected = match with_borrowed_ssl(ssl, |ssl| callback(ssl, &offered)) {
+            Some(selected) => selected,
+            None => return ffi::SSL_TLSEXT_ERR_NOACK,
+        };
+
+        // The selected protocol has to point into memory which outlives the
+        // callback, so it is taken from the client's list
+        match offered.iter().find(|p| **p == &selected[..]) {
+            Some(p) => {
+                *out = p.as_ptr() as *mut c_uchar;
+                *outlen = p.len() as c_uchar;
+                ffi::SSL_TLSEXT_ERR_OK
+            }
+            None => ffi::SSL_TLSEXT_ERR_NOACK,
+        }
+    }
+}
+
 /// The function is given as the callback to `SSL_CTX_set_next_protos_advertised_cb`.
 ///
 /// It causes the parameter `out` to point at a `*const c_uchar` instance that
@@ -363,6 +782,121 @@ fn ssl_encode_byte_strings(strings: &[&[u8]]) -> Vec<u8>
     enc
 }
 
+fn file_exists<P: AsRef<Path>>(path: P) -> bool {
+    fs::metadata(path).map(|m| m.is_file()).unwrap_or(false)
+}
+
+fn dir_has_entries<P: AsRef<Path>>(path: P) -> bool {
+    fs::read_dir(path).map(|mut d| d.next().is_some()).unwrap_or(false)
+}
+
+/// Determines if OpenSSL's default certificate locations, or the environment
+/// variables overriding them, point at any certificates.
+fn default_verify_paths_exist() -> bool {
+    let file = match env::var_os("SSL_CERT_FILE") {
+        Some(file) => PathBuf::from(file),
+        None => unsafe {
+            PathBuf::from(String::from_utf8_lossy(
+                CStr::from_ptr(ffi::X509_get_default_cert_file()).to_bytes()).into_owned())
+        },
+    };
+    let dir = match env::var_os("SSL_CERT_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => unsafe {
+            PathBuf::from(String::from_utf8_lossy(
+                CStr::from_ptr(ffi::X509_get_default_cert_dir()).to_bytes()).into_owned())
+        },
+    };
+
+    file_exists(&file) || dir_has_entries(&dir)
+}
+
+#[cfg(unix)]
+fn path_to_cstring(path: &Path) -> Result<CString, SslError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        StreamError(io::Error::new(io::ErrorKind::InvalidInput,
+                                   "path contains a nul byte"))
+    })
+}
+
+#[cfg(not(unix))]
+fn path_to_cstring(path: &Path) -> Result<CString, SslError> {
+    match path.to_str().and_then(|p| CString::new(p).ok()) {
+        Some(path) => Ok(path),
+        None => Err(StreamError(io::Error::new(io::ErrorKind::InvalidInput,
+                                               "path is not valid UTF-8 or contains a nul byte"))),
+    }
+}
+
+/// The operation a session ticket key is requested for
+pub enum TicketKeyRequest<'a> {
+    /// A new ticket is being issued, and the key currently in use for new
+    /// tickets should be returned
+    Encrypt,
+    /// A ticket presented by a client is being decrypted, and the key with
+    /// the given name should be returned
+    Decrypt(&'a [u8; 16]),
+}
+
+/// The result of the callback set with
+/// `SslContext::set_client_cert_lookup_callback`
+pub enum ClientCertLookup {
+    /// The certificate to present and its private key
+    Found(X509, PKey),
+    /// Continue the handshake without a certificate
+    NotFound,
+    /// Suspend the handshake while the lookup completes. The callback is
+    /// invoked again when the handshake is resumed.
+    ///
+    /// Only `SslMemStream` can resume a suspended handshake. Blocking streams
+    /// have no way to learn when the lookup completes, so their operations
+    /// fail instead.
+    Pending,
+}
+
+/// The keys protecting a session ticket
+///
+/// Tickets are encrypted with AES-128-CBC and authenticated with
+/// HMAC-SHA256.
+pub struct TicketKey {
+    /// The name identifying the key, which is sent in the clear as part of
+    /// the ticket
+    pub name: [u8; 16],
+    /// The AES key
+    pub aes_key: [u8; 16],
+    /// The HMAC key
+    pub hmac_key: [u8; 16],
+    /// When decrypting, requests that a new ticket be issued to the client,
+    /// typically because the key is being rotated out
+    pub renew: bool,
+}
+
+/// The verification status of one certificate in a peer's chain, recorded by
+/// `SslContext::set_verify_report`
+pub struct VerifyReportEntry {
+    /// The certificate
+    pub cert: X509,
+    /// The error found with the certificate, or `None` if it passed
+    pub error: Option<X509ValidationError>,
+    /// The position of the certificate in the chain, where the peer's own
+    /// certificate has depth 0
+    pub depth: u32,
+}
+
+fn record_verify(preverify_ok: bool, x509_ctx: &X509StoreContext,
+                 report: &Arc<Mutex<Vec<VerifyReportEntry>>>) -> bool {
+    if let Some(cert) = x509_ctx.get_current_cert() {
+        report.lock().unwrap().push(VerifyReportEntry {
+            cert: cert.to_owned(),
+            error: x509_ctx.get_error(),
+            depth: x509_ctx.error_depth(),
+        });
+    }
+    preverify_ok
+}
+
 /// The signature of functions that can be used to manually verify certificates
 pub type VerifyCallback = fn(preverify_ok: bool,
                              x509_ctx: &X509StoreContext) -> bool;
@@ -423,16 +957,57 @@ impl SslContext {
         Ok(ctx)
     }
 
+    /// Creates a new SSL context configured with a conservative baseline,
+    /// along the lines of Mozilla's "modern" profile.
+    ///
+    /// Only TLSv1.2 and newer (DTLSv1.2 for DTLS methods) are allowed, cipher
+    /// suites are restricted to forward secret AEAD ones, and compression and
+    /// renegotiation are disabled. Verification tries trusted certificates
+    /// first when building chains. Peer verification itself is left to
+    /// `set_verify`, and any setting can still be adjusted afterwards.
+    ///
+    /// An error is returned if the OpenSSL version in use can't disable
+    /// renegotiation (see `disable_renegotiation`).
+    pub fn modern(method: SslMethod) -> Result<SslContext, SslError> {
+        let mut ctx = try!(SslContext::new(method));
+
+        let min = if method.is_dtls() { SslVersion::Dtlsv1_2 } else { SslVersion::Tlsv1_2 };
+        try!(ctx.set_protocol_version_range(Some(min), None));
+        try!(ctx.set_cipher_list("ECDHE+AESGCM:ECDHE+CHACHA20:DHE+AESGCM:DHE+CHACHA20:\
+                                  !aNULL:!eNULL:!MD5:!DSS"));
+        ctx.set_options(SSL_OP_NO_COMPRESSION);
+        if !ctx.disable_renegotiation() {
+            return Err(StreamError(io::Error::new(io::ErrorKind::Other,
+                                                  "renegotiation can't be disabled with this \
+                                                   version of OpenSSL")));
+        }
+        try!(ctx.set_verify_flags(X509_V_FLAG_TRUSTED_FIRST));
+
+        Ok(ctx)
+    }
+
+    /// Returns the underlying `SSL_CTX`, which is still owned and freed by
+    /// this context.
+    pub unsafe fn get_handle(&self) -> *mut ffi::SSL_CTX {
+        self.ctx
+    }
+
     /// Configures the certificate verification method for new connections.
+    ///
+    /// If no callback is provided, OpenSSL's built-in verification is used
+    /// directly without calling back into Rust for each certificate.
     pub fn set_verify(&mut self, mode: SslVerifyMode,
                       verify: Option<VerifyCallback>) {
         unsafe {
             ffi::SSL_CTX_set_ex_data(self.ctx, VERIFY_IDX,
                                      mem::transmute(verify));
-            let f: extern fn(c_int, *mut ffi::X509_STORE_CTX) -> c_int =
-                                raw_verify;
+            let f: Option<extern fn(c_int, *mut ffi::X509_STORE_CTX) -> c_int> =
+                match verify {
+                    Some(_) => Some(raw_verify),
+                    None => None,
+                };
 
-            ffi::SSL_CTX_set_verify(self.ctx, mode.bits as c_int, Some(f));
+            ffi::SSL_CTX_set_verify(self.ctx, mode.bits as c_int, f);
         }
     }
 
@@ -457,6 +1032,19 @@ impl SslContext {
         }
     }
 
+    /// Sets the verification mode, and records the status of each certificate
+    /// in the peer's chain in `report` as it is verified.
+    ///
+    /// Certificates are checked from the root down to the peer's own
+    /// certificate, and one is recorded again for each further error found
+    /// with it. The outcome of the verification is not changed. This takes
+    /// the place of any verify callback, and all connections using the
+    /// context record into the same report.
+    pub fn set_verify_report(&mut self, mode: SslVerifyMode,
+                             report: Arc<Mutex<Vec<VerifyReportEntry>>>) {
+        self.set_verify_with_data(mode, record_verify, report);
+    }
+
     /// Sets verification depth
     pub fn set_verify_depth(&mut self, depth: u32) {
         unsafe {
@@ -464,14 +1052,143 @@ impl SslContext {
         }
     }
 
+    /// Returns the certificate verification mode of new connections.
+    ///
+    /// Code handed a context by a caller can check this to make sure peers
+    /// are verified.
+    pub fn get_verify_mode(&self) -> SslVerifyMode {
+        let mode = unsafe { ffi::SSL_CTX_get_verify_mode(self.ctx) };
+        SslVerifyMode::from_bits_truncate(mode)
+    }
+
+    /// Verifies a certificate against the trusted certificates of this
+    /// context without establishing a connection.
+    ///
+    /// `chain` holds untrusted intermediate certificates which may be used to
+    /// build a path to a trusted root. The certificate is checked for use by a
+    /// TLS server. Returns `Ok(None)` if the certificate is trusted.
+    pub fn verify_cert(&self, cert: &X509Ref, chain: &[X509])
+                       -> Result<Option<X509ValidationError>, SslError> {
+        unsafe {
+            let stack = try_ssl_null!(ffi::sk_new_null());
+            for c in chain {
+                if ffi::sk_push(stack, c.get_handle() as *mut c_void) == 0 {
+                    ffi::sk_free(stack);
+                    return Err(SslError::get());
+                }
+            }
+
+            let ctx = ffi::X509_STORE_CTX_new();
+            if ctx.is_null() {
+                ffi::sk_free(stack);
+                return Err(SslError::get());
+            }
+
+            let store = ffi::SSL_CTX_get_cert_store(self.ctx);
+            let purpose = CString::new("ssl_server").unwrap();
+            let ret = if ffi::X509_STORE_CTX_init(ctx, store, cert.get_handle(), stack) == 0 ||
+                    ffi::X509_STORE_CTX_set_default(ctx, purpose.as_ptr()) == 0 {
+                Err(SslError::get())
+            } else {
+                ffi::X509_verify_cert(ctx);
+                Ok(X509StoreContext::new(ctx).get_error())
+            };
+
+            ffi::X509_STORE_CTX_free(ctx);
+            // the stack does not own the certificates in it
+            ffi::sk_free(stack);
+            ret
+        }
+    }
+
+    /// Sets the purpose peer certificates are checked against during
+    /// verification.
+    ///
+    /// The purpose is normally inferred from the role of the connection, but
+    /// it can be overridden to validate certificates issued for other uses,
+    /// such as S/MIME.
+    pub fn set_purpose(&mut self, purpose: X509Purpose) -> Result<(),SslError> {
+        wrap_ssl_result(
+            unsafe {
+                ffi::SSL_CTX_set_purpose(self.ctx, purpose as c_int)
+            })
+    }
+
+    /// Sets the security level of the context.
+    ///
+    /// Each level rejects cipher suites, keys and DH parameters weaker than a
+    /// minimum strength: level 1 requires 80 bits of security, level 2
+    /// requires 112 bits, and so on up to level 5. Level 0 permits anything.
+    ///
+    /// This method needs the `security_level` feature.
+    #[cfg(feature = "security_level")]
+    pub fn set_security_level(&mut self, level: u32) {
+        unsafe {
+            ffi::SSL_CTX_set_security_level(self.ctx, level as c_int);
+        }
+    }
+
+    /// Returns the security level of the context.
+    ///
+    /// This method needs the `security_level` feature.
+    #[cfg(feature = "security_level")]
+    pub fn get_security_level(&self) -> u32 {
+        unsafe { ffi::SSL_CTX_get_security_level(self.ctx) as u32 }
+    }
+
     pub fn set_read_ahead(&self, m: u32) {
         unsafe {
             ffi::SSL_CTX_set_read_ahead(self.ctx, m as c_long);
         }
     }
 
+    /// Sets the maximum amount of plaintext sent in a single TLS record.
+    ///
+    /// Smaller records can be decrypted by the peer as soon as they arrive,
+    /// which helps interactive traffic, while the default of 16384 bytes is
+    /// best for bulk transfers. The size must be between 512 and 16384.
+    pub fn set_max_send_fragment(&mut self, size: c_long) -> Result<(), SslError> {
+        wrap_ssl_result(unsafe {
+            ffi::SSL_CTX_set_max_send_fragment(self.ctx, size) as c_int
+        })
+    }
+
+    /// Sets whether connections skip the close_notify exchange when shut
+    /// down.
+    ///
+    /// This saves a round trip for protocols which delimit their own data,
+    /// but the peer can then no longer tell a complete stream from one that
+    /// was truncated by an attacker closing the underlying connection.
+    pub fn set_quiet_shutdown(&mut self, mode: bool) {
+        unsafe { ffi::SSL_CTX_set_quiet_shutdown(self.ctx, mode as c_int) }
+    }
+
+    /// Returns whether connections skip the close_notify exchange when shut
+    /// down.
+    pub fn quiet_shutdown(&self) -> bool {
+        unsafe { ffi::SSL_CTX_get_quiet_shutdown(self.ctx) != 0 }
+    }
+
+    /// Sets the oldest and newest protocol versions that connections may
+    /// negotiate, where `None` leaves that end of the range open.
+    ///
+    /// This is only meaningful for contexts created with a method supporting
+    /// several versions, such as `Sslv23`. OpenSSL versions before 1.1.0 are
+    /// limited to disabling the SSLv3 through TLSv1.2 protocols with the
+    /// `SSL_OP_NO_*` options, which this replaces.
+    pub fn set_protocol_version_range(&mut self, min: Option<SslVersion>,
+                                      max: Option<SslVersion>) -> Result<(),SslError> {
+        let min = min.map_or(0, |v| v.to_raw());
+        let max = max.map_or(0, |v| v.to_raw());
+        wrap_ssl_result(unsafe { ffi::SSL_CTX_set_proto_version_range(self.ctx, min, max) })
+    }
+
     #[allow(non_snake_case)]
     /// Specifies the file that contains trusted CA certificates.
+    ///
+    /// On failure the error holds every entry OpenSSL reported, so a file
+    /// which cannot be opened can be told apart from one containing no
+    /// certificates.
     pub fn set_CA_file<P: AsRef<Path>>(&mut self, file: P) -> Result<(),SslError> {
         let file = CString::new(file.as_ref().as_os_str().to_str().expect("invalid utf8")).unwrap();
         wrap_ssl_result(
@@ -480,18 +1197,196 @@ impl SslContext {
             })
     }
 
-    /// Specifies the file that contains certificate
-    pub fn set_certificate_file<P: AsRef<Path>>(&mut self, file: P, file_type: X509FileType)
-                                                -> Result<(),SslError> {
+    /// Sets the list of CA names sent to clients when requesting a client
+    /// certificate, taken from the subjects of the provided certificates.
+    ///
+    /// Clients use the list to select which of their certificates to present.
+    #[allow(non_snake_case)]
+    pub fn set_client_CA_list(&mut self, cas: &[X509]) -> Result<(),SslError> {
+        unsafe {
+            let names = try_ssl_null!(ffi::sk_new_null());
+            for ca in cas {
+                let name = ffi::X509_NAME_dup(ffi::X509_get_subject_name(ca.get_handle()));
+                if name.is_null() || ffi::sk_push(names, name) == 0 {
+                    if !name.is_null() {
+                        ffi::X509_NAME_free(name);
+                    }
+                    free_name_stack(names);
+                    return Err(SslError::get());
+                }
+            }
+            // The context takes ownership of the list
+            ffi::SSL_CTX_set_client_CA_list(self.ctx, names);
+        }
+        Ok(())
+    }
+
+    /// Sets the list of CA names sent to clients when requesting a client
+    /// certificate, taken from the subjects of the PEM certificates in a file.
+    #[allow(non_snake_case)]
+    pub fn set_client_CA_file<P: AsRef<Path>>(&mut self, file: P) -> Result<(),SslError> {
         let file = CString::new(file.as_ref().as_os_str().to_str().expect("invalid utf8")).unwrap();
+        unsafe {
+            let names = try_ssl_null!(ffi::SSL_load_client_CA_file(file.as_ptr()));
+            ffi::SSL_CTX_set_client_CA_list(self.ctx, names);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of CA names sent to clients when requesting a
+    /// client certificate.
+    #[allow(non_snake_case)]
+    pub fn client_CA_count(&self) -> usize {
+        unsafe {
+            let names = ffi::SSL_CTX_get_client_CA_list(self.ctx);
+            if names.is_null() { 0 } else { ffi::sk_num(names) as usize }
+        }
+    }
+
+    /// Configures the context to trust the CA certificates found in OpenSSL's
+    /// default locations.
+    ///
+    /// These locations are fixed when OpenSSL is built and may be overridden
+    /// with the `SSL_CERT_FILE` and `SSL_CERT_DIR` environment variables.
+    pub fn set_default_verify_paths(&mut self) -> Result<(),SslError> {
         wrap_ssl_result(
             unsafe {
-                ffi::SSL_CTX_use_certificate_file(self.ctx, file.as_ptr(), file_type as c_int)
+                ffi::SSL_CTX_set_default_verify_paths(self.ctx)
             })
     }
 
-    /// Specifies the certificate
-    pub fn set_certificate(&mut self, cert: &X509) -> Result<(),SslError> {
+    /// Like `set_default_verify_paths`, but falls back to well-known system
+    /// CA bundle locations if OpenSSL's default locations contain no
+    /// certificates, and to a bundled copy of the Mozilla root certificates
+    /// if none of those exist either.
+    ///
+    /// This is useful when OpenSSL was built for a different system layout
+    /// than the one it is running on, in which case its default locations
+    /// are usually missing or empty.
+    pub fn set_default_verify_paths_or_bundle(&mut self) -> Result<(),SslError> {
+        try!(self.set_default_verify_paths());
+        if default_verify_paths_exist() {
+            return Ok(());
+        }
+
+        let ffi::probe::ProbeResult { cert_file, cert_dir } = ffi::probe::probe();
+        // probe passes the environment variables through without checking them
+        let cert_file = cert_file.and_then(|p| if file_exists(&p) { Some(p) } else { None });
+        let cert_dir = cert_dir.and_then(|p| if dir_has_entries(&p) { Some(p) } else { None });
+        if cert_file.is_none() && cert_dir.is_none() {
+            return roots::load_bundle(self);
+        }
+
+        let cert_file = match cert_file {
+            Some(p) => Some(try!(path_to_cstring(&p))),
+            None => None,
+        };
+        let cert_dir = match cert_dir {
+            Some(p) => Some(try!(path_to_cstring(&p))),
+            None => None,
+        };
+        wrap_ssl_result(
+            unsafe {
+                ffi::SSL_CTX_load_verify_locations(self.ctx,
+                                                   cert_file.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+                                                   cert_dir.as_ref().map_or(ptr::null(), |p| p.as_ptr()))
+            })
+    }
+
+    /// Configures the context to trust the root certificates of the operating
+    /// system.
+    ///
+    /// On Windows the certificates are read from the system `ROOT` certificate
+    /// store and on OS X from the anchors of the system keychain. Elsewhere
+    /// this is equivalent to `set_default_verify_paths_or_bundle`.
+    pub fn load_os_roots(&mut self) -> Result<(),SslError> {
+        roots::load(self)
+    }
+
+    /// Adds a certificate to the store of trusted CA certificates.
+    #[allow(non_snake_case)]
+    pub fn add_CA_cert(&mut self, cert: &X509Ref) -> Result<(),SslError> {
+        self.cert_store_mut().add_cert(cert)
+    }
+
+    /// Sets the flags used when verifying peer certificates.
+    ///
+    /// Flags set previously remain set.
+    pub fn set_verify_flags(&mut self, flags: X509VerifyFlags) -> Result<(),SslError> {
+        self.cert_store_mut().set_flags(flags)
+    }
+
+    /// Verifies peer certificates as of `unix_time`, in seconds since the
+    /// epoch, rather than the current time.
+    ///
+    /// This allows checking whether a certificate was valid at some point in
+    /// the past, e.g. when auditing old connections.
+    pub fn set_verify_time(&mut self, unix_time: i64) {
+        self.cert_store_mut().set_time(unix_time)
+    }
+
+    /// Requires the peer's certificate to match `host`, which OpenSSL checks
+    /// as part of verifying the certificate chain.
+    ///
+    /// A mismatch fails verification with `X509HostnameMismatch`, which
+    /// aborts the handshake when `SSL_VERIFY_PEER` is set. It applies to all
+    /// connections using the context as well as to `verify_cert`, while
+    /// `Ssl::set_verified_hostname` sets a name for a single connection. An
+    /// empty `host` turns the check off again.
+    pub fn set_verify_hostname(&mut self, host: &str) -> Result<(),SslError> {
+        self.cert_store_mut().set_host(host)
+    }
+
+    /// Requires the peer's certificate to have a subject alternative name
+    /// matching `ip`, for connections made to an IP address rather than a
+    /// host name.
+    ///
+    /// Like `set_verify_hostname`, this is checked as part of verifying the
+    /// certificate chain, and a mismatch fails verification with
+    /// `X509IpAddressMismatch`.
+    pub fn set_verify_ip(&mut self, ip: net::IpAddr) -> Result<(),SslError> {
+        self.cert_store_mut().set_ip(ip)
+    }
+
+    /// Requires the peer's certificate to belong to the email address
+    /// `email`, as when verifying the signer of an S/MIME message.
+    ///
+    /// A mismatch fails verification with `X509EmailMismatch`. An empty
+    /// `email` turns the check off again.
+    pub fn set_verify_email(&mut self, email: &str) -> Result<(),SslError> {
+        self.cert_store_mut().set_email(email)
+    }
+
+    /// Returns the store of trusted certificates used to verify peers.
+    pub fn cert_store<'a>(&'a self) -> X509StoreRef<'a> {
+        unsafe { X509StoreRef::new(ffi::SSL_CTX_get_cert_store(self.ctx)) }
+    }
+
+    /// Returns a mutable reference to the store of trusted certificates used
+    /// to verify peers.
+    ///
+    /// Changes to the store affect every connection created from the context
+    /// afterwards.
+    pub fn cert_store_mut<'a>(&'a mut self) -> X509StoreRefMut<'a> {
+        unsafe { X509StoreRefMut::new(ffi::SSL_CTX_get_cert_store(self.ctx)) }
+    }
+
+    /// Specifies the file that contains certificate
+    pub fn set_certificate_file<P: AsRef<Path>>(&mut self, file: P, file_type: X509FileType)
+                                                -> Result<(),SslError> {
+        let file = CString::new(file.as_ref().as_os_str().to_str().expect("invalid utf8")).unwrap();
+        wrap_ssl_result(
+            unsafe {
+                ffi::SSL_CTX_use_certificate_file(self.ctx, file.as_ptr(), file_type as c_int)
+            })
+    }
+
+    /// Specifies the certificate
+    ///
+    /// This is the in-memory counterpart of `set_certificate_file`. Together
+    /// with `set_private_key` it allows a server identity to be configured
+    /// without touching the filesystem.
+    pub fn set_certificate(&mut self, cert: &X509Ref) -> Result<(),SslError> {
         wrap_ssl_result(
             unsafe {
                 ffi::SSL_CTX_use_certificate(self.ctx, cert.get_handle())
@@ -500,7 +1395,7 @@ impl SslContext {
 
     /// Adds a certificate to the certificate chain presented together with the
     /// certificate specified using set_certificate()
-    pub fn add_extra_chain_cert(&mut self, cert: &X509) -> Result<(),SslError> {
+    pub fn add_extra_chain_cert(&mut self, cert: &X509Ref) -> Result<(),SslError> {
         wrap_ssl_result(
             unsafe {
                 ffi::SSL_CTX_add_extra_chain_cert(self.ctx, cert.get_handle()) as c_int
@@ -517,6 +1412,24 @@ impl SslContext {
             })
     }
 
+    /// Sets the passphrase used to decrypt encrypted PEM private keys, such as
+    /// those loaded by `set_private_key_file`.
+    ///
+    /// This must be called before the key is loaded.
+    pub fn set_private_key_password(&mut self, password: &str) {
+        let password = Box::new(CString::new(password).unwrap());
+        unsafe {
+            let old = ffi::SSL_CTX_get_ex_data(self.ctx, *PASSWORD_IDX);
+            ffi::SSL_CTX_set_default_passwd_cb_userdata(self.ctx,
+                                                        password.as_ptr() as *mut c_void);
+            ffi::SSL_CTX_set_ex_data(self.ctx, *PASSWORD_IDX, mem::transmute(password));
+            if !old.is_null() {
+                let _: Box<CString> = mem::transmute(old);
+            }
+            ffi::SSL_CTX_set_default_passwd_cb(self.ctx, Some(raw_password));
+        }
+    }
+
     /// Specifies the private key
     pub fn set_private_key(&mut self, key: &PKey) -> Result<(),SslError> {
         wrap_ssl_result(
@@ -525,6 +1438,19 @@ impl SslContext {
             })
     }
 
+    /// Loads the private key identified by `key_id` from `engine`.
+    ///
+    /// This allows using keys which never leave a hardware module. The format
+    /// of `key_id` is specific to the engine.
+    ///
+    /// This method needs the `engine` feature.
+    #[cfg(feature = "engine")]
+    pub fn set_private_key_engine(&mut self, engine: &Engine,
+                                  key_id: &str) -> Result<(),SslError> {
+        let key = try!(engine.load_private_key(key_id));
+        self.set_private_key(&key)
+    }
+
     /// Check consistency of private key and certificate
     pub fn check_private_key(&mut self) -> Result<(),SslError> {
         wrap_ssl_result(
@@ -541,6 +1467,128 @@ impl SslContext {
             })
     }
 
+    /// Restricts the context to cipher suites using authenticated encryption,
+    /// namely AES-GCM and ChaCha20-Poly1305, preferring forward secret key
+    /// exchanges.
+    ///
+    /// CBC mode and stream ciphers are excluded, which avoids attacks such
+    /// as BEAST and Lucky 13. Peers which only support older ciphers can no
+    /// longer connect. With the `security_level` feature the security level
+    /// is also raised to at least 2.
+    pub fn require_aead(&mut self) -> Result<(),SslError> {
+        try!(self.set_cipher_list("ECDHE+AESGCM:ECDHE+CHACHA20:DHE+AESGCM:DHE+CHACHA20:\
+                                   RSA+AESGCM:!aNULL:!eNULL:!PSK:!SRP"));
+        #[cfg(feature = "security_level")]
+        {
+            if self.get_security_level() < 2 {
+                self.set_security_level(2);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the cipher suites used for TLSv1.3 connections.
+    ///
+    /// TLSv1.3 suites are configured separately from the ones of older
+    /// versions, so `set_cipher_list` has no effect on them. `suites` is a
+    /// colon separated list of names such as `TLS_AES_128_GCM_SHA256`.
+    ///
+    /// This method needs the `tlsv1_3` feature.
+    #[cfg(feature = "tlsv1_3")]
+    pub fn set_ciphersuites(&mut self, suites: &str) -> Result<(),SslError> {
+        wrap_ssl_result(
+            unsafe {
+                let suites = CString::new(suites).unwrap();
+                ffi::SSL_CTX_set_ciphersuites(self.ctx, suites.as_ptr())
+            })
+    }
+
+    /// Returns the names of the ciphers enabled by the cipher list, in order
+    /// of preference, joined by colons.
+    ///
+    /// The result reflects the list after OpenSSL has expanded aliases such
+    /// as `DEFAULT`, so it can be passed back to `set_cipher_list`.
+    pub fn get_cipher_list_string(&self) -> Result<String,SslError> {
+        // The expanded list is only exposed through connections
+        let ssl = try!(Ssl::new(self));
+        let mut names = vec![];
+        for i in 0.. {
+            let name = unsafe { ffi::SSL_get_cipher_list(ssl.ssl, i) };
+            if name.is_null() {
+                break;
+            }
+            let name = unsafe { CStr::from_ptr(name) };
+            names.push(String::from_utf8_lossy(name.to_bytes()).into_owned());
+        }
+        Ok(names.join(":"))
+    }
+
+    /// Returns the cipher suites enabled by the cipher list, in order of
+    /// preference.
+    ///
+    /// This reports what connections created from the context can negotiate
+    /// without opening any.
+    pub fn supported_ciphers<'a>(&'a self) -> Result<Vec<SslCipher<'a>>,SslError> {
+        let ssl = try!(Ssl::new(self));
+        unsafe {
+            let ciphers = ffi::SSL_get_ciphers(ssl.ssl);
+            if ciphers.is_null() {
+                return Ok(vec![]);
+            }
+            // The stack belongs to the connection, but the ciphers in it are
+            // static data of the library
+            Ok((0..ffi::sk_num(ciphers)).map(|i| {
+                SslCipher {
+                    cipher: ffi::sk_value(ciphers, i) as *const ffi::SSL_CIPHER,
+                    _marker: PhantomData,
+                }
+            }).collect())
+        }
+    }
+
+    /// Sets the elliptic curves supported for ECDHE key exchange, in order of
+    /// preference, as a colon separated list such as `"P-256:P-384"`.
+    ///
+    /// This method needs the `curves_list` feature.
+    #[cfg(feature = "curves_list")]
+    pub fn set_curves_list(&mut self, curves: &str) -> Result<(),SslError> {
+        wrap_ssl_result(
+            unsafe {
+                let curves = CString::new(curves).unwrap();
+                ffi::SSL_CTX_set1_curves_list(self.ctx, curves.as_ptr()) as c_int
+            })
+    }
+
+    /// Sets the signature algorithms accepted from the peer during the
+    /// handshake, in order of preference, as a colon separated list such as
+    /// `"ECDSA+SHA256:RSA+SHA256"`.
+    ///
+    /// This can forbid weak schemes such as those using SHA-1. The peer's
+    /// certificate must be signable with one of them, or the handshake fails.
+    ///
+    /// This method needs the `sigalgs_list` feature.
+    #[cfg(feature = "sigalgs_list")]
+    pub fn set_sigalgs_list(&mut self, sigalgs: &str) -> Result<(),SslError> {
+        wrap_ssl_result(
+            unsafe {
+                let sigalgs = CString::new(sigalgs).unwrap();
+                ffi::SSL_CTX_set1_sigalgs_list(self.ctx, sigalgs.as_ptr()) as c_int
+            })
+    }
+
+    /// Enables ECDHE key exchange using a curve picked automatically.
+    ///
+    /// On OpenSSL 1.0.2 and newer the curve is negotiated with each client.
+    /// Older versions use a single curve, matching the key of the context's
+    /// certificate if it is an EC key and P-256 otherwise, so the certificate
+    /// should be set before this is called.
+    pub fn set_ecdh_auto(&mut self, onoff: bool) -> Result<(),SslError> {
+        wrap_ssl_result(
+            unsafe {
+                ffi::SSL_CTX_set_ecdh_auto(self.ctx, onoff as c_int) as c_int
+            })
+    }
+
     pub fn set_options(&mut self, option: SslContextOptions) -> SslContextOptions {
         let raw_bits = option.bits();
         let ret = unsafe {
@@ -549,7 +1597,8 @@ impl SslContext {
         SslContextOptions::from_bits(ret).unwrap()
     }
 
-    pub fn get_options(&mut self) -> SslContextOptions {
+    /// Returns the options currently set on this context.
+    pub fn get_options(&self) -> SslContextOptions {
         let ret = unsafe {
             ffi::SSL_CTX_get_options(self.ctx)
         };
@@ -564,6 +1613,81 @@ impl SslContext {
         SslContextOptions::from_bits(ret).unwrap()
     }
 
+    /// Enables the given modes, returning the full set of modes now in effect.
+    ///
+    /// With `SSL_MODE_ENABLE_PARTIAL_WRITE` set, `write` on an `SslStream`
+    /// returns as soon as a single record has been written instead of
+    /// waiting for the whole buffer to be accepted.
+    pub fn set_mode(&mut self, mode: SslContextMode) -> SslContextMode {
+        let ret = unsafe {
+            ffi::SSL_CTX_set_mode(self.ctx, mode.bits())
+        };
+        SslContextMode::from_bits_truncate(ret)
+    }
+
+    /// Returns the modes currently enabled.
+    pub fn get_mode(&self) -> SslContextMode {
+        let ret = unsafe {
+            ffi::SSL_CTX_get_mode(self.ctx)
+        };
+        SslContextMode::from_bits_truncate(ret)
+    }
+
+    /// Sets which sessions are cached, returning the previous mode.
+    pub fn set_session_cache_mode(&mut self, mode: SslSessionCacheMode) -> SslSessionCacheMode {
+        let ret = unsafe {
+            ffi::SSL_CTX_ctrl(self.ctx, ffi::SSL_CTRL_SET_SESS_CACHE_MODE, mode.bits(),
+                              ptr::null_mut())
+        };
+        SslSessionCacheMode::from_bits_truncate(ret)
+    }
+
+    /// Returns which sessions are cached.
+    pub fn get_session_cache_mode(&self) -> SslSessionCacheMode {
+        let ret = unsafe {
+            ffi::SSL_CTX_ctrl(self.ctx, ffi::SSL_CTRL_GET_SESS_CACHE_MODE, 0, ptr::null_mut())
+        };
+        SslSessionCacheMode::from_bits_truncate(ret)
+    }
+
+    /// Turns off the session cache for a stateless server, which resumes
+    /// sessions using tickets only.
+    ///
+    /// Without the cache, resumption relies on the client returning a ticket
+    /// holding the encrypted session, so tickets are enabled by clearing
+    /// `SSL_OP_NO_TICKET`. Disabling tickets as well would leave no way to
+    /// resume sessions. Servers which don't want resumption at all should
+    /// set `SSL_OP_NO_TICKET` after calling this.
+    pub fn set_session_cache_off(&mut self) {
+        self.set_session_cache_mode(SSL_SESS_CACHE_OFF | SSL_SESS_CACHE_NO_INTERNAL);
+        self.clear_options(SSL_OP_NO_TICKET);
+    }
+
+    /// Sets the maximum number of bytes of early data a server accepts.
+    ///
+    /// The limit is included in the session tickets the server issues, and
+    /// clients may only send early data when resuming such a session. The
+    /// default of 0 disables early data.
+    ///
+    /// This method needs the `early_data` feature.
+    #[cfg(feature = "early_data")]
+    pub fn set_max_early_data(&mut self, max: u32) -> Result<(),SslError> {
+        wrap_ssl_result(unsafe { ffi::SSL_CTX_set_max_early_data(self.ctx, max) })
+    }
+
+    /// Refuses all renegotiation requests from peers.
+    ///
+    /// Client-initiated renegotiation lets a client force a server to repeat
+    /// expensive handshake operations, so servers rarely want to allow it.
+    ///
+    /// This sets the `SSL_OP_NO_RENEGOTIATION` option, which requires OpenSSL
+    /// 1.1.0h or newer. Older versions ignore it, in which case `false` is
+    /// returned and renegotiation is still allowed; `SslContext::modern`
+    /// fails instead.
+    pub fn disable_renegotiation(&mut self) -> bool {
+        self.set_options(SSL_OP_NO_RENEGOTIATION).contains(SSL_OP_NO_RENEGOTIATION)
+    }
+
     /// Set the protocols to be used during Next Protocol Negotiation (the protocols
     /// supported by the application).
     ///
@@ -617,6 +1741,322 @@ impl SslContext {
             ffi::SSL_CTX_set_alpn_select_cb(self.ctx, raw_alpn_select_cb, ptr::null_mut());
         }
     }
+
+    /// Configures the callback used by a server to select the protocol to
+    /// use during ALPN (application layer protocol negotiation).
+    ///
+    /// The callback is passed the protocols offered by the client in order of
+    /// the client's preference and returns the selected one, which must be
+    /// one of those offered. If `None` is returned, no protocol is
+    /// negotiated. This replaces the selection made by `set_alpn_protocols`.
+    ///
+    /// This method needs the `alpn` feature.
+    #[cfg(feature = "alpn")]
+    pub fn set_alpn_select_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, &[&[u8]]) -> Option<Vec<u8>> + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_alpn_select_cb(self.ctx, raw_alpn_select::<F>, ptr::null_mut());
+        }
+    }
+
+    /// Configures a callback which selects the certificate presented by a
+    /// client when the server requests one.
+    ///
+    /// The callback is only invoked if no certificate has been set on the
+    /// context. It can inspect the CA names accepted by the server through
+    /// `Ssl::client_CA_names`, and returns the certificate and its private
+    /// key, or `None` to continue without a certificate.
+    pub fn set_client_cert_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl) -> Option<(X509, PKey)> + Any + 'static + Sync + Send {
+        self.set_client_cert_lookup_callback(move |ssl| {
+            match callback(ssl) {
+                Some((cert, key)) => ClientCertLookup::Found(cert, key),
+                None => ClientCertLookup::NotFound,
+            }
+        })
+    }
+
+    /// Like `set_client_cert_callback`, but allows the callback to suspend
+    /// the handshake while a slow lookup, e.g. on a hardware token, completes.
+    ///
+    /// `SslMemStream` operations return `WantX509Lookup` while the lookup is
+    /// pending, and can be retried once it completes. `SslStream`s can't
+    /// wait for it, and fail with an error if the callback returns `Pending`.
+    pub fn set_client_cert_lookup_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl) -> ClientCertLookup + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_client_cert_cb(self.ctx, raw_client_cert::<F>);
+        }
+    }
+
+    /// Enables DANE (DNS-based Authentication of Named Entities) for
+    /// connections created from this context.
+    ///
+    /// DANE must additionally be enabled on each connection with
+    /// `Ssl::dane_enable`, which sets the name of the peer, and the peer's
+    /// TLSA records added with `Ssl::dane_tlsa_add`.
+    ///
+    /// This method needs the `dane` feature.
+    #[cfg(feature = "dane")]
+    pub fn dane_enable(&mut self) -> Result<(),SslError> {
+        wrap_ssl_result(unsafe { ffi::SSL_CTX_dane_enable(self.ctx) })
+    }
+
+    /// Configures a callback which is invoked as the state of connections
+    /// created from this context changes.
+    ///
+    /// The callback is passed the kind of event being reported and a value
+    /// whose meaning depends on it: for `SSL_CB_ALERT` it holds the alert
+    /// type and description, and for `SSL_CB_EXIT` the return value of the
+    /// handshake function. This is mainly useful for tracing handshakes.
+    pub fn set_info_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, SslInfoState, i32) + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_info_callback(self.ctx, raw_info_callback::<F>);
+        }
+    }
+
+    /// Configures a callback which is passed the secrets of connections
+    /// created from this context in the NSS key log format.
+    ///
+    /// Each line can be written to the file named by Wireshark's
+    /// `(Pre)-Master-Secret log filename` setting to decrypt captured traffic.
+    /// The lines must be kept secret, as they allow anyone to decrypt the
+    /// connection.
+    ///
+    /// This method needs the `keylog` feature.
+    #[cfg(feature = "keylog")]
+    pub fn set_keylog_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, &str) + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_keylog_callback(self.ctx, raw_keylog_callback::<F>);
+        }
+    }
+
+    /// Configures the callback used by a server to look up the keys
+    /// protecting session tickets.
+    ///
+    /// By default OpenSSL generates random ticket keys for each context, so
+    /// tickets cannot be shared between servers and keys are never rotated.
+    /// With a callback, servers can share a set of keys and retire old ones.
+    /// Returning `None` declines to issue a ticket, or rejects the presented
+    /// ticket and performs a full handshake.
+    pub fn set_ticket_key_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, TicketKeyRequest) -> Option<TicketKey>
+                     + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_tlsext_ticket_key_cb(self.ctx, raw_ticket_key::<F>);
+        }
+    }
+
+    /// Protects session tickets with a fixed key instead of a random one.
+    ///
+    /// The key is laid out as in OpenSSL's ticket key files: a 16 byte key
+    /// name, followed by the 16 byte HMAC key and the 16 byte AES key.
+    /// Contexts sharing the key accept each other's tickets, which makes
+    /// resumption deterministic in tests. Deployments should rotate keys
+    /// with `set_ticket_key_callback` instead.
+    pub fn set_static_ticket_key(&mut self, key: &[u8; 48]) {
+        let mut name = [0; 16];
+        let mut hmac_key = [0; 16];
+        let mut aes_key = [0; 16];
+        name.clone_from_slice(&key[..16]);
+        hmac_key.clone_from_slice(&key[16..32]);
+        aes_key.clone_from_slice(&key[32..]);
+
+        self.set_ticket_key_callback(move |_, request| {
+            if let TicketKeyRequest::Decrypt(requested) = request {
+                if *requested != name {
+                    return None;
+                }
+            }
+            Some(TicketKey {
+                name: name,
+                aes_key: aes_key,
+                hmac_key: hmac_key,
+                renew: false,
+            })
+        });
+    }
+
+    /// Configures the callbacks used by a DTLS server to generate and verify
+    /// the cookies exchanged through HelloVerifyRequest messages.
+    ///
+    /// A server only commits resources to a client once it has echoed back a
+    /// cookie accepted by `verify`, which prevents spoofed source addresses
+    /// from being used for amplification attacks. `generate` should derive the
+    /// cookie from the client's address and a server secret, and `verify`
+    /// should recompute and compare it. Cookies longer than
+    /// `DTLS1_COOKIE_LENGTH` bytes are rejected.
+    ///
+    /// This also enables the `SSL_OP_COOKIE_EXCHANGE` option.
+    ///
+    /// This method needs the `dtlsv1` or `dtlsv1_2` feature.
+    #[cfg(any(feature = "dtlsv1", feature = "dtlsv1_2"))]
+    pub fn set_cookie_callbacks<G, V>(&mut self, generate: G, verify: V)
+            where G: Fn(&Ssl) -> Vec<u8> + Any + 'static + Sync + Send,
+                  V: Fn(&Ssl, &[u8]) -> bool + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<G>(),
+                                     mem::transmute(Box::new(generate)));
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<V>(),
+                                     mem::transmute(Box::new(verify)));
+            ffi::SSL_CTX_set_cookie_generate_cb(self.ctx, raw_cookie_generate::<G>);
+            ffi::SSL_CTX_set_cookie_verify_cb(self.ctx, raw_cookie_verify::<V>);
+        }
+        self.set_options(SSL_OP_COOKIE_EXCHANGE);
+    }
+
+    /// Configures the callback used by a client to select a pre-shared key.
+    ///
+    /// The callback is passed the identity hint sent by the server, if any,
+    /// and returns the identity and key to use, or `None` to abort the
+    /// handshake. A PSK cipher suite must be enabled through
+    /// `set_cipher_list` for the key to be used.
+    ///
+    /// This method needs the `psk` feature.
+    #[cfg(feature = "psk")]
+    pub fn set_psk_client_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, Option<&[u8]>) -> Option<(Vec<u8>, Vec<u8>)>
+                     + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_psk_client_callback(self.ctx, raw_psk_client::<F>);
+        }
+    }
+
+    /// Configures the callback used by a server to look up the pre-shared key
+    /// for the identity presented by a client.
+    ///
+    /// Returning `None` rejects the identity and aborts the handshake.
+    ///
+    /// This method needs the `psk` feature.
+    #[cfg(feature = "psk")]
+    pub fn set_psk_server_callback<F>(&mut self, callback: F)
+            where F: Fn(&Ssl, &[u8]) -> Option<Vec<u8>> + Any + 'static + Sync + Send {
+        unsafe {
+            ffi::SSL_CTX_set_ex_data(self.ctx, get_verify_data_idx::<F>(),
+                                     mem::transmute(Box::new(callback)));
+            ffi::SSL_CTX_set_psk_server_callback(self.ctx, raw_psk_server::<F>);
+        }
+    }
+
+    /// Sets the identity hint sent by a server to clients using PSK.
+    ///
+    /// This method needs the `psk` feature.
+    #[cfg(feature = "psk")]
+    pub fn set_psk_identity_hint(&mut self, hint: &str) -> Result<(), SslError> {
+        let hint = CString::new(hint).unwrap();
+        wrap_ssl_result(unsafe { ffi::SSL_CTX_use_psk_identity_hint(self.ctx, hint.as_ptr()) })
+    }
+}
+
+#[cfg(feature = "psk")]
+extern fn raw_psk_client<F>(ssl: *mut ffi::SSL, hint: *const c_char, identity: *mut c_char,
+                            max_identity_len: c_uint, psk: *mut c_uchar,
+                            max_psk_len: c_uint) -> c_uint
+                            where F: Fn(&Ssl, Option<&[u8]>) -> Option<(Vec<u8>, Vec<u8>)>
+                                     + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let hint = if hint.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(hint).to_bytes())
+        };
+
+        let (id, key) = match with_borrowed_ssl(ssl, |ssl| callback(ssl, hint)) {
+            Some(r) => r,
+            None => return 0,
+        };
+        // the identity is written out as a nul-terminated string
+        if id.len() >= max_identity_len as usize || key.is_empty() ||
+                key.len() > max_psk_len as usize {
+            return 0;
+        }
+
+        ptr::copy_nonoverlapping(id.as_ptr(), identity as *mut u8, id.len());
+        *identity.offset(id.len() as isize) = 0;
+        ptr::copy_nonoverlapping(key.as_ptr(), psk, key.len());
+        key.len() as c_uint
+    }
+}
+
+#[cfg(feature = "psk")]
+extern fn raw_psk_server<F>(ssl: *mut ffi::SSL, identity: *const c_char, psk: *mut c_uchar,
+                            max_psk_len: c_uint) -> c_uint
+                            where F: Fn(&Ssl, &[u8]) -> Option<Vec<u8>>
+                                     + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let identity = if identity.is_null() {
+            &[][..]
+        } else {
+            CStr::from_ptr(identity).to_bytes()
+        };
+
+        let key = match with_borrowed_ssl(ssl, |ssl| callback(ssl, identity)) {
+            Some(key) => key,
+            None => return 0,
+        };
+        if key.is_empty() || key.len() > max_psk_len as usize {
+            return 0;
+        }
+
+        ptr::copy_nonoverlapping(key.as_ptr(), psk, key.len());
+        key.len() as c_uint
+    }
+}
+
+extern fn raw_info_callback<F>(ssl: *const ffi::SSL, where_: c_int, ret: c_int)
+                               where F: Fn(&Ssl, SslInfoState, i32) + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl = ssl as *mut ffi::SSL;
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let state = SslInfoState::from_bits_truncate(where_);
+        with_borrowed_ssl(ssl, |ssl| callback(ssl, state, ret));
+    }
+}
+
+#[cfg(feature = "keylog")]
+extern fn raw_keylog_callback<F>(ssl: *const ffi::SSL, line: *const c_char)
+                                 where F: Fn(&Ssl, &str) + Any + 'static + Sync + Send {
+    unsafe {
+        let ssl = ssl as *mut ffi::SSL;
+        let ssl_ctx = ffi::SSL_get_SSL_CTX(ssl);
+        let callback = ffi::SSL_CTX_get_ex_data(ssl_ctx, get_verify_data_idx::<F>());
+        let callback: &F = mem::transmute(callback);
+
+        let line = String::from_utf8_lossy(CStr::from_ptr(line).to_bytes());
+        with_borrowed_ssl(ssl, |ssl| callback(ssl, &line));
+    }
+}
+
+unsafe fn free_name_stack(names: *mut ffi::_STACK) {
+    for i in 0..ffi::sk_num(names) {
+        ffi::X509_NAME_free(ffi::sk_value(names, i));
+    }
+    ffi::sk_free(names);
 }
 
 #[allow(dead_code)]
@@ -685,6 +2125,20 @@ impl Ssl {
         }
     }
 
+    fn set_mode(&self, mode: SslContextMode) -> SslContextMode {
+        let mode = unsafe {
+            ffi::SSL_ctrl(self.ssl, ffi::SSL_CTRL_MODE, mode.bits(), ptr::null_mut())
+        };
+        SslContextMode::from_bits_truncate(mode)
+    }
+
+    fn clear_mode(&self, mode: SslContextMode) -> SslContextMode {
+        let mode = unsafe {
+            ffi::SSL_ctrl(self.ssl, ffi::SSL_CTRL_CLEAR_MODE, mode.bits(), ptr::null_mut())
+        };
+        SslContextMode::from_bits_truncate(mode)
+    }
+
     fn connect(&self) -> c_int {
         unsafe { ffi::SSL_connect(self.ssl) }
     }
@@ -693,9 +2147,55 @@ impl Ssl {
         unsafe { ffi::SSL_accept(self.ssl) }
     }
 
-    fn read(&self, buf: &mut [u8]) -> c_int {
+    #[cfg(feature = "early_data")]
+    fn write_early_data(&self, buf: &[u8], written: &mut size_t) -> c_int {
+        unsafe {
+            ffi::SSL_write_early_data(self.ssl, buf.as_ptr() as *const c_void,
+                                      buf.len() as size_t, written)
+        }
+    }
+
+    #[cfg(feature = "early_data")]
+    fn read_early_data(&self, buf: &mut [u8], read: &mut size_t) -> c_int {
+        unsafe {
+            ffi::SSL_read_early_data(self.ssl, buf.as_mut_ptr() as *mut c_void,
+                                     buf.len() as size_t, read)
+        }
+    }
+
+    /// Returns how much early data the session set with `set_session`
+    /// permits sending, which is 0 if there is no session.
+    #[cfg(feature = "early_data")]
+    fn max_early_data(&self) -> usize {
+        unsafe {
+            let session = ffi::SSL_get_session(self.ssl);
+            if session.is_null() {
+                0
+            } else {
+                ffi::SSL_SESSION_get_max_early_data(session) as usize
+            }
+        }
+    }
+
+    #[cfg(feature = "post_handshake_auth")]
+    fn do_handshake(&self) -> c_int {
+        unsafe { ffi::SSL_do_handshake(self.ssl) }
+    }
+
+    // Returns a positive value on success, with the number of bytes read
+    // stored in `read`. Unlike `SSL_read`, this isn't limited to `INT_MAX`
+    // bytes on OpenSSL 1.1.1 and newer, and a failure never looks like a
+    // successful read.
+    fn read(&self, buf: &mut [u8], read: &mut size_t) -> c_int {
+        unsafe {
+            ffi::SSL_read_ex(self.ssl, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t,
+                             read)
+        }
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> c_int {
         let len = cmp::min(c_int::max_value() as usize, buf.len()) as c_int;
-        unsafe { ffi::SSL_read(self.ssl, buf.as_ptr() as *mut c_void, len) }
+        unsafe { ffi::SSL_peek(self.ssl, buf.as_ptr() as *mut c_void, len) }
     }
 
     fn write(&self, buf: &[u8]) -> c_int {
@@ -724,13 +2224,82 @@ impl Ssl {
         }
     }
 
+    /// Sets the error returned from the handshake if it fails.
+    ///
+    /// Callbacks which reject a connection can use this to make their reason
+    /// observable to the caller of `SslStream::connect` or `accept`, instead
+    /// of the generic error reported by OpenSSL. The callback must still fail
+    /// the handshake by its return value.
+    pub fn set_handshake_error(&self, err: SslError) {
+        unsafe { set_handshake_error_raw(self.ssl, err) }
+    }
+
+    fn take_handshake_error(&self) -> Option<SslError> {
+        unsafe {
+            let err = ffi::SSL_get_ex_data(self.ssl, *HANDSHAKE_ERROR_IDX);
+            if err.is_null() {
+                return None;
+            }
+            ffi::SSL_set_ex_data(self.ssl, *HANDSHAKE_ERROR_IDX, ptr::null_mut());
+            let err: Box<SslError> = mem::transmute(err);
+            Some(*err)
+        }
+    }
+
+    /// Configures the certificate verification method for this connection,
+    /// overriding the one set on its context.
+    ///
+    /// This allows connections sharing a context to use different policies,
+    /// such as pinning a certificate for a single connection. If no callback
+    /// is provided, OpenSSL's built-in verification is used directly.
+    pub fn set_verify(&self, mode: SslVerifyMode, verify: Option<VerifyCallback>) {
+        unsafe {
+            ffi::SSL_set_ex_data(self.ssl, SSL_VERIFY_IDX, mem::transmute(verify));
+            let f: Option<extern fn(c_int, *mut ffi::X509_STORE_CTX) -> c_int> =
+                match verify {
+                    Some(_) => Some(raw_ssl_verify),
+                    None => None,
+                };
+
+            ffi::SSL_set_verify(self.ssl, mode.bits as c_int, f);
+        }
+    }
+
+    /// Sets the host name to be used with SNI, and additionally requires the
+    /// peer's certificate to be valid for it.
+    ///
+    /// OpenSSL checks the name while verifying the certificate chain against
+    /// the context's trusted certificates, as with
+    /// `SslContext::set_verify_hostname`, and a mismatch fails the handshake.
+    /// This enables `SSL_VERIFY_PEER` for the connection, but leaves any
+    /// verify callback in place.
+    pub fn set_verified_hostname(&self, hostname: &str) -> Result<(), SslError> {
+        try!(self.set_hostname(hostname));
+
+        unsafe {
+            // OpenSSL takes a length of 0 to mean a NUL-terminated string
+            let ptr = if hostname.is_empty() {
+                ptr::null()
+            } else {
+                hostname.as_ptr() as *const c_char
+            };
+            let param = ffi::SSL_get0_param(self.ssl);
+            try_ssl!(ffi::X509_VERIFY_PARAM_set1_host(param, ptr, hostname.len() as size_t));
+            // A null callback keeps the current one
+            let mode = ffi::SSL_get_verify_mode(self.ssl) | ffi::SSL_VERIFY_PEER;
+            ffi::SSL_set_verify(self.ssl, mode, None);
+        }
+
+        Ok(())
+    }
+
     pub fn get_peer_certificate(&self) -> Option<X509> {
         unsafe {
             let ptr = ffi::SSL_get_peer_certificate(self.ssl);
             if ptr.is_null() {
                 None
             } else {
-                Some(X509::new(ptr, true))
+                Some(X509::new(ptr))
             }
         }
     }
@@ -781,12 +2350,309 @@ impl Ssl {
         }
     }
 
+    /// Returns the outcome of ALPN on a client connection.
+    ///
+    /// Unlike `get_selected_alpn_protocol`, this tells a server which did not
+    /// take part in ALPN apart from one selecting a protocol the client never
+    /// offered. Protocols such as HTTP/2 require the connection to be aborted
+    /// in the latter case.
+    ///
+    /// This method needs the `alpn` feature.
+    #[cfg(feature = "alpn")]
+    pub fn get_alpn_status(&self) -> AlpnStatus {
+        let offered = unsafe {
+            let ssl_ctx = ffi::SSL_get_SSL_CTX(self.ssl);
+            let protocols = ffi::SSL_CTX_get_ex_data(ssl_ctx, *ALPN_PROTOS_IDX);
+            if protocols.is_null() {
+                return AlpnStatus::NotOffered;
+            }
+            let protocols: &Vec<u8> = mem::transmute(protocols);
+            &protocols[..]
+        };
+
+        let selected = match self.get_selected_alpn_protocol() {
+            Some(selected) => selected,
+            None => return AlpnStatus::NotAcknowledged,
+        };
+
+        // The offered list is a series of length-prefixed byte strings
+        let mut rest = offered;
+        while !rest.is_empty() {
+            let len = rest[0] as usize;
+            if &rest[1..len + 1] == selected {
+                return AlpnStatus::Selected(selected);
+            }
+            rest = &rest[len + 1..];
+        }
+        AlpnStatus::Unexpected(selected)
+    }
+
+    /// Returns the cipher negotiated for this connection, if the handshake
+    /// has completed.
+    pub fn get_current_cipher<'a>(&'a self) -> Option<SslCipher<'a>> {
+        let cipher = unsafe { ffi::SSL_get_current_cipher(self.ssl) };
+        if cipher.is_null() {
+            None
+        } else {
+            Some(SslCipher { cipher: cipher, _marker: PhantomData })
+        }
+    }
+
     /// pending() takes into account only bytes from the TLS/SSL record that is currently being processed (if any).
     pub fn pending(&self) -> usize {
         unsafe {
             ffi::SSL_pending(self.ssl) as usize
         }
     }
+
+    /// Returns the protocol version used by this connection, or `None` if it
+    /// is not one this crate knows of.
+    pub fn version(&self) -> Option<SslVersion> {
+        SslVersion::from_raw(unsafe { ffi::SSL_version(self.ssl) })
+    }
+
+    /// Returns which close_notify alerts have been sent and received on this connection.
+    pub fn get_shutdown(&self) -> SslShutdownState {
+        let state = unsafe { ffi::SSL_get_shutdown(self.ssl) };
+        SslShutdownState::from_bits_truncate(state)
+    }
+
+    /// wpending() returns the number of bytes of encrypted data buffered in the write BIO
+    /// which have not yet been written to the underlying stream.
+    pub fn wpending(&self) -> usize {
+        unsafe {
+            ffi::BIO_ctrl_pending(ffi::SSL_get_wbio(self.ssl)) as usize
+        }
+    }
+
+    /// Enables DANE verification of the peer for this connection.
+    ///
+    /// `host` is the TLSA base domain, which is also used as the reference
+    /// name when checking certificates validated against a trust anchor.
+    /// The context must have DANE enabled with `SslContext::dane_enable`, and
+    /// peer verification must be enabled for the result to be acted upon.
+    ///
+    /// This method needs the `dane` feature.
+    #[cfg(feature = "dane")]
+    pub fn dane_enable(&self, host: &str) -> Result<(), SslError> {
+        let host = CString::new(host).unwrap();
+        lift_ssl_if!(unsafe { ffi::SSL_dane_enable(self.ssl, host.as_ptr()) } <= 0)
+    }
+
+    /// Adds a TLSA record which the peer's certificate chain is verified
+    /// against.
+    ///
+    /// The arguments are the certificate usage, selector and matching type
+    /// fields of the record, and its certificate association data. Returns
+    /// `false` if the record is well formed but cannot be used, e.g. because
+    /// its matching type is not supported, in which case it is ignored.
+    ///
+    /// This method needs the `dane` feature.
+    #[cfg(feature = "dane")]
+    pub fn dane_tlsa_add(&self, usage: u8, selector: u8, mtype: u8,
+                         data: &[u8]) -> Result<bool, SslError> {
+        match unsafe {
+            ffi::SSL_dane_tlsa_add(self.ssl, usage, selector, mtype, data.as_ptr(),
+                                   data.len() as size_t)
+        } {
+            0 => Ok(false),
+            ret if ret > 0 => Ok(true),
+            _ => Err(SslError::get()),
+        }
+    }
+
+    /// Allows the server to request a certificate from this client after the
+    /// handshake has completed.
+    ///
+    /// This only has an effect on TLSv1.3 connections, and must be called
+    /// before the handshake.
+    ///
+    /// This method needs the `post_handshake_auth` feature.
+    #[cfg(feature = "post_handshake_auth")]
+    pub fn set_post_handshake_auth(&self, onoff: bool) {
+        unsafe { ffi::SSL_set_post_handshake_auth(self.ssl, onoff as c_int) }
+    }
+
+    /// Returns the DER encoded CA names sent by the server when requesting a
+    /// client certificate.
+    ///
+    /// These can be compared against the output of `X509Name::to_der` for the
+    /// issuers of the client's certificates.
+    #[allow(non_snake_case)]
+    pub fn client_CA_names(&self) -> Vec<Vec<u8>> {
+        unsafe {
+            let names = ffi::SSL_get_client_CA_list(self.ssl);
+            if names.is_null() {
+                return vec![];
+            }
+
+            (0..ffi::sk_num(names)).map(|i| {
+                let name = ffi::sk_value(names, i) as *mut ffi::X509_NAME;
+                let len = ffi::i2d_X509_NAME(name, ptr::null_mut());
+                if len < 0 {
+                    return vec![];
+                }
+                let mut buf = vec![0u8; len as usize];
+                let len = ffi::i2d_X509_NAME(name, &mut buf.as_mut_ptr());
+                buf.truncate(len as usize);
+                buf
+            }).collect()
+        }
+    }
+
+    /// Returns the session negotiated for this connection, if any.
+    pub fn get_session(&self) -> Option<SslSession> {
+        let session = unsafe { ffi::SSL_get1_session(self.ssl) };
+        if session.is_null() {
+            None
+        } else {
+            Some(SslSession { session: session })
+        }
+    }
+
+    /// Sets the session to attempt to resume when connecting.
+    ///
+    /// This must be called before the handshake.
+    pub fn set_session(&self, session: &SslSession) -> Result<(), SslError> {
+        wrap_ssl_result(unsafe { ffi::SSL_set_session(self.ssl, session.session) })
+    }
+}
+
+/// A cipher suite negotiated for a connection
+pub struct SslCipher<'a> {
+    cipher: *const ffi::SSL_CIPHER,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> fmt::Debug for SslCipher<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "SslCipher({})", self.name())
+    }
+}
+
+impl<'a> SslCipher<'a> {
+    /// Returns the OpenSSL name of the cipher suite, e.g. `AES128-SHA`.
+    pub fn name(&self) -> String {
+        unsafe {
+            let name = ffi::SSL_CIPHER_get_name(self.cipher);
+            String::from_utf8_lossy(CStr::from_ptr(name).to_bytes()).into_owned()
+        }
+    }
+
+    /// Returns the protocol version which introduced the cipher suite.
+    pub fn version(&self) -> String {
+        unsafe {
+            let version = ffi::SSL_CIPHER_get_version(self.cipher);
+            String::from_utf8_lossy(CStr::from_ptr(version).to_bytes()).into_owned()
+        }
+    }
+
+    /// Returns the number of secret bits used by the symmetric cipher.
+    pub fn bits(&self) -> i32 {
+        unsafe { ffi::SSL_CIPHER_get_bits(self.cipher, ptr::null_mut()) as i32 }
+    }
+
+    /// Returns OpenSSL's one line description of the cipher suite.
+    pub fn description(&self) -> String {
+        let mut buf = [0 as c_char; 128];
+        unsafe {
+            let desc = ffi::SSL_CIPHER_description(self.cipher, buf.as_mut_ptr(),
+                                                   buf.len() as c_int);
+            if desc.is_null() {
+                return String::new();
+            }
+            String::from_utf8_lossy(CStr::from_ptr(desc).to_bytes()).trim().to_owned()
+        }
+    }
+
+    /// Returns the key exchange algorithm, e.g. `ECDH` or `RSA`.
+    pub fn key_exchange(&self) -> Option<String> {
+        self.description_field("Kx=")
+    }
+
+    /// Returns the authentication algorithm, e.g. `RSA` or `ECDSA`.
+    pub fn authentication(&self) -> Option<String> {
+        self.description_field("Au=")
+    }
+
+    /// Returns the symmetric encryption algorithm, e.g. `AESGCM(128)`.
+    pub fn encryption(&self) -> Option<String> {
+        self.description_field("Enc=")
+    }
+
+    /// Returns the message authentication algorithm, e.g. `SHA1`, or `AEAD`
+    /// for ciphers which authenticate the data themselves.
+    pub fn mac(&self) -> Option<String> {
+        self.description_field("Mac=")
+    }
+
+    /// Determines if the cipher suite uses an AEAD cipher.
+    pub fn is_aead(&self) -> bool {
+        self.mac().map_or(false, |mac| mac == "AEAD")
+    }
+
+    fn description_field(&self, prefix: &str) -> Option<String> {
+        self.description()
+            .split_whitespace()
+            .find(|field| field.starts_with(prefix))
+            .map(|field| field[prefix.len()..].to_owned())
+    }
+}
+
+/// A TLS session, which can be used to resume a connection
+pub struct SslSession {
+    session: *mut ffi::SSL_SESSION
+}
+
+unsafe impl Send for SslSession {}
+unsafe impl Sync for SslSession {}
+
+impl fmt::Debug for SslSession {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "SslSession")
+    }
+}
+
+impl Drop for SslSession {
+    fn drop(&mut self) {
+        unsafe { ffi::SSL_SESSION_free(self.session) }
+    }
+}
+
+impl SslSession {
+    /// Deserializes a DER-encoded session, as produced by `to_der`
+    pub fn from_der(der: &[u8]) -> Result<SslSession, SslError> {
+        init();
+
+        unsafe {
+            let mut ptr = der.as_ptr();
+            let session = try_ssl_null!(ffi::d2i_SSL_SESSION(ptr::null_mut(), &mut ptr,
+                                                             der.len() as c_long));
+            Ok(SslSession { session: session })
+        }
+    }
+
+    /// Serializes the session to DER, allowing it to be stored outside of
+    /// the process and resumed later
+    pub fn to_der(&self) -> Result<Vec<u8>, SslError> {
+        unsafe {
+            let len = ffi::i2d_SSL_SESSION(self.session, ptr::null_mut());
+            try_ssl_if!(len <= 0);
+            let mut buf = vec![0u8; len as usize];
+            let len = ffi::i2d_SSL_SESSION(self.session, &mut buf.as_mut_ptr());
+            buf.truncate(len as usize);
+            Ok(buf)
+        }
+    }
+
+    /// Returns the maximum number of bytes of early data the server accepts
+    /// when resuming this session, or 0 if it doesn't accept early data.
+    ///
+    /// This method needs the `early_data` feature.
+    #[cfg(feature = "early_data")]
+    pub fn max_early_data(&self) -> u32 {
+        unsafe { ffi::SSL_SESSION_get_max_early_data(self.session) }
+    }
 }
 
 macro_rules! make_LibSslError {
@@ -825,6 +2691,8 @@ struct IndirectStream<S> {
     ssl: Arc<Ssl>,
     // Max TLS record size is 16k
     buf: Box<[u8; 16 * 1024]>,
+    // Ciphertext taken from the SSL which a non-blocking stream didn't accept
+    out: Vec<u8>,
 }
 
 impl<S: Clone> Clone for IndirectStream<S> {
@@ -832,7 +2700,8 @@ impl<S: Clone> Clone for IndirectStream<S> {
         IndirectStream {
             stream: self.stream.clone(),
             ssl: self.ssl.clone(),
-            buf: Box::new(*self.buf)
+            buf: Box::new(*self.buf),
+            out: self.out.clone(),
         }
     }
 }
@@ -842,7 +2711,8 @@ impl IndirectStream<net::TcpStream> {
         Ok(IndirectStream {
             stream: try!(self.stream.try_clone()),
             ssl: self.ssl.clone(),
-            buf: Box::new(*self.buf)
+            buf: Box::new(*self.buf),
+            out: self.out.clone(),
         })
     }
 }
@@ -860,6 +2730,7 @@ impl<S: Read+Write> IndirectStream<S> {
             stream: stream,
             ssl: Arc::new(ssl),
             buf: Box::new([0; 16 * 1024]),
+            out: vec![],
         })
     }
 
@@ -875,18 +2746,66 @@ impl<S: Read+Write> IndirectStream<S> {
         Ok(ssl)
     }
 
+    #[cfg(feature = "early_data")]
+    fn connect_with_early_data<T: IntoSsl>(ssl: T, stream: S, data: &[u8])
+                                           -> Result<(IndirectStream<S>, usize), SslError> {
+        let mut ssl = try!(IndirectStream::new_base(ssl, stream));
+        let mut written = 0;
+        // OpenSSL fails the whole write rather than sending a prefix if it
+        // exceeds the limit
+        let max = ssl.ssl.max_early_data();
+        let data = &data[..cmp::min(data.len(), max)];
+        if !data.is_empty() {
+            try!(ssl.in_retry_wrapper(|ssl| ssl.write_early_data(data, &mut written)));
+        }
+        try!(ssl.in_retry_wrapper(|ssl| ssl.connect()));
+        Ok((ssl, written as usize))
+    }
+
+    #[cfg(feature = "early_data")]
+    fn accept_with_early_data<T: IntoSsl>(ssl: T, stream: S)
+                                          -> Result<(IndirectStream<S>, Vec<u8>), SslError> {
+        let mut ssl = try!(IndirectStream::new_base(ssl, stream));
+        let mut data = vec![];
+        let mut buf = [0; 16 * 1024];
+        loop {
+            let mut read = 0;
+            let ret = try!(ssl.in_retry_wrapper(|ssl| ssl.read_early_data(&mut buf, &mut read)));
+            data.extend(buf[..read as usize].iter().cloned());
+            match ret {
+                ffi::SSL_READ_EARLY_DATA_SUCCESS => {}
+                ffi::SSL_READ_EARLY_DATA_FINISH => break,
+                _ => return Err(StreamError(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                                           "unexpected EOF during handshake"))),
+            }
+        }
+        try!(ssl.in_retry_wrapper(|ssl| ssl.accept()));
+        Ok((ssl, data))
+    }
+
     fn in_retry_wrapper<F>(&mut self, mut blk: F) -> Result<c_int, SslError>
             where F: FnMut(&Ssl) -> c_int {
+        // Stale errors would otherwise be attributed to this operation
+        error::clear_error_stack();
         loop {
             let ret = blk(&self.ssl);
             if ret > 0 {
+                // A read can process handshake messages from the peer, such as
+                // a renegotiation request, and queue replies. These have to be
+                // sent now, as the peer may not send anything else until it
+                // receives them. A non-blocking stream may take them later.
+                if self.ssl.wpending() > 0 || !self.out.is_empty() {
+                    try_ssl_stream!(ignore_would_block(self.write_through()));
+                }
                 return Ok(ret);
             }
 
             let e = self.ssl.get_error(ret);
             match e {
                 LibSslError::ErrorWantRead => {
-                    try_ssl_stream!(self.flush());
+                    // Waiting for the stream to accept our data would stall
+                    // a non-blocking read, and the data stays buffered anyway
+                    try_ssl_stream!(ignore_would_block(self.flush()));
                     let len = try_ssl_stream!(self.stream.read(&mut self.buf[..]));
                     if len == 0 {
                         self.ssl.get_rbio().set_eof(true);
@@ -895,23 +2814,78 @@ impl<S: Read+Write> IndirectStream<S> {
                     }
                 }
                 LibSslError::ErrorWantWrite => { try_ssl_stream!(self.flush()) }
+                LibSslError::ErrorWantX509Lookup => {
+                    return Err(StreamError(lookup_pending_error()));
+                }
                 LibSslError::ErrorZeroReturn => return Err(SslSessionClosed),
-                LibSslError::ErrorSsl => return Err(SslError::get()),
-                LibSslError::ErrorSyscall if ret == 0 => return Ok(0),
+                LibSslError::ErrorSsl => {
+                    let err = SslError::get();
+                    return Err(self.ssl.take_handshake_error().unwrap_or(err));
+                }
+                LibSslError::ErrorSyscall => {
+                    let err = SslError::get();
+                    let count = match err {
+                        SslError::OpenSslErrors(ref v) => v.len(),
+                        _ => unreachable!(),
+                    };
+                    // With an empty error queue the cause is only recorded in
+                    // errno, and a return of 0 is an unexpected EOF
+                    if count > 0 {
+                        return Err(err);
+                    } else if ret == 0 {
+                        return Ok(0);
+                    } else {
+                        return Err(StreamError(io::Error::last_os_error()));
+                    }
+                }
                 err => panic!("unexpected error {:?} with ret {}", err, ret),
             }
         }
     }
 
+    // Ciphertext the stream doesn't accept is kept in `out`, so a
+    // non-blocking stream returning `WouldBlock` doesn't lose any of it
     fn write_through(&mut self) -> io::Result<()> {
-        io::copy(&mut *self.ssl.get_wbio(), &mut self.stream).map(|_| ())
+        loop {
+            if self.out.is_empty() {
+                let len = try!(self.ssl.get_wbio().read(&mut self.buf[..]));
+                if len == 0 {
+                    return Ok(());
+                }
+                self.out.extend(self.buf[..len].iter().cloned());
+            }
+
+            match self.stream.write(&self.out) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                   "failed to write buffered ciphertext")),
+                Ok(len) => { self.out.drain(..len); }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<S: Read+Write> IndirectStream<S> {
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.in_retry_wrapper(|ssl| { ssl.peek(buf) }) {
+            Ok(len) => Ok(len as usize),
+            Err(SslSessionClosed) => Ok(0),
+            Err(StreamError(e)) => Err(e),
+            Err(e @ OpenSslErrors(_)) => {
+                Err(io::Error::new(io::ErrorKind::Other, e))
+            }
+        }
     }
 }
 
 impl<S: Read+Write> Read for IndirectStream<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.in_retry_wrapper(|ssl| { ssl.read(buf) }) {
-            Ok(len) => Ok(len as usize),
+        let mut read = 0;
+        match self.in_retry_wrapper(|ssl| { ssl.read(buf, &mut read) }) {
+            // The stream reached EOF
+            Ok(0) => Ok(0),
+            Ok(_) => Ok(read as usize),
             Err(SslSessionClosed) => Ok(0),
             Err(StreamError(e)) => Err(e),
             Err(e @ OpenSslErrors(_)) => {
@@ -924,19 +2898,57 @@ impl<S: Read+Write> Read for IndirectStream<S> {
 impl<S: Read+Write> Write for IndirectStream<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let count = match self.in_retry_wrapper(|ssl| ssl.write(buf)) {
+            Ok(0) | Err(SslSessionClosed) => return Err(closed_write_error()),
             Ok(len) => len as usize,
-            Err(SslSessionClosed) => 0,
             Err(StreamError(e)) => return Err(e),
             Err(e @ OpenSslErrors(_)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
         };
-        try!(self.write_through());
+        // The SSL has consumed the data, so it's reported as written even if
+        // a non-blocking stream takes the ciphertext later
+        try!(ignore_would_block(self.write_through()));
         Ok(count)
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        try!(self.write_through());
-        self.stream.flush()
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_through());
+        self.stream.flush()
+    }
+}
+
+/// The error returned when writing to a connection which has been closed.
+fn closed_write_error() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the TLS connection has been closed")
+}
+
+/// The error returned when an operation on a non-blocking stream has to wait
+/// for the socket.
+fn would_block_error() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "the operation would block")
+}
+
+/// The error returned when a blocking stream's handshake is suspended by a
+/// pending client certificate lookup.
+fn lookup_pending_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other,
+                   "the handshake is suspended by a pending client certificate lookup")
+}
+
+fn ignore_would_block(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        res => res,
+    }
+}
+
+unsafe fn nid_short_name(nid: c_int) -> Option<String> {
+    if nid == ffi::NID_undef {
+        return None;
+    }
+    let name = ffi::OBJ_nid2sn(nid);
+    if name.is_null() {
+        return None;
     }
+    Some(String::from_utf8_lossy(CStr::from_ptr(name).to_bytes()).into_owned())
 }
 
 #[derive(Clone)]
@@ -963,6 +2975,10 @@ impl<S> DirectStream<S> {
             }
             ffi::SSL_set_bio(ssl.ssl, bio, bio);
         }
+        // OpenSSL retries on its own when the socket is blocking. Otherwise
+        // writes which would block are retried by the caller, who may not
+        // pass the same buffer again
+        ssl.set_mode(SSL_MODE_AUTO_RETRY | SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER);
 
         Ok(DirectStream {
             stream: stream,
@@ -992,7 +3008,15 @@ impl<S> DirectStream<S> {
 
     fn make_error(&self, ret: c_int) -> SslError {
         match self.ssl.get_error(ret) {
-            LibSslError::ErrorSsl => SslError::get(),
+            LibSslError::ErrorZeroReturn => SslSessionClosed,
+            LibSslError::ErrorWantRead | LibSslError::ErrorWantWrite => {
+                SslError::StreamError(would_block_error())
+            }
+            LibSslError::ErrorWantX509Lookup => SslError::StreamError(lookup_pending_error()),
+            LibSslError::ErrorSsl => {
+                let err = SslError::get();
+                self.ssl.take_handshake_error().unwrap_or(err)
+            }
             LibSslError::ErrorSyscall => {
                 let err = SslError::get();
                 let count = match err {
@@ -1015,9 +3039,9 @@ impl<S> DirectStream<S> {
     }
 }
 
-impl<S> Read for DirectStream<S> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let ret = self.ssl.read(buf);
+impl<S> DirectStream<S> {
+    fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = self.ssl.peek(buf);
         if ret >= 0 {
             return Ok(ret as usize);
         }
@@ -1029,6 +3053,22 @@ impl<S> Read for DirectStream<S> {
     }
 }
 
+impl<S> Read for DirectStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        let ret = self.ssl.read(buf, &mut read);
+        if ret > 0 {
+            return Ok(read as usize);
+        }
+
+        match self.make_error(ret) {
+            SslSessionClosed => Ok(0),
+            SslError::StreamError(e) => Err(e),
+            e => Err(io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
 impl<S: Write> Write for DirectStream<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let ret = self.ssl.write(buf);
@@ -1037,6 +3077,8 @@ impl<S: Write> Write for DirectStream<S> {
         }
 
         match self.make_error(ret) {
+            SslSessionClosed => Err(closed_write_error()),
+            SslError::StreamError(_) if ret == 0 => Err(closed_write_error()),
             SslError::StreamError(e) => Err(e),
             e => Err(io::Error::new(io::ErrorKind::Other, e)),
         }
@@ -1077,9 +3119,18 @@ impl<S> StreamKind<S> {
 }
 
 /// A stream wrapper which handles SSL encryption for an underlying stream.
+///
+/// If the underlying stream is switched to non-blocking mode after the
+/// handshake, reads and writes which would block return an error of kind
+/// `WouldBlock` and can be retried once the socket is ready, so the stream
+/// can be driven from a poll loop. Ciphertext which the socket doesn't accept
+/// straight away is buffered, and `flush` returns `WouldBlock` until all of
+/// it has been written.
 #[derive(Clone)]
 pub struct SslStream<S> {
     kind: StreamKind<S>,
+    bytes_read: u64,
+    bytes_written: u64,
 }
 
 impl SslStream<net::TcpStream> {
@@ -1090,7 +3141,9 @@ impl SslStream<net::TcpStream> {
             StreamKind::Direct(ref s) => StreamKind::Direct(try!(s.try_clone()))
         };
         Ok(SslStream {
-            kind: kind
+            kind: kind,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
         })
     }
 }
@@ -1112,9 +3165,7 @@ impl<S: Read+Write+::std::os::unix::io::AsRawFd> SslStream<S> {
         let ssl = try!(ssl.into_ssl());
         let fd = stream.as_raw_fd() as c_int;
         let stream = try!(DirectStream::connect(ssl, stream, fd));
-        Ok(SslStream {
-            kind: StreamKind::Direct(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Direct(stream)))
     }
 
     /// Creates an SSL/TLS server operating over the provided stream.
@@ -1126,9 +3177,7 @@ impl<S: Read+Write+::std::os::unix::io::AsRawFd> SslStream<S> {
         let ssl = try!(ssl.into_ssl());
         let fd = stream.as_raw_fd() as c_int;
         let stream = try!(DirectStream::accept(ssl, stream, fd));
-        Ok(SslStream {
-            kind: StreamKind::Direct(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Direct(stream)))
     }
 }
 
@@ -1143,9 +3192,7 @@ impl<S: Read+Write+::std::os::windows::io::AsRawSocket> SslStream<S> {
         let ssl = try!(ssl.into_ssl());
         let fd = stream.as_raw_socket() as c_int;
         let stream = try!(DirectStream::connect(ssl, stream, fd));
-        Ok(SslStream {
-            kind: StreamKind::Direct(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Direct(stream)))
     }
 
     /// Creates an SSL/TLS server operating over the provided stream.
@@ -1157,9 +3204,17 @@ impl<S: Read+Write+::std::os::windows::io::AsRawSocket> SslStream<S> {
         let ssl = try!(ssl.into_ssl());
         let fd = stream.as_raw_socket() as c_int;
         let stream = try!(DirectStream::accept(ssl, stream, fd));
-        Ok(SslStream {
-            kind: StreamKind::Direct(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Direct(stream)))
+    }
+}
+
+impl<S> SslStream<S> {
+    fn new_base(kind: StreamKind<S>) -> SslStream<S> {
+        SslStream {
+            kind: kind,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
     }
 }
 
@@ -1171,9 +3226,7 @@ impl<S: Read+Write> SslStream<S> {
     /// that do not implement `AsRawFd` and `AsRawSocket`.
     pub fn connect_generic<T: IntoSsl>(ssl: T, stream: S) -> Result<SslStream<S>, SslError> {
         let stream = try!(IndirectStream::connect(ssl, stream));
-        Ok(SslStream {
-            kind: StreamKind::Indirect(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Indirect(stream)))
     }
 
     /// Creates an SSL/TLS server operating over the provided stream.
@@ -1183,9 +3236,51 @@ impl<S: Read+Write> SslStream<S> {
     /// that do not implement `AsRawFd` and `AsRawSocket`.
     pub fn accept_generic<T: IntoSsl>(ssl: T, stream: S) -> Result<SslStream<S>, SslError> {
         let stream = try!(IndirectStream::accept(ssl, stream));
-        Ok(SslStream {
-            kind: StreamKind::Indirect(stream)
-        })
+        Ok(SslStream::new_base(StreamKind::Indirect(stream)))
+    }
+
+    /// Creates a TLSv1.3 client which sends `data` as early data (0-RTT)
+    /// before completing the handshake, like `connect_generic`.
+    ///
+    /// Early data is only sent when resuming a session set with
+    /// `Ssl::set_session` which permits it, and at most
+    /// `SslSession::max_early_data` bytes are sent. The number of bytes sent
+    /// is returned along with the stream, and any remainder should be written
+    /// normally. The server may still reject the early data, which
+    /// `early_data_status` reports after the handshake, in which case all of
+    /// it has to be written again.
+    ///
+    /// Early data is not protected against replay by an attacker, so it
+    /// should only carry requests which are safe to repeat.
+    ///
+    /// This method needs the `early_data` feature.
+    #[cfg(feature = "early_data")]
+    pub fn connect_with_early_data<T: IntoSsl>(ssl: T, stream: S, data: &[u8])
+                                               -> Result<(SslStream<S>, usize), SslError> {
+        let (stream, written) = try!(IndirectStream::connect_with_early_data(ssl, stream, data));
+        let mut stream = SslStream::new_base(StreamKind::Indirect(stream));
+        stream.bytes_written = written as u64;
+        Ok((stream, written))
+    }
+
+    /// Creates a TLSv1.3 server which accepts early data (0-RTT), like
+    /// `accept_generic`.
+    ///
+    /// Any early data sent by the client is returned along with the stream,
+    /// and is empty if the client sent none or it was rejected. The context
+    /// must enable early data with `SslContext::set_max_early_data`.
+    ///
+    /// Early data is not protected against replay by an attacker, so it
+    /// should only carry requests which are safe to repeat.
+    ///
+    /// This method needs the `early_data` feature.
+    #[cfg(feature = "early_data")]
+    pub fn accept_with_early_data<T: IntoSsl>(ssl: T, stream: S)
+                                              -> Result<(SslStream<S>, Vec<u8>), SslError> {
+        let (stream, data) = try!(IndirectStream::accept_with_early_data(ssl, stream));
+        let mut stream = SslStream::new_base(StreamKind::Indirect(stream));
+        stream.bytes_read = data.len() as u64;
+        Ok((stream, data))
     }
 
     /// # Deprecated
@@ -1224,6 +3319,11 @@ impl<S: Read+Write> SslStream<S> {
         self.kind.ssl().get_peer_certificate()
     }
 
+    /// Returns the session negotiated for this connection, if any.
+    pub fn get_session(&self) -> Option<SslSession> {
+        self.kind.ssl().get_session()
+    }
+
     /// Returns a mutable reference to the underlying stream.
     ///
     /// ## Warning
@@ -1234,6 +3334,120 @@ impl<S: Read+Write> SslStream<S> {
         self.kind.mut_stream()
     }
 
+    /// Returns the cipher negotiated for this connection.
+    pub fn get_current_cipher<'a>(&'a self) -> Option<SslCipher<'a>> {
+        self.kind.ssl().get_current_cipher()
+    }
+
+    /// Returns the number of bytes of application data read through this
+    /// handle.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of bytes of application data written through this
+    /// handle.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the number of renegotiations performed on this connection,
+    /// whether requested locally or by the peer.
+    pub fn renegotiation_count(&self) -> u64 {
+        unsafe {
+            ffi::SSL_ctrl(self.kind.ssl().ssl, ffi::SSL_CTRL_GET_TOTAL_RENEGOTIATIONS, 0,
+                          ptr::null_mut()) as u64
+        }
+    }
+
+    /// Returns true if the connection resumed a previous session rather than
+    /// performing a full handshake.
+    pub fn session_reused(&self) -> bool {
+        unsafe {
+            ffi::SSL_ctrl(self.kind.ssl().ssl, ffi::SSL_CTRL_GET_SESSION_REUSED, 0,
+                          ptr::null_mut()) != 0
+        }
+    }
+
+    /// Returns the master secret of the connection's session, or an empty
+    /// vector if there is no session.
+    ///
+    /// Anyone holding the secret can decrypt the connection, so this is only
+    /// meant for debugging and testing, e.g. against known answer vectors.
+    /// In TLSv1.3 the secret is only used to resume the session.
+    pub fn master_key(&self) -> Vec<u8> {
+        unsafe {
+            let session = ffi::SSL_get_session(self.kind.ssl().ssl);
+            if session.is_null() {
+                return vec![];
+            }
+            let len = ffi::SSL_SESSION_get_master_key(session, ptr::null_mut(), 0);
+            let mut buf = vec![0; len as usize];
+            let len = ffi::SSL_SESSION_get_master_key(session, buf.as_mut_ptr(), len);
+            buf.truncate(len as usize);
+            buf
+        }
+    }
+
+    /// Returns the short name of the algorithm the peer signed the handshake
+    /// with, e.g. `RSASSA-PSS`.
+    ///
+    /// Returns `None` if the peer didn't sign the handshake or the OpenSSL
+    /// in use predates 1.1.1.
+    pub fn get_peer_signature_type(&self) -> Option<String> {
+        unsafe { nid_short_name(ffi::SSL_get_peer_signature_type_nid(self.kind.ssl().ssl)) }
+    }
+
+    /// Returns the short name of the group used for the key exchange, e.g.
+    /// `X25519`.
+    ///
+    /// Returns `None` if no group was used or the OpenSSL in use predates
+    /// 3.0.
+    pub fn get_negotiated_group(&self) -> Option<String> {
+        unsafe { nid_short_name(ffi::SSL_get_negotiated_group(self.kind.ssl().ssl)) }
+    }
+
+    /// Requests a certificate from the client on an established TLSv1.3
+    /// connection.
+    ///
+    /// The request is sent immediately, and the client's certificate is
+    /// processed by the next read. It is verified according to the mode set
+    /// with `SslContext::set_verify`, which must include `SSL_VERIFY_PEER`.
+    /// The client must have enabled this with `Ssl::set_post_handshake_auth`.
+    ///
+    /// This method needs the `post_handshake_auth` feature.
+    #[cfg(feature = "post_handshake_auth")]
+    pub fn request_client_auth(&mut self) -> Result<(), SslError> {
+        try_ssl!(unsafe { ffi::SSL_verify_client_post_handshake(self.kind.ssl().ssl) });
+
+        match self.kind {
+            StreamKind::Indirect(ref mut s) => {
+                try!(s.in_retry_wrapper(|ssl| ssl.do_handshake()));
+                Ok(())
+            }
+            StreamKind::Direct(ref mut s) => {
+                let ret = s.ssl.do_handshake();
+                if ret > 0 {
+                    Ok(())
+                } else {
+                    Err(s.make_error(ret))
+                }
+            }
+        }
+    }
+
+    /// Returns whether the early data sent by the client was accepted.
+    ///
+    /// This method needs the `early_data` feature.
+    #[cfg(feature = "early_data")]
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        match unsafe { ffi::SSL_get_early_data_status(self.kind.ssl().ssl) } {
+            ffi::SSL_EARLY_DATA_ACCEPTED => EarlyDataStatus::Accepted,
+            ffi::SSL_EARLY_DATA_REJECTED => EarlyDataStatus::Rejected,
+            _ => EarlyDataStatus::NotSent,
+        }
+    }
+
     /// Get the compression currently in use.  The result will be
     /// either None, indicating no compression is in use, or a string
     /// with the compression name.
@@ -1273,27 +3487,114 @@ impl<S: Read+Write> SslStream<S> {
         self.kind.ssl().get_selected_alpn_protocol()
     }
 
+    /// Returns the outcome of ALPN on a client connection.
+    ///
+    /// This method needs the `alpn` feature.
+    #[cfg(feature = "alpn")]
+    pub fn get_alpn_status(&self) -> AlpnStatus {
+        self.kind.ssl().get_alpn_status()
+    }
+
     /// pending() takes into account only bytes from the TLS/SSL record that is currently being processed (if any).
     pub fn pending(&self) -> usize {
         self.kind.ssl().pending()
     }
+
+    /// Writes all of the provided buffers to the stream.
+    ///
+    /// The buffers are coalesced before being handed to OpenSSL, so that many
+    /// small buffers are sent in as few TLS records as possible rather than a
+    /// record per buffer. A single record holds at most 16KB of data.
+    pub fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        let len = bufs.iter().fold(0, |len, buf| len + buf.len());
+        let mut coalesced = Vec::with_capacity(len);
+        for buf in bufs {
+            coalesced.extend(buf.iter().cloned());
+        }
+        self.write_all(&coalesced)
+    }
+
+    /// Writes as much of `buf` as fits in a single record, returning the
+    /// number of bytes written.
+    ///
+    /// This behaves like `write` with `SSL_MODE_ENABLE_PARTIAL_WRITE` set, but
+    /// only for this call.
+    pub fn write_partial(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Enabling no modes just returns the current ones
+        let was_set = self.kind.ssl().set_mode(SslContextMode::empty())
+                          .contains(SSL_MODE_ENABLE_PARTIAL_WRITE);
+        if !was_set {
+            self.kind.ssl().set_mode(SSL_MODE_ENABLE_PARTIAL_WRITE);
+        }
+        let res = self.write(buf);
+        if !was_set {
+            self.kind.ssl().clear_mode(SSL_MODE_ENABLE_PARTIAL_WRITE);
+        }
+        res
+    }
+
+    /// Returns the protocol version negotiated for the connection.
+    pub fn protocol_version(&self) -> Option<SslVersion> {
+        self.kind.ssl().version()
+    }
+
+    /// Returns which close_notify alerts have been sent and received.
+    ///
+    /// A connection which was closed cleanly by the peer has
+    /// `SSL_RECEIVED_SHUTDOWN` set, while one which ended abruptly does not.
+    pub fn get_shutdown_state(&self) -> SslShutdownState {
+        self.kind.ssl().get_shutdown()
+    }
+
+    /// wpending() returns the number of bytes of encrypted data queued for the underlying stream.
+    ///
+    /// Streams created with `connect` or `accept` write directly to the socket, so this is
+    /// always 0 for them.
+    pub fn wpending(&self) -> usize {
+        self.kind.ssl().wpending()
+    }
+
+    /// Reads data into `buf` without removing it from the stream, so that a
+    /// later `read` returns the same data.
+    pub fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.kind {
+            StreamKind::Indirect(ref mut s) => s.peek(buf),
+            StreamKind::Direct(ref mut s) => s.peek(buf),
+        }
+    }
+
+    /// Writes any buffered ciphertext to the underlying stream without
+    /// flushing the underlying stream itself.
+    ///
+    /// Streams created with `connect` or `accept` write directly to the
+    /// socket, so this is a no-op for them.
+    pub fn flush_ssl(&mut self) -> io::Result<()> {
+        match self.kind {
+            StreamKind::Indirect(ref mut s) => s.write_through(),
+            StreamKind::Direct(_) => Ok(()),
+        }
+    }
 }
 
 impl<S: Read+Write> Read for SslStream<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.kind {
+        let len = try!(match self.kind {
             StreamKind::Indirect(ref mut s) => s.read(buf),
             StreamKind::Direct(ref mut s) => s.read(buf),
-        }
+        });
+        self.bytes_read += len as u64;
+        Ok(len)
     }
 }
 
 impl<S: Read+Write> Write for SslStream<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.kind {
+        let len = try!(match self.kind {
             StreamKind::Indirect(ref mut s) => s.write(buf),
             StreamKind::Direct(ref mut s) => s.write(buf),
-        }
+        });
+        self.bytes_written += len as u64;
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -1304,6 +3605,327 @@ impl<S: Read+Write> Write for SslStream<S> {
     }
 }
 
+impl SslStream<net::TcpStream> {
+    /// Splits the stream into a reading half and a writing half, which can be
+    /// used concurrently, e.g. from different threads.
+    ///
+    /// OpenSSL does not allow one connection to be used from several threads
+    /// at once, so the halves share a lock which is held while data is
+    /// encrypted or decrypted, but not while waiting on the socket. The stream
+    /// must not have been cloned.
+    ///
+    /// Only the reader reads from the socket, so a write which needs data from
+    /// the peer, e.g. during a renegotiation, waits for the reader's next
+    /// `read`. Such a write fails once the reader has been dropped.
+    pub fn split(self) -> io::Result<(SslReader<net::TcpStream>, SslWriter<net::TcpStream>)> {
+        let (ssl, stream) = match self.kind {
+            StreamKind::Indirect(s) => (s.ssl, s.stream),
+            StreamKind::Direct(s) => {
+                // Socket BIOs would access the socket from inside OpenSSL, so
+                // they are swapped for memory BIOs which the halves pump.
+                // Anything already read from the socket is buffered by the
+                // SSL itself rather than the BIO, so nothing is lost.
+                let to_io = |e| io::Error::new(io::ErrorKind::Other, e);
+                let rbio = try!(MemBio::new().map_err(&to_io));
+                let wbio = try!(MemBio::new().map_err(&to_io));
+                unsafe { ffi::SSL_set_bio(s.ssl.ssl, rbio.unwrap(), wbio.unwrap()) }
+                (s.ssl, s.stream)
+            }
+        };
+
+        let writer = try!(stream.try_clone());
+        let shared = Arc::new(SplitShared {
+            ssl: ssl,
+            lock: Mutex::new(()),
+            fed: Condvar::new(),
+            reader_alive: AtomicBool::new(true),
+            stream: Mutex::new(writer),
+        });
+        let reader = SslReader {
+            shared: shared.clone(),
+            stream: stream,
+            buf: Box::new([0; 16 * 1024]),
+        };
+        Ok((reader, SslWriter { shared: shared }))
+    }
+}
+
+struct SplitShared<S> {
+    ssl: Arc<Ssl>,
+    // Held while the SSL is in use
+    lock: Mutex<()>,
+    // Signalled when the reader has passed data from the socket to the SSL,
+    // or has been dropped
+    fed: Condvar,
+    // Cleared with `lock` held when the reader is dropped
+    reader_alive: AtomicBool,
+    // The handle ciphertext is written to
+    stream: Mutex<S>,
+}
+
+impl<S: Write> SplitShared<S> {
+    // Must be called with `lock` held
+    fn write_through(&self) -> io::Result<()> {
+        if self.ssl.wpending() == 0 {
+            return Ok(());
+        }
+        let mut stream = self.stream.lock().unwrap();
+        try!(io::copy(&mut *self.ssl.get_wbio(), &mut *stream));
+        stream.flush()
+    }
+
+    fn error_result(&self, err: LibSslError, ret: c_int) -> io::Result<usize> {
+        match err {
+            LibSslError::ErrorZeroReturn => Ok(0),
+            LibSslError::ErrorSyscall => {
+                match SslError::get() {
+                    OpenSslErrors(ref errs) if errs.is_empty() => {
+                        if ret == 0 {
+                            Ok(0)
+                        } else {
+                            Err(io::Error::last_os_error())
+                        }
+                    }
+                    err => Err(io::Error::new(io::ErrorKind::Other, err)),
+                }
+            }
+            LibSslError::ErrorSsl => Err(io::Error::new(io::ErrorKind::Other, SslError::get())),
+            LibSslError::ErrorWantX509Lookup => Err(lookup_pending_error()),
+            err => panic!("unexpected error {:?} with ret {}", err, ret),
+        }
+    }
+}
+
+/// The reading half of an `SslStream`, created by `SslStream::split`.
+pub struct SslReader<S> {
+    shared: Arc<SplitShared<S>>,
+    stream: S,
+    buf: Box<[u8; 16 * 1024]>,
+}
+
+impl<S: Read+Write> Read for SslReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let shared = &*self.shared;
+        let mut guard = shared.lock.lock().unwrap();
+        loop {
+            let mut read = 0;
+            let ret = shared.ssl.read(buf, &mut read);
+            if ret > 0 {
+                try!(shared.write_through());
+                return Ok(read as usize);
+            }
+
+            match shared.ssl.get_error(ret) {
+                LibSslError::ErrorWantRead => {
+                    try!(shared.write_through());
+                    drop(guard);
+                    let len = try!(self.stream.read(&mut self.buf[..]));
+                    guard = shared.lock.lock().unwrap();
+                    if len == 0 {
+                        shared.ssl.get_rbio().set_eof(true);
+                    } else {
+                        try!(shared.ssl.get_rbio().write_all(&self.buf[..len]));
+                    }
+                    shared.fed.notify_all();
+                }
+                LibSslError::ErrorWantWrite => try!(shared.write_through()),
+                err => return shared.error_result(err, ret),
+            }
+        }
+    }
+}
+
+impl<S> Drop for SslReader<S> {
+    fn drop(&mut self) {
+        // The lock is wanted even if poisoned, so that a waiting writer
+        // can't miss the notification
+        let _guard = self.shared.lock.lock();
+        self.shared.reader_alive.store(false, Ordering::SeqCst);
+        self.shared.fed.notify_all();
+    }
+}
+
+/// The writing half of an `SslStream`, created by `SslStream::split`.
+pub struct SslWriter<S> {
+    shared: Arc<SplitShared<S>>,
+}
+
+impl<S: Write> Write for SslWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let shared = &*self.shared;
+        let mut guard = shared.lock.lock().unwrap();
+        loop {
+            let ret = shared.ssl.write(buf);
+            if ret > 0 {
+                try!(shared.write_through());
+                return Ok(ret as usize);
+            }
+
+            match shared.ssl.get_error(ret) {
+                LibSslError::ErrorWantRead => {
+                    // Only the reader reads from the socket, so wait for it
+                    // to pass on the data the SSL needs, e.g. during a
+                    // renegotiation
+                    try!(shared.write_through());
+                    if !shared.reader_alive.load(Ordering::SeqCst) {
+                        return Err(io::Error::new(io::ErrorKind::Other,
+                                                  "the write needs data from the peer, \
+                                                   but the SslReader has been dropped"));
+                    }
+                    guard = shared.fed.wait(guard).unwrap();
+                }
+                LibSslError::ErrorWantWrite => try!(shared.write_through()),
+                err => {
+                    return match shared.error_result(err, ret) {
+                        Ok(0) => Err(closed_write_error()),
+                        res => res,
+                    };
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _guard = self.shared.lock.lock().unwrap();
+        self.shared.write_through()
+    }
+}
+
+/// An SSL/TLS connection which isn't tied to any I/O type.
+///
+/// Encrypted data received from the peer is passed in with `feed`, and
+/// encrypted data for the peer is taken out with `drain`. Operations which
+/// can't make progress with the data fed so far return `WantRead` instead of
+/// blocking, so the connection can be driven from an event loop over any
+/// transport.
+///
+/// Anything queued by an operation, including by a failed one, should be
+/// drained and sent to the peer after each call.
+pub struct SslMemStream {
+    ssl: Ssl,
+}
+
+impl SslMemStream {
+    /// Creates a client connection. The handshake is started by the first
+    /// call to `handshake`, `read` or `write`.
+    pub fn connect<T: IntoSsl>(ssl: T) -> Result<SslMemStream, SslError> {
+        let stream = try!(SslMemStream::new_base(ssl));
+        unsafe { ffi::SSL_set_connect_state(stream.ssl.ssl) }
+        Ok(stream)
+    }
+
+    /// Creates a server connection. The handshake is started by the first
+    /// call to `handshake`, `read` or `write`.
+    pub fn accept<T: IntoSsl>(ssl: T) -> Result<SslMemStream, SslError> {
+        let stream = try!(SslMemStream::new_base(ssl));
+        unsafe { ffi::SSL_set_accept_state(stream.ssl.ssl) }
+        Ok(stream)
+    }
+
+    fn new_base<T: IntoSsl>(ssl: T) -> Result<SslMemStream, SslError> {
+        let ssl = try!(ssl.into_ssl());
+        let rbio = try!(MemBio::new());
+        let wbio = try!(MemBio::new());
+        // An empty read BIO means the peer hasn't sent more data yet rather
+        // than that it has closed the connection
+        rbio.set_eof(false);
+        unsafe { ffi::SSL_set_bio(ssl.ssl, rbio.unwrap(), wbio.unwrap()) }
+        Ok(SslMemStream { ssl: ssl })
+    }
+
+    /// Passes encrypted data received from the peer to the connection.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), SslError> {
+        self.ssl.get_rbio().write_all(data).map_err(StreamError)
+    }
+
+    /// Signals that the peer will not send any more data.
+    pub fn feed_eof(&mut self) {
+        self.ssl.get_rbio().set_eof(true);
+    }
+
+    /// Takes encrypted data queued for the peer, returning the number of
+    /// bytes copied into `buf`.
+    pub fn drain(&mut self, buf: &mut [u8]) -> Result<usize, SslError> {
+        self.ssl.get_wbio().read(buf).map_err(StreamError)
+    }
+
+    /// Returns the number of bytes of encrypted data waiting to be drained.
+    pub fn pending_output(&self) -> usize {
+        self.ssl.wpending()
+    }
+
+    /// Performs the handshake as far as the data fed so far allows.
+    pub fn handshake(&mut self) -> Result<(), NonblockingSslError> {
+        let ret = unsafe { ffi::SSL_do_handshake(self.ssl.ssl) };
+        if ret > 0 {
+            Ok(())
+        } else {
+            Err(self.make_error(ret))
+        }
+    }
+
+    /// Reads decrypted data, returning 0 once the peer has closed the
+    /// connection cleanly.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, NonblockingSslError> {
+        let mut read = 0;
+        let ret = self.ssl.read(buf, &mut read);
+        if ret > 0 {
+            return Ok(read as usize);
+        }
+
+        match self.make_error(ret) {
+            NonblockingSslError::SslError(SslSessionClosed) => Ok(0),
+            err => Err(err),
+        }
+    }
+
+    /// Encrypts data for the peer, returning the number of bytes consumed.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, NonblockingSslError> {
+        let ret = self.ssl.write(buf);
+        if ret > 0 {
+            Ok(ret as usize)
+        } else {
+            Err(self.make_error(ret))
+        }
+    }
+
+    /// Returns the `Ssl` driving the connection.
+    pub fn ssl(&self) -> &Ssl {
+        &self.ssl
+    }
+
+    fn make_error(&self, ret: c_int) -> NonblockingSslError {
+        match self.ssl.get_error(ret) {
+            LibSslError::ErrorWantRead => NonblockingSslError::WantRead,
+            LibSslError::ErrorWantWrite => NonblockingSslError::WantWrite,
+            LibSslError::ErrorWantX509Lookup => NonblockingSslError::WantX509Lookup,
+            LibSslError::ErrorZeroReturn => NonblockingSslError::SslError(SslSessionClosed),
+            LibSslError::ErrorSsl => {
+                let err = SslError::get();
+                NonblockingSslError::SslError(self.ssl.take_handshake_error().unwrap_or(err))
+            }
+            LibSslError::ErrorSyscall => {
+                let err = match SslError::get() {
+                    // The read BIO reached the EOF set by `feed_eof`
+                    OpenSslErrors(ref errs) if errs.is_empty() => {
+                        StreamError(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                                   "unexpected EOF observed"))
+                    }
+                    err => err,
+                };
+                NonblockingSslError::SslError(err)
+            }
+            err => panic!("unexpected error {:?} with ret {}", err, ret),
+        }
+    }
+}
+
+/// A type which can be converted into the `Ssl` driving a stream.
+///
+/// Both `&SslContext` and `Ssl` implement this trait. Passing an `Ssl`
+/// allows per-connection state such as the server name or session to be set
+/// up before the handshake, while the handshake role is chosen by calling
+/// either one of the `connect` or the `accept` constructors of `SslStream`.
 pub trait IntoSsl {
     fn into_ssl(self) -> Result<Ssl, SslError>;
 }
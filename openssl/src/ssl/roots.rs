@@ -0,0 +1,149 @@
+//! Loading of the root certificates trusted by the operating system.
+//!
+//! Windows and OS X do not keep their trusted roots in files OpenSSL knows
+//! how to read, so the certificates are pulled out of the native stores and
+//! added to the context's certificate store one by one. A copy of the
+//! Mozilla root list is bundled for systems where no CA bundle can be found.
+
+use ffi;
+use ssl::SslContext;
+use ssl::error::SslError;
+use x509::X509;
+
+/// The Mozilla root certificates, in PEM format.
+const BUNDLED_ROOTS: &'static [u8] = include_bytes!("roots.pem");
+
+/// Adds the bundled root certificates to the context.
+///
+/// Certificates already present in the store are skipped.
+pub fn load_bundle(ctx: &mut SslContext) -> Result<(), SslError> {
+    for cert in try!(X509::stack_from_pem(BUNDLED_ROOTS)) {
+        if ctx.add_CA_cert(&cert).is_err() {
+            unsafe { ffi::ERR_clear_error(); }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a DER-encoded root certificate to the context.
+///
+/// Certificates OpenSSL cannot parse, or which are already present in the
+/// store, are skipped.
+#[cfg(any(windows, target_os = "macos"))]
+fn add_der(ctx: &mut SslContext, der: &[u8]) {
+    let res = X509::from_der(der).and_then(|cert| ctx.add_CA_cert(&cert));
+    if res.is_err() {
+        unsafe { ffi::ERR_clear_error(); }
+    }
+}
+
+#[cfg(windows)]
+pub fn load(ctx: &mut SslContext) -> Result<(), SslError> {
+    use libc::{c_int, c_void};
+    use std::io;
+    use std::ptr;
+    use std::slice;
+    use ssl::error::StreamError;
+
+    const X509_ASN_ENCODING: u32 = 0x1;
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    struct CERT_CONTEXT {
+        dwCertEncodingType: u32,
+        pbCertEncoded: *const u8,
+        cbCertEncoded: u32,
+        pCertInfo: *mut c_void,
+        hCertStore: *mut c_void,
+    }
+
+    #[link(name = "crypt32")]
+    extern "system" {
+        fn CertOpenSystemStoreW(hProv: usize, szSubsystemProtocol: *const u16) -> *mut c_void;
+        fn CertEnumCertificatesInStore(hCertStore: *mut c_void,
+                                       pPrevCertContext: *const CERT_CONTEXT)
+                                       -> *const CERT_CONTEXT;
+        fn CertCloseStore(hCertStore: *mut c_void, dwFlags: u32) -> c_int;
+    }
+
+    let name = [b'R' as u16, b'O' as u16, b'O' as u16, b'T' as u16, 0];
+
+    unsafe {
+        let store = CertOpenSystemStoreW(0, name.as_ptr());
+        if store.is_null() {
+            return Err(StreamError(io::Error::last_os_error()));
+        }
+
+        let mut cert = CertEnumCertificatesInStore(store, ptr::null());
+        while !cert.is_null() {
+            if (*cert).dwCertEncodingType & X509_ASN_ENCODING != 0 {
+                let der = slice::from_raw_parts((*cert).pbCertEncoded,
+                                                (*cert).cbCertEncoded as usize);
+                add_der(ctx, der);
+            }
+            // Frees the previous context
+            cert = CertEnumCertificatesInStore(store, cert);
+        }
+
+        CertCloseStore(store, 0);
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn load(ctx: &mut SslContext) -> Result<(), SslError> {
+    use libc::c_void;
+    use std::io;
+    use std::ptr;
+    use std::slice;
+    use ssl::error::StreamError;
+
+    type CFIndex = isize;
+    type OSStatus = i32;
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern {
+        fn CFArrayGetCount(array: *const c_void) -> CFIndex;
+        fn CFArrayGetValueAtIndex(array: *const c_void, idx: CFIndex) -> *const c_void;
+        fn CFDataGetLength(data: *const c_void) -> CFIndex;
+        fn CFDataGetBytePtr(data: *const c_void) -> *const u8;
+        fn CFRelease(cf: *const c_void);
+    }
+
+    #[link(name = "Security", kind = "framework")]
+    extern {
+        fn SecTrustCopyAnchorCertificates(anchors: *mut *const c_void) -> OSStatus;
+        fn SecCertificateCopyData(certificate: *const c_void) -> *const c_void;
+    }
+
+    unsafe {
+        let mut anchors = ptr::null();
+        let status = SecTrustCopyAnchorCertificates(&mut anchors);
+        if status != 0 {
+            return Err(StreamError(io::Error::new(io::ErrorKind::Other,
+                                                  format!("SecTrustCopyAnchorCertificates \
+                                                           failed: {}", status))));
+        }
+
+        for i in 0..CFArrayGetCount(anchors) {
+            let data = SecCertificateCopyData(CFArrayGetValueAtIndex(anchors, i));
+            if data.is_null() {
+                continue;
+            }
+            let der = slice::from_raw_parts(CFDataGetBytePtr(data),
+                                            CFDataGetLength(data) as usize);
+            add_der(ctx, der);
+            CFRelease(data);
+        }
+
+        CFRelease(anchors);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn load(ctx: &mut SslContext) -> Result<(), SslError> {
+    ctx.set_default_verify_paths_or_bundle()
+}
@@ -7,16 +7,20 @@ use std::path::Path;
 use std::net::TcpListener;
 use std::thread;
 use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use crypto::hash::Type::{SHA256};
 use ssl;
 use ssl::SslMethod;
 use ssl::SslMethod::Sslv23;
-use ssl::{SslContext, SslStream, VerifyCallback};
+use ssl::{Ssl, SslContext, SslStream, VerifyCallback};
 use ssl::SSL_VERIFY_PEER;
+use ssl::error::{SslError, OpensslError};
 use x509::X509StoreContext;
 use x509::X509FileType;
-use x509::X509;
+use x509::{X509, X509Generator, X509ValidationError};
 use crypto::pkey::PKey;
 
 #[cfg(feature="dtlsv1")]
@@ -40,6 +44,14 @@ mod udp {
     }
 }
 
+/// Creates a context which presents the test certificate and key.
+fn ctx_with_cert(method: SslMethod) -> SslContext {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_certificate_file(&Path::new("test/cert.pem"), X509FileType::PEM).unwrap();
+    ctx.set_private_key_file(&Path::new("test/key.pem"), X509FileType::PEM).unwrap();
+    ctx
+}
+
 macro_rules! run_test(
     ($module:ident, $blk:expr) => (
         #[cfg(test)]
@@ -110,6 +122,83 @@ run_test!(verify_trusted, |method, stream| {
     }
 });
 
+run_test!(verify_per_ssl_callback_override, |method, stream| {
+    fn accept_all(_preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
+        true
+    }
+
+    // The context rejects the untrusted certificate, but this connection's
+    // own policy accepts it
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, None);
+    let ssl = ssl::Ssl::new(&ctx).unwrap();
+    ssl.set_verify(SSL_VERIFY_PEER, Some(accept_all as VerifyCallback));
+
+    match SslStream::connect_generic(ssl, stream) {
+        Ok(_) => (),
+        Err(err) => panic!("Expected success, got {:?}", err)
+    }
+});
+
+run_test!(verify_callback_handshake_error, |method, stream| {
+    fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
+        let err = io::Error::new(io::ErrorKind::PermissionDenied, "certificate not pinned");
+        x509_ctx.set_handshake_error(ssl::error::StreamError(err));
+        false
+    }
+
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+
+    match SslStream::connect_generic(&ctx, stream) {
+        Err(ssl::error::StreamError(ref err)) => {
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied)
+        }
+        res => panic!("Expected the callback's error, got {:?}", res.err())
+    }
+});
+
+run_test!(verify_hostname_trusted, |method, stream| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_CA_file(&Path::new("test/cert.pem")).unwrap();
+    let ssl = ssl::Ssl::new(&ctx).unwrap();
+    ssl.set_verified_hostname("test_cert").unwrap();
+
+    match SslStream::connect_generic(ssl, stream) {
+        Ok(_) => (),
+        Err(err) => panic!("Expected success, got {:?}", err)
+    }
+});
+
+run_test!(verify_hostname_keeps_callback, |method, stream| {
+    use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+    static CALLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    fn callback(preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
+        CALLED.store(true, Ordering::SeqCst);
+        preverify_ok
+    }
+
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_CA_file(&Path::new("test/cert.pem")).unwrap();
+    let ssl = ssl::Ssl::new(&ctx).unwrap();
+    ssl.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+    ssl.set_verified_hostname("test_cert").unwrap();
+
+    SslStream::connect_generic(ssl, stream).unwrap();
+    assert!(CALLED.load(Ordering::SeqCst));
+});
+
+run_test!(verify_hostname_mismatch, |method, stream| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_CA_file(&Path::new("test/cert.pem")).unwrap();
+    let ssl = ssl::Ssl::new(&ctx).unwrap();
+    ssl.set_verified_hostname("example.com").unwrap();
+
+    assert!(SslStream::connect_generic(ssl, stream).is_err());
+});
+
 run_test!(verify_untrusted_callback_override_ok, |method, stream| {
     fn callback(_preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
         true
@@ -180,6 +269,45 @@ run_test!(verify_callback_load_certs, |method, stream| {
     assert!(SslStream::connect_generic(&ctx, stream).is_ok());
 });
 
+run_test!(verify_callback_get_chain, |method, stream| {
+    fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
+        let chain = x509_ctx.get_chain().unwrap();
+        assert!(!chain.is_empty());
+        true
+    }
+
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+
+    assert!(SslStream::connect_generic(&ctx, stream).is_ok());
+});
+
+run_test!(verify_callback_current_cert_der, |method, stream| {
+    fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
+        let mut expected = vec![];
+        ::std::fs::File::open("test/cert.der").unwrap().read_to_end(&mut expected).unwrap();
+        assert_eq!(x509_ctx.current_cert_der().unwrap(), expected);
+        true
+    }
+
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+
+    assert!(SslStream::connect_generic(&ctx, stream).is_ok());
+});
+
+run_test!(verify_trusted_with_purpose, |method, stream| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, None);
+    ctx.set_purpose(::x509::X509Purpose::SslServer).unwrap();
+
+    match ctx.set_CA_file(&Path::new("test/cert.pem")) {
+        Ok(_) => {}
+        Err(err) => panic!("Unexpected error {:?}", err)
+    }
+    assert!(SslStream::connect_generic(&ctx, stream).is_ok());
+});
+
 run_test!(verify_trusted_get_error_ok, |method, stream| {
     fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
         assert!(x509_ctx.get_error().is_none());
@@ -208,6 +336,24 @@ run_test!(verify_trusted_get_error_err, |method, stream| {
     assert!(SslStream::connect_generic(&ctx, stream).is_err());
 });
 
+run_test!(verify_callback_set_error, |method, stream| {
+    fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
+        x509_ctx.set_error(Some(::x509::X509ValidationError::X509CertRejected));
+        match x509_ctx.get_error() {
+            Some(::x509::X509ValidationError::X509CertRejected) => {}
+            _ => panic!("Expected the overridden error"),
+        }
+        x509_ctx.set_error(None);
+        assert!(x509_ctx.get_error().is_none());
+        true
+    }
+
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+
+    assert!(SslStream::connect_generic(&ctx, stream).is_ok());
+});
+
 run_test!(verify_callback_data, |method, stream| {
     fn callback(_preverify_ok: bool, x509_ctx: &X509StoreContext, node_id: &Vec<u8>) -> bool {
         let cert = x509_ctx.get_current_cert();
@@ -246,125 +392,1345 @@ fn test_write_hits_stream() {
         let ctx = SslContext::new(Sslv23).unwrap();
         let stream = TcpStream::connect(addr).unwrap();
         let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
-
-        stream.write_all(b"hello").unwrap();
-        stream
+
+        stream.write_all(b"hello").unwrap();
+        stream
+    });
+
+    let mut ctx = ctx_with_cert(Sslv23);
+    ctx.set_verify(SSL_VERIFY_PEER, None);
+    let stream = listener.accept().unwrap().0;
+    let mut stream = SslStream::accept(&ctx, stream).unwrap();
+
+    let mut buf = [0; 5];
+    assert_eq!(5, stream.read(&mut buf).unwrap());
+    assert_eq!(&b"hello"[..], &buf[..]);
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_private_key_password() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_certificate_file(&Path::new("test/cert.pem"), X509FileType::PEM).unwrap();
+
+    ctx.set_private_key_password("wrong");
+    assert!(ctx.set_private_key_file(&Path::new("test/key.enc.pem"), X509FileType::PEM).is_err());
+
+    ctx.set_private_key_password("mypass");
+    ctx.set_private_key_file(&Path::new("test/key.enc.pem"), X509FileType::PEM).unwrap();
+    ctx.check_private_key().unwrap();
+}
+
+#[test]
+fn test_set_ca_file_error_detail() {
+    fn reasons(err: SslError) -> Vec<String> {
+        match err {
+            SslError::OpenSslErrors(errs) => {
+                errs.into_iter().map(|OpensslError::UnknownError { reason, .. }| reason).collect()
+            }
+            err => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    let missing = reasons(ctx.set_CA_file(&Path::new("test/missing.pem")).unwrap_err());
+    let no_certs = reasons(ctx.set_CA_file(&Path::new("test/key.pem")).unwrap_err());
+    assert!(!missing.is_empty());
+    assert!(!no_certs.is_empty());
+    assert!(missing != no_certs);
+}
+
+#[test]
+fn test_accept_preconfigured_ssl() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let ctx = SslContext::new(Sslv23).unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+        stream.write_all(b"hello").unwrap();
+    });
+
+    let ctx = ctx_with_cert(Sslv23);
+    let ssl = Ssl::new(&ctx).unwrap();
+    let stream = listener.accept().unwrap().0;
+    let mut stream = SslStream::accept_generic(ssl, stream).unwrap();
+
+    let mut buf = [0; 5];
+    assert_eq!(5, stream.read(&mut buf).unwrap());
+    assert_eq!(&b"hello"[..], &buf[..]);
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_split() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let ctx = SslContext::new(Sslv23).unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(4, stream.read(&mut buf).unwrap());
+        assert_eq!(&b"ping"[..], &buf[..]);
+        stream.write_all(b"pong").unwrap();
+    });
+
+    let ctx = ctx_with_cert(Sslv23);
+    let stream = listener.accept().unwrap().0;
+    let stream = SslStream::accept(&ctx, stream).unwrap();
+    let (mut reader, mut writer) = stream.split().unwrap();
+
+    // The reader blocks before anything is written
+    let read = thread::spawn(move || {
+        let mut buf = [0; 4];
+        assert_eq!(4, reader.read(&mut buf).unwrap());
+        assert_eq!(&b"pong"[..], &buf[..]);
+    });
+    writer.write_all(b"ping").unwrap();
+
+    read.join().unwrap();
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_verify_cert_untrusted() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+
+    let ctx = SslContext::new(Sslv23).unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509DepthZeroSelfSignedCert) => {}
+        _ => panic!("Expected the self-signed certificate to be rejected"),
+    }
+}
+
+#[test]
+fn test_cert_store() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    unsafe {
+        assert!(ctx.cert_store().get_handle() == ctx.cert_store_mut().get_handle());
+    }
+    ctx.cert_store_mut().set_default_paths().unwrap();
+    ctx.cert_store_mut().add_cert(&cert).unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+}
+
+#[test]
+fn test_get_verify_mode() {
+    use ssl::{SSL_VERIFY_NONE, SSL_VERIFY_FAIL_IF_NO_PEER_CERT};
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    assert_eq!(ctx.get_verify_mode(), SSL_VERIFY_NONE);
+    ctx.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT, None);
+    assert_eq!(ctx.get_verify_mode(), SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT);
+}
+
+#[test]
+fn test_modern() {
+    use ssl::{SslVersion, SSL_OP_NO_COMPRESSION, SSL_OP_NO_RENEGOTIATION};
+
+    let ctx = SslContext::modern(Sslv23).unwrap();
+    assert!(ctx.get_options().contains(SSL_OP_NO_COMPRESSION));
+    assert!(ctx.get_options().contains(SSL_OP_NO_RENEGOTIATION));
+
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    assert!(stream.protocol_version().unwrap() >= SslVersion::Tlsv1_2);
+    let cipher = stream.get_current_cipher().unwrap().name();
+    assert!(cipher.contains("GCM") || cipher.contains("CHACHA20"));
+}
+
+#[test]
+fn test_set_verify_flags() {
+    use x509::{X509_V_FLAG_CRL_CHECK, X509_V_FLAG_TRUSTED_FIRST};
+
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+    ctx.set_verify_flags(X509_V_FLAG_TRUSTED_FIRST).unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+
+    // The store holds no CRLs, so revocation status can't be determined
+    ctx.set_verify_flags(X509_V_FLAG_CRL_CHECK).unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509UnableToGetCrl) => {}
+        _ => panic!("Expected the missing CRL to be reported"),
+    }
+}
+
+#[test]
+fn test_set_verify_time() {
+    let mut cert_file = File::open(&Path::new("test/cert.pem")).unwrap();
+    let cert = X509::from_pem(&mut cert_file).unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509CertHasExpired) => {}
+        _ => panic!("Expected the certificate to have expired"),
+    }
+
+    // The certificate is valid from May 2015 to May 2016
+    ctx.set_verify_time(1450000000);
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+
+    ctx.set_verify_time(0);
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509CertNotYetValid) => {}
+        _ => panic!("Expected the certificate to not be valid yet"),
+    }
+}
+
+#[test]
+fn test_set_verify_hostname() {
+    use x509::extension::Extension::SubjectAltName;
+    use x509::extension::AltNameOption as SAN;
+
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "test_me".to_string())
+        .add_extension(SubjectAltName(vec![(SAN::DNS, "*.example.com".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+    ctx.set_verify_hostname("www.example.com").unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+
+    ctx.set_verify_hostname("example.com").unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509HostnameMismatch) => {}
+        _ => panic!("Expected the hostname to be rejected"),
+    }
+
+    // An empty host stops the check
+    ctx.set_verify_hostname("").unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+}
+
+#[test]
+fn test_set_verify_ip() {
+    use std::net::{IpAddr, Ipv4Addr};
+    use x509::extension::Extension::SubjectAltName;
+    use x509::extension::AltNameOption as SAN;
+
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "10.0.0.2".to_string())
+        .add_extension(SubjectAltName(vec![(SAN::IPAddress, "10.0.0.1".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+    ctx.set_verify_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))).unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+
+    // Addresses are never matched against the common name
+    ctx.set_verify_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))).unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509IpAddressMismatch) => {}
+        _ => panic!("Expected the address to be rejected"),
+    }
+}
+
+#[test]
+fn test_set_verify_email() {
+    use x509::extension::Extension::SubjectAltName;
+    use x509::extension::AltNameOption as SAN;
+
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "test_me".to_string())
+        .add_extension(SubjectAltName(vec![(SAN::Email, "me@example.com".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+    ctx.set_verify_email("me@example.com").unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+
+    ctx.set_verify_email("you@example.com").unwrap();
+    match ctx.verify_cert(&cert, &[]).unwrap() {
+        Some(X509ValidationError::X509EmailMismatch) => {}
+        _ => panic!("Expected the email address to be rejected"),
+    }
+
+    ctx.set_verify_email("").unwrap();
+    assert!(ctx.verify_cert(&cert, &[]).unwrap().is_none());
+}
+
+#[test]
+fn test_set_verify_report() {
+    use std::sync::Mutex;
+
+    let report = Arc::new(Mutex::new(vec![]));
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_verify_report(SSL_VERIFY_PEER, report.clone());
+    // The server's certificate is self-signed and not trusted
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    assert!(SslStream::connect_generic(&ctx, stream).is_err());
+    {
+        let report = report.lock().unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].depth, 0);
+        let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+        assert_eq!(report[0].cert.to_der(), cert.to_der());
+        match report[0].error {
+            Some(X509ValidationError::X509DepthZeroSelfSignedCert) => {}
+            _ => panic!("Expected the certificate to be reported as self-signed"),
+        }
+    }
+
+    report.lock().unwrap().clear();
+    ctx.set_CA_file(&Path::new("test/cert.pem")).unwrap();
+    // The certificate has expired
+    ctx.set_verify_time(1450000000);
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    SslStream::connect_generic(&ctx, stream).unwrap();
+    let report = report.lock().unwrap();
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].depth, 0);
+    assert!(report[0].error.is_none());
+}
+
+/// Tests that verification callbacks see the client's certificate when a
+/// server requests one.
+#[test]
+fn test_verify_client_cert() {
+    use std::sync::Mutex;
+    use std::sync::atomic::ATOMIC_BOOL_INIT;
+    use ssl::SSL_VERIFY_FAIL_IF_NO_PEER_CERT;
+
+    static PINNED_CALLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    fn client_cert_pinned(_preverify_ok: bool, x509_ctx: &X509StoreContext) -> bool {
+        PINNED_CALLED.store(true, Ordering::SeqCst);
+        let expected = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+        match x509_ctx.get_current_cert() {
+            Some(cert) => cert.to_der() == expected.to_der(),
+            None => false,
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let report = Arc::new(Mutex::new(vec![]));
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_CA_file(&Path::new("test/cert.pem")).unwrap();
+    listener_ctx.set_verify_time(1450000000);
+    listener_ctx.set_verify_report(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+                                   report.clone());
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        SslStream::accept(&listener_ctx, stream).unwrap();
+
+        // This connection's own callback takes over from the context's
+        let (stream, _) = listener.accept().unwrap();
+        let ssl = Ssl::new(&listener_ctx).unwrap();
+        ssl.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+                       Some(client_cert_pinned as VerifyCallback));
+        SslStream::accept(ssl, stream).unwrap();
+    });
+
+    let ctx = ctx_with_cert(Sslv23);
+    for _ in 0..2 {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = SslStream::connect(&ctx, stream).unwrap();
+        // Make sure the server has processed the certificate before moving on
+        let _ = stream.read(&mut [0]);
+    }
+    guard.join().unwrap();
+    assert!(PINNED_CALLED.load(Ordering::SeqCst));
+
+    // The context's callback only saw the first connection
+    let report = report.lock().unwrap();
+    assert_eq!(report.len(), 1);
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    assert_eq!(report[0].cert.to_der(), cert.to_der());
+    assert!(report[0].error.is_none());
+}
+
+#[test]
+fn test_set_client_ca_list() {
+    let mut cert_file = File::open(&Path::new("test/cert.pem")).unwrap();
+    let cert = X509::from_pem(&mut cert_file).unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    assert_eq!(ctx.client_CA_count(), 0);
+    ctx.set_client_CA_list(&[cert]).unwrap();
+    assert_eq!(ctx.client_CA_count(), 1);
+    ctx.set_client_CA_file(&Path::new("test/cert.pem")).unwrap();
+    assert_eq!(ctx.client_CA_count(), 1);
+}
+
+#[test]
+fn test_set_certificate_and_private_key() {
+    let key_path = Path::new("test/key.pem");
+    let cert_path = Path::new("test/cert.pem");
+    let mut key_file = File::open(&key_path)
+        .ok()
+        .expect("Failed to open `test/key.pem`");
+    let mut cert_file = File::open(&cert_path)
+        .ok()
+        .expect("Failed to open `test/cert.pem`");
+
+    let key = PKey::private_key_from_pem(&mut key_file).unwrap();
+    let cert = X509::from_pem(&mut cert_file).unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_private_key(&key).unwrap();
+    ctx.set_certificate(&cert).unwrap();
+
+    assert!(ctx.check_private_key().is_ok());
+}
+
+#[test]
+fn test_ec_server_identity() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_certificate_file(&Path::new("test/ec_cert.pem"), X509FileType::PEM).unwrap();
+    ctx.set_private_key_file(&Path::new("test/ec_key.pem"), X509FileType::PEM).unwrap();
+    assert!(ctx.check_private_key().is_ok());
+
+    // The RSA key doesn't match the certificate
+    let key = PKey::private_key_from_pem(&mut File::open("test/key.pem").unwrap()).unwrap();
+    assert!(ctx.set_private_key(&key).is_err() || ctx.check_private_key().is_err());
+}
+
+#[test]
+fn test_generated_ec_server_identity() {
+    use ffi;
+
+    let key = PKey::generate_ec(ffi::NID_X9_62_prime256v1).unwrap();
+    let cert = X509Generator::new()
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "localhost".to_string())
+        .sign(&key)
+        .unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_certificate(&cert).unwrap();
+    ctx.set_private_key(&key).unwrap();
+    assert!(ctx.check_private_key().is_ok());
+}
+
+#[test]
+fn test_in_memory_server_identity() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(), "localhost".to_string());
+    let (cert, key) = gen.generate().unwrap();
+
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let ctx = SslContext::new(Sslv23).unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+        stream.write_all(b"hello").unwrap();
+    });
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_certificate(&cert).unwrap();
+    ctx.set_private_key(&key).unwrap();
+    assert!(ctx.check_private_key().is_ok());
+    let stream = listener.accept().unwrap().0;
+    let mut stream = SslStream::accept(&ctx, stream).unwrap();
+
+    let mut buf = [0; 5];
+    assert_eq!(5, stream.read(&mut buf).unwrap());
+    assert_eq!(&b"hello"[..], &buf[..]);
+    guard.join().unwrap();
+}
+
+run_test!(set_default_verify_paths_or_bundle, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_default_verify_paths_or_bundle().unwrap();
+});
+
+#[test]
+fn test_bundled_roots() {
+    use nid::Nid;
+    use x509::X509;
+
+    let pem = include_bytes!("roots.pem");
+    let certs = X509::stack_from_pem(pem).unwrap();
+    let root = certs.iter()
+                    .find(|c| {
+                        c.subject_name().text_by_nid(Nid::CN)
+                         .map_or(false, |cn| &*cn == "ISRG Root X1")
+                    })
+                    .unwrap();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_verify_time(1700000000);
+    assert!(ctx.verify_cert(root, &[]).unwrap().is_some());
+
+    super::roots::load_bundle(&mut ctx).unwrap();
+    assert!(ctx.verify_cert(root, &[]).unwrap().is_none());
+}
+
+run_test!(load_os_roots, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.load_os_roots().unwrap();
+});
+
+run_test!(add_ca_cert, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    let mut file = ::std::fs::File::open("test/cert.pem").unwrap();
+    let cert = ::x509::X509::from_pem(&mut file).unwrap();
+    ctx.add_CA_cert(&cert).unwrap();
+});
+
+run_test!(session_der_round_trip, |method, stream| {
+    let ctx = SslContext::new(method).unwrap();
+    let stream = SslStream::connect_generic(&ctx, stream).unwrap();
+
+    let session = stream.get_session().unwrap();
+    let der = session.to_der().unwrap();
+    let session = ssl::SslSession::from_der(&der).unwrap();
+    assert_eq!(session.to_der().unwrap(), der);
+
+    let ssl = ssl::Ssl::new(&ctx).unwrap();
+    ssl.set_session(&session).unwrap();
+});
+
+run_test!(get_ctx_options, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.get_options();
+    ctx.set_options(ssl::SSL_OP_NO_SSLV3);
+    assert!(ctx.get_options().contains(ssl::SSL_OP_NO_SSLV3));
+});
+
+run_test!(set_ctx_options, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    let opts = ctx.set_options(ssl::SSL_OP_NO_TICKET);
+    assert!(opts.contains(ssl::SSL_OP_NO_TICKET));
+    assert!(!opts.contains(ssl::SSL_OP_CISCO_ANYCONNECT));
+    let more_opts = ctx.set_options(ssl::SSL_OP_CISCO_ANYCONNECT);
+    assert!(more_opts.contains(ssl::SSL_OP_NO_TICKET));
+    assert!(more_opts.contains(ssl::SSL_OP_CISCO_ANYCONNECT));
+});
+
+#[test]
+#[cfg(feature = "security_level")]
+fn test_security_level() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_security_level(2);
+    assert_eq!(ctx.get_security_level(), 2);
+
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    SslStream::connect_generic(&ctx, tcp).unwrap();
+}
+
+run_test!(disable_renegotiation, |method, stream| {
+    let mut ctx = SslContext::new(method).unwrap();
+    if ctx.disable_renegotiation() {
+        assert!(ctx.get_options().contains(ssl::SSL_OP_NO_RENEGOTIATION));
+    }
+    SslStream::connect_generic(&ctx, stream).unwrap();
+});
+
+#[test]
+#[cfg(feature = "curves_list")]
+fn test_set_curves_list() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_curves_list("P-256:P-384").unwrap();
+    assert!(ctx.set_curves_list("not-a-curve").is_err());
+}
+
+#[test]
+#[cfg(feature = "sigalgs_list")]
+fn test_set_sigalgs_list() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    assert!(ctx.set_sigalgs_list("not-a-sigalg").is_err());
+
+    ctx.set_sigalgs_list("RSA+SHA256:RSA-PSS+SHA256").unwrap();
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    SslStream::connect_generic(&ctx, stream).unwrap();
+
+    // The server's certificate has an RSA key
+    ctx.set_sigalgs_list("ECDSA+SHA256").unwrap();
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    assert!(SslStream::connect_generic(&ctx, stream).is_err());
+}
+
+#[test]
+fn test_get_cipher_list_string() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_cipher_list("AES128-SHA:AES256-SHA").unwrap();
+    // TLSv1.3 suites are configured separately and come first when supported
+    assert!(ctx.get_cipher_list_string().unwrap().ends_with("AES128-SHA:AES256-SHA"));
+}
+
+run_test!(clear_ctx_options, |method, _| {
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_options(ssl::SSL_OP_ALL);
+    let opts = ctx.clear_options(ssl::SSL_OP_ALL);
+    assert!(!opts.contains(ssl::SSL_OP_ALL));
+});
+
+#[test]
+fn test_max_send_fragment() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    assert!(ctx.set_max_send_fragment(256).is_err());
+    assert!(ctx.set_max_send_fragment(32768).is_err());
+    ctx.set_max_send_fragment(512).unwrap();
+
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    stream.write_all(&[b'a'; 2048]).unwrap();
+    stream.flush().unwrap();
+}
+
+#[test]
+fn test_quiet_shutdown() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    assert!(!ctx.quiet_shutdown());
+    ctx.set_quiet_shutdown(true);
+    assert!(ctx.quiet_shutdown());
+
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    stream.write_all(b"hello").unwrap();
+    stream.flush().unwrap();
+
+    ctx.set_quiet_shutdown(false);
+    assert!(!ctx.quiet_shutdown());
+}
+
+#[test]
+fn test_write() {
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
+    stream.write_all("hello".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    stream.write_all(" there".as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+#[test]
+#[cfg(feature = "tlsv1_3")]
+fn test_handshake_parameters() {
+    // The signature type needs OpenSSL 1.1.1, which tlsv1_3 implies
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
+    // The server's certificate has an RSA key
+    let sig = stream.get_peer_signature_type().unwrap();
+    assert!(sig == "RSASSA-PSS" || sig == "rsaEncryption", "{}", sig);
+    // OpenSSL only reports the group from 3.0 on
+    if let Some(group) = stream.get_negotiated_group() {
+        assert!(!group.is_empty());
+    }
+}
+
+#[test]
+fn test_write_vectored() {
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
+    stream.write_all_vectored(&[b"hello", b" ", b"there"]).unwrap();
+    stream.flush().unwrap();
+}
+
+#[test]
+fn test_connection_stats() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let listener_ctx = ctx_with_cert(Sslv23);
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = SslStream::accept(&listener_ctx, stream).unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(b"hi").unwrap();
+        assert_eq!(stream.bytes_read(), 5);
+        assert_eq!(stream.bytes_written(), 2);
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(),
+                                                stream).unwrap();
+    assert_eq!(stream.bytes_written(), 0);
+    stream.write_all(b"hello").unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(stream.bytes_written(), 5);
+    assert_eq!(stream.bytes_read(), 2);
+    assert_eq!(stream.renegotiation_count(), 0);
+
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_read_connection_reset() {
+    use std::sync::mpsc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let listener_ctx = ctx_with_cert(Sslv23);
+    let (tx, rx) = mpsc::channel();
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let stream = SslStream::accept(&listener_ctx, stream).unwrap();
+        // Closing a socket with unread data makes the kernel reset the
+        // connection instead of shutting it down cleanly
+        rx.recv().unwrap();
+        drop(stream);
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
+    stream.write_all(b"unread").unwrap();
+    tx.send(()).unwrap();
+    guard.join().unwrap();
+
+    let err = stream.read(&mut [0; 1]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
+
+#[test]
+fn test_peer_renegotiation() {
+    use ffi;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let listener_ctx = ctx_with_cert(Sslv23);
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = SslStream::accept_generic(&listener_ctx, stream).unwrap();
+        // The HelloRequest goes out ahead of the data, and the client's
+        // handshake messages arrive while reading
+        unsafe { assert_eq!(1, ffi::SSL_renegotiate(stream.kind.ssl().ssl)); }
+        stream.write_all(b"a").unwrap();
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(b"b", &buf);
+        stream.write_all(b"c").unwrap();
+    });
+
+    // TLSv1.3 has no renegotiation
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_protocol_version_range(None, Some(ssl::SslVersion::Tlsv1_2)).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(b"a", &buf);
+    stream.write_all(b"b").unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(b"c", &buf);
+    assert_eq!(stream.renegotiation_count(), 1);
+
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_ecdh_auto() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_ecdh_auto(true).unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        SslStream::accept(&listener_ctx, stream).unwrap();
+    });
+
+    // Only forward-secret suites are offered for the older protocol versions
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_cipher_list("ECDHE-RSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-SHA").unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    SslStream::connect_generic(&ctx, stream).unwrap();
+}
+
+#[test]
+fn test_client_cert_callback() {
+    fn callback(_preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
+        true
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+    listener_ctx.set_client_CA_list(&[cert]).unwrap();
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = SslStream::accept(&listener_ctx, stream).unwrap();
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf).unwrap();
+        assert!(stream.get_peer_certificate().is_some());
+    });
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_client_cert_callback(|ssl| {
+        let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+        if !ssl.client_CA_names().contains(&cert.issuer_name().to_der()) {
+            return None;
+        }
+        let key = PKey::private_key_from_pem(&mut File::open("test/key.pem").unwrap()).unwrap();
+        Some((cert, key))
+    });
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    stream.write_all(b"a").unwrap();
+
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_client_cert_lookup_callback() {
+    use ssl::ClientCertLookup;
+
+    fn callback(_preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
+        true
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+    let guard = thread::spawn(move || {
+        // The client gives up on the first handshake
+        let (stream, _) = listener.accept().unwrap();
+        assert!(SslStream::accept(&listener_ctx, stream).is_err());
+
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = SslStream::accept(&listener_ctx, stream).unwrap();
+            let mut buf = [0; 1];
+            stream.read_exact(&mut buf).unwrap();
+            assert!(stream.get_peer_certificate().is_some());
+        }
+    });
+
+    let pending = Arc::new(AtomicBool::new(true));
+    let pending2 = pending.clone();
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_client_cert_lookup_callback(move |_| {
+        if pending2.load(Ordering::SeqCst) {
+            return ClientCertLookup::Pending;
+        }
+        let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+        let key = PKey::private_key_from_pem(&mut File::open("test/key.pem").unwrap()).unwrap();
+        ClientCertLookup::Found(cert, key)
+    });
+
+    // A blocking stream can't wait for the lookup
+    let stream = TcpStream::connect(addr).unwrap();
+    match SslStream::connect_generic(&ctx, stream) {
+        Err(SslError::StreamError(ref e)) => assert_eq!(e.kind(), io::ErrorKind::Other),
+        _ => panic!("expected the pending lookup to fail the handshake"),
+    }
+    pending.store(false, Ordering::SeqCst);
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    stream.write_all(b"a").unwrap();
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect(&ctx, stream).unwrap();
+    stream.write_all(b"a").unwrap();
+
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_write_direct() {
+    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
+    stream.write_all("hello".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    stream.write_all(" there".as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+run_test!(get_peer_certificate, |method, stream| {
+    let stream = SslStream::connect_generic(&SslContext::new(method).unwrap(), stream).unwrap();
+    let cert = stream.get_peer_certificate().unwrap();
+    let fingerprint = cert.fingerprint(SHA256).unwrap();
+    let node_hash_str = "db400bb62f1b1f29c3b8f323b8f7d9dea724fdcd67104ef549c772ae3749655b";
+    let node_id = node_hash_str.from_hex().unwrap();
+    assert_eq!(node_id, fingerprint)
+});
+
+run_test!(get_peer_certificate_verify_none, |method, stream| {
+    // The certificate isn't trusted, but it is kept for inspection since
+    // verification is disabled
+    let mut ctx = SslContext::new(method).unwrap();
+    ctx.set_verify(ssl::SSL_VERIFY_NONE, None);
+    let stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    let cert = stream.get_peer_certificate().unwrap();
+    let fingerprint = cert.fingerprint(SHA256).unwrap();
+    let node_hash_str = "db400bb62f1b1f29c3b8f323b8f7d9dea724fdcd67104ef549c772ae3749655b";
+    assert_eq!(node_hash_str.from_hex().unwrap(), fingerprint)
+});
+
+#[test]
+#[cfg(feature = "dtlsv1")]
+fn test_write_dtlsv1() {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let stream = sock.connect("127.0.0.1:15410").unwrap();
+
+    let mut stream = SslStream::connect_generic(&SslContext::new(Dtlsv1).unwrap(), stream).unwrap();
+    stream.write_all("hello".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    stream.write_all(" there".as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+#[test]
+fn test_read() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    io::copy(&mut stream, &mut io::sink()).ok().expect("read error");
+}
+
+fn check_read_nonblocking(mut stream: SslStream<TcpStream>) {
+    stream.get_ref().set_nonblocking(true).unwrap();
+    let mut buf = [0; 1024];
+    // The server doesn't send anything until it receives a request
+    let err = stream.read(&mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    loop {
+        match stream.read(&mut buf) {
+            Ok(len) => {
+                assert!(len > 0);
+                break;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("read error: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_read_nonblocking() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    check_read_nonblocking(stream);
+
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    check_read_nonblocking(stream);
+}
+
+#[test]
+fn test_write_after_close_direct() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    // The server shuts the connection down once it has responded
+    io::copy(&mut stream, &mut io::sink()).unwrap();
+
+    // The first writes may still be buffered by the kernel
+    let err = (0..10).filter_map(|_| {
+        thread::sleep(Duration::from_millis(10));
+        stream.write(b"hello").err()
+    }).next().expect("writes to a closed connection should fail");
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn test_read_direct() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    stream.flush().unwrap();
+    io::copy(&mut stream, &mut io::sink()).ok().expect("read error");
+}
+
+#[test]
+fn test_info_callback() {
+    let done = Arc::new(AtomicBool::new(false));
+    let done2 = done.clone();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_info_callback(move |_, state, _| {
+        if state.contains(ssl::SSL_CB_HANDSHAKE_DONE) {
+            done2.store(true, Ordering::SeqCst);
+        }
+    });
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert!(done.load(Ordering::SeqCst));
+}
+
+#[test]
+#[cfg(feature = "keylog")]
+fn test_keylog_callback() {
+    let logged = Arc::new(AtomicBool::new(false));
+    let logged2 = logged.clone();
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_keylog_callback(move |_, line| {
+        assert!(!line.is_empty());
+        logged2.store(true, Ordering::SeqCst);
+    });
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert!(logged.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_ticket_key_callback() {
+    let issued = Arc::new(AtomicBool::new(false));
+    let issued2 = issued.clone();
+    // Panicking inside the callback would unwind into OpenSSL
+    let decrypted = Arc::new(AtomicBool::new(false));
+    let decrypted2 = decrypted.clone();
+
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let ctx = SslContext::new(Sslv23).unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+        let mut buf = [0; 5];
+        assert_eq!(5, stream.read(&mut buf).unwrap());
+        assert_eq!(&b"hello"[..], &buf[..]);
     });
 
-    let mut ctx = SslContext::new(Sslv23).unwrap();
-    ctx.set_verify(SSL_VERIFY_PEER, None);
-    ctx.set_certificate_file(&Path::new("test/cert.pem"), X509FileType::PEM).unwrap();
-    ctx.set_private_key_file(&Path::new("test/key.pem"), X509FileType::PEM).unwrap();
+    let mut ctx = ctx_with_cert(Sslv23);
+    ctx.set_ticket_key_callback(move |_, request| {
+        match request {
+            ssl::TicketKeyRequest::Encrypt => issued2.store(true, Ordering::SeqCst),
+            ssl::TicketKeyRequest::Decrypt(_) => {
+                decrypted2.store(true, Ordering::SeqCst);
+                return None;
+            }
+        }
+        Some(ssl::TicketKey {
+            name: [1; 16],
+            aes_key: [2; 16],
+            hmac_key: [3; 16],
+            renew: false,
+        })
+    });
     let stream = listener.accept().unwrap().0;
-    let mut stream = SslStream::accept(&ctx, stream).unwrap();
+    let mut stream = SslStream::accept_generic(&ctx, stream).unwrap();
+    stream.write_all(b"hello").unwrap();
+    guard.join().unwrap();
+
+    assert!(issued.load(Ordering::SeqCst));
+    assert!(!decrypted.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_static_ticket_key() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        // Each connection uses a new context, which only shares the key
+        for _ in 0..2 {
+            let mut ctx = ctx_with_cert(Sslv23);
+            ctx.set_static_ticket_key(&[7; 48]);
+            let stream = listener.accept().unwrap().0;
+            let mut stream = SslStream::accept_generic(&ctx, stream).unwrap();
+            stream.write_all(b"hello").unwrap();
+        }
+    });
 
+    let ctx = SslContext::new(Sslv23).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
     let mut buf = [0; 5];
+    // Reading processes any tickets sent after the handshake
     assert_eq!(5, stream.read(&mut buf).unwrap());
-    assert_eq!(&b"hello"[..], &buf[..]);
+    assert!(!stream.session_reused());
+    let session = stream.get_session().unwrap();
+
+    let ssl = Ssl::new(&ctx).unwrap();
+    ssl.set_session(&session).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(ssl, stream).unwrap();
+    assert_eq!(5, stream.read(&mut buf).unwrap());
+    assert!(stream.session_reused());
+
     guard.join().unwrap();
 }
 
 #[test]
-fn test_set_certificate_and_private_key() {
-    let key_path = Path::new("test/key.pem");
-    let cert_path = Path::new("test/cert.pem");
-    let mut key_file = File::open(&key_path)
-        .ok()
-        .expect("Failed to open `test/key.pem`");
-    let mut cert_file = File::open(&cert_path)
-        .ok()
-        .expect("Failed to open `test/cert.pem`");
+#[cfg(feature = "early_data")]
+fn test_early_data() {
+    use ssl::EarlyDataStatus;
 
-    let key = PKey::private_key_from_pem(&mut key_file).unwrap();
-    let cert = X509::from_pem(&mut cert_file).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let mut ctx = ctx_with_cert(Sslv23);
+        ctx.set_max_early_data(1024).unwrap();
+
+        let stream = listener.accept().unwrap().0;
+        let (mut stream, data) = SslStream::accept_with_early_data(&ctx, stream).unwrap();
+        assert!(data.is_empty());
+        stream.write_all(b"hello").unwrap();
+
+        let stream = listener.accept().unwrap().0;
+        let (mut stream, data) = SslStream::accept_with_early_data(&ctx, stream).unwrap();
+        assert_eq!(EarlyDataStatus::Accepted, stream.early_data_status());
+        stream.write_all(&data).unwrap();
+
+        // Only part of the data fits, and the rest follows the handshake
+        let stream = listener.accept().unwrap().0;
+        let (mut stream, mut data) = SslStream::accept_with_early_data(&ctx, stream).unwrap();
+        assert_eq!(1024, data.len());
+        let mut rest = vec![0; 976];
+        stream.read_exact(&mut rest).unwrap();
+        assert_eq!(2000, stream.bytes_read());
+        data.extend(rest);
+        stream.write_all(&data).unwrap();
+    });
+
+    let ctx = SslContext::new(Sslv23).unwrap();
+    // Without a session to resume, nothing can be sent early
+    let stream = TcpStream::connect(addr).unwrap();
+    let (mut stream, written) = SslStream::connect_with_early_data(&ctx, stream, b"early")
+        .unwrap();
+    assert_eq!(0, written);
+    let mut buf = [0; 5];
+    assert_eq!(5, stream.read(&mut buf).unwrap());
+    assert_eq!(EarlyDataStatus::NotSent, stream.early_data_status());
+    let session = stream.get_session().unwrap();
+    assert_eq!(1024, session.max_early_data());
+
+    let ssl = Ssl::new(&ctx).unwrap();
+    ssl.set_session(&session).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let (mut stream, written) = SslStream::connect_with_early_data(ssl, stream, b"early")
+        .unwrap();
+    assert_eq!(5, written);
+    assert_eq!(5, stream.bytes_written());
+    assert_eq!(EarlyDataStatus::Accepted, stream.early_data_status());
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(b"early", &buf);
+    // The ticket issued on this connection allows early data again
+    let session = stream.get_session().unwrap();
+
+    let data = (0..2000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let ssl = Ssl::new(&ctx).unwrap();
+    ssl.set_session(&session).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let (mut stream, written) = SslStream::connect_with_early_data(ssl, stream, &data).unwrap();
+    assert_eq!(1024, written);
+    stream.write_all(&data[written..]).unwrap();
+    assert_eq!(2000, stream.bytes_written());
+    let mut echoed = vec![0; 2000];
+    stream.read_exact(&mut echoed).unwrap();
+    assert!(echoed == data);
+
+    guard.join().unwrap();
+}
 
+#[test]
+fn test_session_cache_off() {
     let mut ctx = SslContext::new(Sslv23).unwrap();
-    ctx.set_private_key(&key).unwrap();
-    ctx.set_certificate(&cert).unwrap();
+    assert!(ctx.get_session_cache_mode().contains(ssl::SSL_SESS_CACHE_SERVER));
+    ctx.set_options(ssl::SSL_OP_NO_TICKET);
+
+    ctx.set_session_cache_off();
+    let mode = ctx.get_session_cache_mode();
+    assert!(!mode.intersects(ssl::SSL_SESS_CACHE_BOTH));
+    assert!(mode.contains(ssl::SSL_SESS_CACHE_NO_INTERNAL));
+    assert!(!ctx.get_options().contains(ssl::SSL_OP_NO_TICKET));
+
+    let old = ctx.set_session_cache_mode(ssl::SSL_SESS_CACHE_CLIENT);
+    assert_eq!(old, mode);
+    assert_eq!(ctx.get_session_cache_mode(), ssl::SSL_SESS_CACHE_CLIENT);
+}
 
-    assert!(ctx.check_private_key().is_ok());
+#[test]
+fn test_partial_write() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_mode(ssl::SSL_MODE_ENABLE_PARTIAL_WRITE);
+    assert!(ctx.get_mode().contains(ssl::SSL_MODE_ENABLE_PARTIAL_WRITE));
+
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    let buf = [0u8; 32 * 1024];
+    let written = stream.write(&buf).unwrap();
+    assert!(written > 0 && written < buf.len());
 }
 
-run_test!(get_ctx_options, |method, _| {
-    let mut ctx = SslContext::new(method).unwrap();
-    ctx.get_options();
-});
+#[test]
+fn test_write_partial() {
+    let ctx = SslContext::new(Sslv23).unwrap();
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    let buf = [0u8; 32 * 1024];
+    let written = stream.write_partial(&buf).unwrap();
+    assert!(written > 0 && written < buf.len());
 
-run_test!(set_ctx_options, |method, _| {
-    let mut ctx = SslContext::new(method).unwrap();
-    let opts = ctx.set_options(ssl::SSL_OP_NO_TICKET);
-    assert!(opts.contains(ssl::SSL_OP_NO_TICKET));
-    assert!(!opts.contains(ssl::SSL_OP_CISCO_ANYCONNECT));
-    let more_opts = ctx.set_options(ssl::SSL_OP_CISCO_ANYCONNECT);
-    assert!(more_opts.contains(ssl::SSL_OP_NO_TICKET));
-    assert!(more_opts.contains(ssl::SSL_OP_CISCO_ANYCONNECT));
-});
+    // The mode only applies to write_partial
+    assert_eq!(buf.len(), stream.write(&buf).unwrap());
+}
 
-run_test!(clear_ctx_options, |method, _| {
-    let mut ctx = SslContext::new(method).unwrap();
-    ctx.set_options(ssl::SSL_OP_ALL);
-    let opts = ctx.clear_options(ssl::SSL_OP_ALL);
-    assert!(!opts.contains(ssl::SSL_OP_ALL));
-});
+#[test]
+fn test_auto_retry_mode() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_mode(ssl::SSL_MODE_AUTO_RETRY);
+    assert!(ctx.get_mode().contains(ssl::SSL_MODE_AUTO_RETRY));
+
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    stream.write_all(b"hello").unwrap();
+}
 
 #[test]
-fn test_write() {
-    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
-    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
-    stream.write_all("hello".as_bytes()).unwrap();
-    stream.flush().unwrap();
-    stream.write_all(" there".as_bytes()).unwrap();
-    stream.flush().unwrap();
+fn test_current_cipher() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    let cipher = stream.get_current_cipher().unwrap();
+    assert!(!cipher.name().is_empty());
+    assert!(cipher.bits() > 0);
+    assert!(cipher.description().starts_with(&cipher.name()));
+    assert!(cipher.key_exchange().is_some());
+    assert!(cipher.authentication().is_some());
+    assert!(cipher.encryption().is_some());
+    let mac = cipher.mac().unwrap();
+    assert_eq!(cipher.is_aead(), mac == "AEAD");
 }
 
 #[test]
-fn test_write_direct() {
-    let stream = TcpStream::connect("127.0.0.1:15418").unwrap();
-    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), stream).unwrap();
-    stream.write_all("hello".as_bytes()).unwrap();
-    stream.flush().unwrap();
-    stream.write_all(" there".as_bytes()).unwrap();
-    stream.flush().unwrap();
+#[cfg(feature = "tlsv1_3")]
+fn test_tlsv1_3_ciphersuites() {
+    let mut ctx = SslContext::new(SslMethod::Tls).unwrap();
+    ctx.set_ciphersuites("TLS_CHACHA20_POLY1305_SHA256").unwrap();
+    assert!(ctx.set_ciphersuites("not-a-suite").is_err());
+
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert_eq!(stream.protocol_version(), Some(ssl::SslVersion::Tlsv1_3));
+    assert_eq!(stream.get_current_cipher().unwrap().name(), "TLS_CHACHA20_POLY1305_SHA256");
 }
 
-run_test!(get_peer_certificate, |method, stream| {
-    let stream = SslStream::connect_generic(&SslContext::new(method).unwrap(), stream).unwrap();
-    let cert = stream.get_peer_certificate().unwrap();
-    let fingerprint = cert.fingerprint(SHA256).unwrap();
-    let node_hash_str = "db400bb62f1b1f29c3b8f323b8f7d9dea724fdcd67104ef549c772ae3749655b";
-    let node_id = node_hash_str.from_hex().unwrap();
-    assert_eq!(node_id, fingerprint)
-});
+#[test]
+fn test_protocol_version() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    let version = stream.protocol_version().unwrap();
+    assert!(version >= ssl::SslVersion::Tlsv1);
+    assert!(version < ssl::SslVersion::Dtlsv1);
+}
 
 #[test]
-#[cfg(feature = "dtlsv1")]
-fn test_write_dtlsv1() {
-    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
-    let stream = sock.connect("127.0.0.1:15410").unwrap();
+fn test_protocol_version_range() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_protocol_version_range(Some(ssl::SslVersion::Tlsv1_2),
+                                   Some(ssl::SslVersion::Tlsv1_2)).unwrap();
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert_eq!(stream.protocol_version(), Some(ssl::SslVersion::Tlsv1_2));
 
-    let mut stream = SslStream::connect_generic(&SslContext::new(Dtlsv1).unwrap(), stream).unwrap();
-    stream.write_all("hello".as_bytes()).unwrap();
-    stream.flush().unwrap();
-    stream.write_all(" there".as_bytes()).unwrap();
-    stream.flush().unwrap();
+    ctx.set_protocol_version_range(Some(ssl::SslVersion::Tlsv1), None).unwrap();
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert!(stream.protocol_version().unwrap() >= ssl::SslVersion::Tlsv1_2);
 }
 
 #[test]
-fn test_read() {
+fn test_master_key() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let guard = thread::spawn(move || {
+        let mut ctx = ctx_with_cert(Sslv23);
+        let stream = listener.accept().unwrap().0;
+        let mut stream = SslStream::accept_generic(&ctx, stream).unwrap();
+        let key = stream.master_key();
+        stream.write_all(&key).unwrap();
+    });
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    // Both ends derive the same secret during a TLSv1.2 handshake
+    ctx.set_protocol_version_range(None, Some(ssl::SslVersion::Tlsv1_2)).unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    let key = stream.master_key();
+    assert_eq!(48, key.len());
+    let mut buf = vec![];
+    stream.read_to_end(&mut buf).unwrap();
+    assert_eq!(key, buf);
+
+    guard.join().unwrap();
+}
+
+#[test]
+fn test_supported_ciphers() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_cipher_list("AES128-SHA:AES256-SHA").unwrap();
+    let names: Vec<String> = ctx.supported_ciphers().unwrap().iter().map(|c| c.name()).collect();
+    assert_eq!(names, ctx.get_cipher_list_string().unwrap().split(':').map(|s| s.to_owned())
+                                                          .collect::<Vec<_>>());
+    assert!(names.iter().any(|n| n == "AES128-SHA"));
+    assert!(names.iter().any(|n| n == "AES256-SHA"));
+
+    assert!(SslMethod::available().contains(&Sslv23));
+    for method in SslMethod::available() {
+        SslContext::new(method).unwrap();
+    }
+}
+
+#[test]
+fn test_require_aead() {
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.require_aead().unwrap();
+    let ciphers = ctx.get_cipher_list_string().unwrap();
+    assert!(!ciphers.is_empty());
+    for cipher in ciphers.split(':') {
+        assert!(cipher.contains("GCM") || cipher.contains("CHACHA20"), "{}", cipher);
+    }
+
+    ctx.set_protocol_version_range(None, Some(ssl::SslVersion::Tlsv1_2)).unwrap();
     let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
-    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
-    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
-    stream.flush().unwrap();
-    io::copy(&mut stream, &mut io::sink()).ok().expect("read error");
+    let stream = SslStream::connect_generic(&ctx, tcp).unwrap();
+    assert!(stream.get_current_cipher().unwrap().name().contains("GCM"));
 }
 
 #[test]
-fn test_read_direct() {
+fn test_shutdown_state() {
     let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
-    let mut stream = SslStream::connect(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
-    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
-    stream.flush().unwrap();
-    io::copy(&mut stream, &mut io::sink()).ok().expect("read error");
+    let stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    assert!(stream.get_shutdown_state().is_empty());
 }
 
 #[test]
@@ -390,6 +1756,38 @@ fn test_pending() {
     assert_eq!(pending, len);
 }
 
+#[test]
+fn test_wpending() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    // Every write is pushed through to the underlying stream
+    assert_eq!(stream.wpending(), 0);
+}
+
+#[test]
+fn test_peek() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+
+    let mut peeked = [0; 4];
+    let len = stream.peek(&mut peeked).unwrap();
+    assert!(len > 0);
+    let mut read = [0; 4];
+    assert_eq!(stream.read(&mut read[..len]).unwrap(), len);
+    assert_eq!(&peeked[..len], &read[..len]);
+}
+
+#[test]
+fn test_flush_ssl() {
+    let tcp = TcpStream::connect("127.0.0.1:15418").unwrap();
+    let mut stream = SslStream::connect_generic(&SslContext::new(Sslv23).unwrap(), tcp).unwrap();
+    stream.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
+    stream.flush_ssl().unwrap();
+    assert_eq!(stream.wpending(), 0);
+}
+
 /// Tests that connecting with the client using NPN, but the server not does not
 /// break the existing connection behavior.
 #[test]
@@ -410,6 +1808,16 @@ fn test_connect_with_unilateral_alpn() {
     // Since the socket to which we connected is not configured to use NPN,
     // there should be no selected protocol...
     assert!(stream.get_selected_alpn_protocol().is_none());
+    assert_eq!(stream.get_alpn_status(), ssl::AlpnStatus::NotAcknowledged);
+}
+
+#[test]
+#[cfg(feature = "alpn")]
+fn test_alpn_status_not_offered() {
+    let ctx = SslContext::new(Sslv23).unwrap();
+    let stream = TcpStream::connect("127.0.0.1:15419").unwrap();
+    let stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    assert_eq!(stream.get_alpn_status(), ssl::AlpnStatus::NotOffered);
 }
 
 /// Tests that connecting with the client using NPN, but the server not does not
@@ -505,6 +1913,7 @@ fn test_connect_with_alpn_successful_single_match() {
     // The client now only supports one of the server's protocols, so that one
     // is used.
     assert_eq!(b"spdy/3.1", stream.get_selected_alpn_protocol().unwrap());
+    assert_eq!(stream.get_alpn_status(), ssl::AlpnStatus::Selected(b"spdy/3.1"));
 }
 
 
@@ -615,6 +2024,125 @@ fn test_alpn_server_advertise_multiple() {
     assert_eq!(b"spdy/3.1", stream.get_selected_alpn_protocol().unwrap());
 }
 
+/// Tests that a server can pick the protocol from the client's list with a
+/// callback.
+#[test]
+#[cfg(feature = "alpn")]
+fn test_alpn_server_select_callback() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_alpn_select_callback(|_, offered| {
+        assert_eq!(offered, &[&b"http/1.1"[..], &b"spdy/3.1"[..]]);
+        Some(b"spdy/3.1".to_vec())
+    });
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let stream = SslStream::accept(&listener_ctx, stream).unwrap();
+        assert_eq!(b"spdy/3.1", stream.get_selected_alpn_protocol().unwrap());
+    });
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_alpn_protocols(&[b"http/1.1", b"spdy/3.1"]);
+    let stream = TcpStream::connect(addr).unwrap();
+    let stream = SslStream::connect(&ctx, stream).unwrap();
+    assert_eq!(b"spdy/3.1", stream.get_selected_alpn_protocol().unwrap());
+    guard.join().unwrap();
+}
+
+#[cfg(feature = "dane")]
+fn dane_connect(tlsa_data: &[u8]) -> bool {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let listener_ctx = ctx_with_cert(Sslv23);
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let _ = SslStream::accept(&listener_ctx, stream);
+    });
+
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_verify(SSL_VERIFY_PEER, None);
+    ctx.dane_enable().unwrap();
+    let ssl = Ssl::new(&ctx).unwrap();
+    ssl.dane_enable("localhost").unwrap();
+    // DANE-EE(3) Cert(0) Full(0)
+    assert!(ssl.dane_tlsa_add(3, 0, 0, tlsa_data).unwrap());
+
+    let stream = TcpStream::connect(addr).unwrap();
+    SslStream::connect(ssl, stream).is_ok()
+}
+
+/// Tests that a certificate is trusted through a matching TLSA record alone.
+#[test]
+#[cfg(feature = "dane")]
+fn test_dane_tlsa_match() {
+    let mut der = vec![];
+    File::open("test/cert.der").unwrap().read_to_end(&mut der).unwrap();
+    assert!(dane_connect(&der));
+
+    der[100] ^= 1;
+    assert!(!dane_connect(&der));
+}
+
+/// Tests that a server can ask for a client certificate after the handshake.
+#[test]
+#[cfg(feature = "post_handshake_auth")]
+fn test_post_handshake_auth() {
+    fn callback(_preverify_ok: bool, _x509_ctx: &X509StoreContext) -> bool {
+        true
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut listener_ctx = ctx_with_cert(Sslv23);
+    listener_ctx.set_verify(SSL_VERIFY_PEER, Some(callback as VerifyCallback));
+    let guard = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = SslStream::accept(&listener_ctx, stream).unwrap();
+        assert!(stream.get_peer_certificate().is_none());
+
+        stream.request_client_auth().unwrap();
+        stream.write_all(b"a").unwrap();
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf).unwrap();
+        assert!(stream.get_peer_certificate().is_some());
+    });
+
+    let ctx = ctx_with_cert(Sslv23);
+    let ssl = Ssl::new(&ctx).unwrap();
+    ssl.set_post_handshake_auth(true);
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut stream = SslStream::connect_generic(ssl, stream).unwrap();
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).unwrap();
+    stream.write_all(b"b").unwrap();
+
+    guard.join().unwrap();
+}
+
+/// Tests that a client can complete a handshake using only a pre-shared key.
+#[test]
+#[cfg(feature = "psk")]
+fn test_connect_with_psk() {
+    // A different port than the other tests: an `openssl` process that has
+    // no certificate and only accepts PSK cipher suites.
+    let stream = TcpStream::connect("127.0.0.1:15422").unwrap();
+    let mut ctx = SslContext::new(Sslv23).unwrap();
+    ctx.set_cipher_list("PSK").unwrap();
+    ctx.set_psk_client_callback(|_, _| {
+        Some((b"client".to_vec(), vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]))
+    });
+    let mut stream = SslStream::connect_generic(&ctx, stream).unwrap();
+    stream.write_all(b"GET /\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert!(!resp.is_empty());
+}
+
 #[cfg(feature="dtlsv1")]
 #[cfg(test)]
 mod dtlsv1 {
@@ -623,6 +2151,7 @@ mod dtlsv1 {
     use std::thread;
 
     use crypto::hash::Type::{SHA256};
+    use ssl;
     use ssl::SslMethod;
     use ssl::SslMethod::Dtlsv1;
     use ssl::{SslContext, SslStream, VerifyCallback};
@@ -635,6 +2164,121 @@ mod dtlsv1 {
     fn test_new_ctx() {
         SslContext::new(PROTOCOL).unwrap();
     }
+
+    #[test]
+    fn test_set_cookie_callbacks() {
+        let mut ctx = SslContext::new(PROTOCOL).unwrap();
+        ctx.set_cookie_callbacks(|_| b"cookie".to_vec(), |_, cookie| cookie == b"cookie");
+        assert!(ctx.get_options().contains(ssl::SSL_OP_COOKIE_EXCHANGE));
+    }
+
+    #[test]
+    fn test_cookie_exchange() {
+        use std::io::prelude::*;
+        use std::net::UdpSocket;
+        use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+        use std::thread;
+        use connected_socket::Connect;
+
+        static GENERATED: AtomicBool = ATOMIC_BOOL_INIT;
+        static VERIFIED: AtomicBool = ATOMIC_BOOL_INIT;
+
+        let server_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server_sock.local_addr().unwrap().to_string();
+        let client_addr = client_sock.local_addr().unwrap().to_string();
+
+        let mut ctx = super::ctx_with_cert(PROTOCOL);
+        ctx.set_cookie_callbacks(|_| {
+            GENERATED.store(true, Ordering::SeqCst);
+            b"cookie".to_vec()
+        }, |_, cookie| {
+            VERIFIED.store(true, Ordering::SeqCst);
+            cookie == b"cookie"
+        });
+
+        let guard = thread::spawn(move || {
+            let stream = server_sock.connect(&client_addr[..]).unwrap();
+            let mut stream = SslStream::accept_generic(&ctx, stream).unwrap();
+            let mut buf = [0; 5];
+            assert_eq!(5, stream.read(&mut buf).unwrap());
+            stream.write_all(&buf).unwrap();
+            stream.flush().unwrap();
+        });
+
+        let stream = client_sock.connect(&server_addr[..]).unwrap();
+        let mut stream = SslStream::connect_generic(&SslContext::new(PROTOCOL).unwrap(), stream)
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        stream.flush().unwrap();
+        let mut buf = [0; 5];
+        assert_eq!(5, stream.read(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf);
+        guard.join().unwrap();
+
+        assert!(GENERATED.load(Ordering::SeqCst));
+        assert!(VERIFIED.load(Ordering::SeqCst));
+    }
+}
+
+#[test]
+fn test_mem_stream() {
+    use ssl::SslMemStream;
+    use ssl::error::NonblockingSslError;
+
+    fn pump(from: &mut SslMemStream, to: &mut SslMemStream) {
+        let mut buf = [0; 4096];
+        loop {
+            let len = from.drain(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            to.feed(&buf[..len]).unwrap();
+        }
+    }
+
+    fn done(res: Result<(), NonblockingSslError>) -> bool {
+        match res {
+            Ok(()) => true,
+            Err(NonblockingSslError::WantRead) => false,
+            Err(e) => panic!("handshake failed: {}", e),
+        }
+    }
+
+    let server_ctx = ctx_with_cert(Sslv23);
+    let mut server = SslMemStream::accept(&server_ctx).unwrap();
+    let mut client = SslMemStream::connect(&SslContext::new(Sslv23).unwrap()).unwrap();
+
+    let mut buf = [0; 5];
+    match client.read(&mut buf) {
+        Err(NonblockingSslError::WantRead) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(client.pending_output() > 0);
+
+    loop {
+        pump(&mut client, &mut server);
+        let server_done = done(server.handshake());
+        pump(&mut server, &mut client);
+        if done(client.handshake()) && server_done {
+            break;
+        }
+    }
+
+    assert_eq!(client.write(b"hello").unwrap(), 5);
+    pump(&mut client, &mut server);
+    assert_eq!(server.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"hello");
+    match server.read(&mut buf) {
+        Err(NonblockingSslError::WantRead) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_thread_setup_complete() {
+    SslContext::new(Sslv23).unwrap();
+    assert!(::thread_setup_complete());
 }
 
 #[test]
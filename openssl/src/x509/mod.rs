@@ -1,4 +1,4 @@
-use libc::{c_char, c_int, c_long, c_ulong, c_uint, c_void};
+use libc::{c_char, c_int, c_long, c_ulong, c_uint, c_void, size_t, time_t};
 use std::io;
 use std::io::prelude::*;
 use std::cmp::Ordering;
@@ -6,12 +6,16 @@ use std::ffi::{CString, CStr};
 use std::iter::repeat;
 use std::mem;
 use std::ptr;
+use std::marker::PhantomData;
 use std::ops::Deref;
 use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::slice;
 use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
 
-use asn1::{Asn1Time};
+use asn1::{Asn1Time, Asn1TimeRef};
 use bio::{MemBio};
 use crypto::hash;
 use crypto::hash::Type as HashType;
@@ -74,6 +78,20 @@ pub enum X509FileType {
     Default = ffi::X509_FILETYPE_DEFAULT
 }
 
+/// The purpose a certificate is verified for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum X509Purpose {
+    SslClient = ffi::X509_PURPOSE_SSL_CLIENT,
+    SslServer = ffi::X509_PURPOSE_SSL_SERVER,
+    NsSslServer = ffi::X509_PURPOSE_NS_SSL_SERVER,
+    SmimeSign = ffi::X509_PURPOSE_SMIME_SIGN,
+    SmimeEncrypt = ffi::X509_PURPOSE_SMIME_ENCRYPT,
+    CrlSign = ffi::X509_PURPOSE_CRL_SIGN,
+    Any = ffi::X509_PURPOSE_ANY,
+    OcspHelper = ffi::X509_PURPOSE_OCSP_HELPER,
+}
+
 #[allow(missing_copy_implementations)]
 pub struct X509StoreContext {
     ctx: *mut ffi::X509_STORE_CTX
@@ -91,14 +109,253 @@ impl X509StoreContext {
         X509ValidationError::from_raw(err)
     }
 
-    pub fn get_current_cert<'a>(&'a self) -> Option<X509<'a>> {
+    /// Overrides the verification error of the current certificate.
+    ///
+    /// Passing `None` clears the error. This is intended to be used from a
+    /// verify callback to adjust the result reported for the connection.
+    pub fn set_error(&self, err: Option<X509ValidationError>) {
+        let err = err.map_or(ffi::X509_V_OK, |err| err.to_raw());
+        unsafe { ffi::X509_STORE_CTX_set_error(self.ctx, err) }
+    }
+
+    /// Sets the error returned from the handshake if this verification fails.
+    ///
+    /// See `Ssl::set_handshake_error`. The verify callback must still return
+    /// `false` to reject the certificate.
+    pub fn set_handshake_error(&self, err: SslError) {
+        unsafe {
+            let idx = ffi::SSL_get_ex_data_X509_STORE_CTX_idx();
+            let ssl = ffi::X509_STORE_CTX_get_ex_data(self.ctx, idx);
+            if !ssl.is_null() {
+                ::ssl::set_handshake_error_raw(ssl, err);
+            }
+        }
+    }
+
+    /// Returns the depth of the current certificate in the chain, where 0 is
+    /// the peer's own certificate.
+    pub fn error_depth(&self) -> u32 {
+        unsafe { ffi::X509_STORE_CTX_get_error_depth(self.ctx) as u32 }
+    }
+
+    pub fn get_current_cert<'a>(&'a self) -> Option<X509Ref<'a>> {
         let ptr = unsafe { ffi::X509_STORE_CTX_get_current_cert(self.ctx) };
 
         if ptr.is_null() {
             None
         } else {
-            Some(X509 { ctx: Some(self), handle: ptr, owned: false })
+            Some(X509Ref::new(ptr))
+        }
+    }
+
+    /// Returns the DER encoding of the certificate currently being verified.
+    ///
+    /// This is useful to log or hash the exact bytes the peer presented.
+    pub fn current_cert_der(&self) -> Option<Vec<u8>> {
+        self.get_current_cert().map(|cert| cert.to_der())
+    }
+
+    /// Returns the certificate chain built so far, starting with the
+    /// certificate being verified.
+    pub fn get_chain(&self) -> Option<Vec<X509>> {
+        unsafe {
+            let chain = ffi::X509_STORE_CTX_get1_chain(self.ctx);
+            if chain.is_null() {
+                return None;
+            }
+
+            // get1_chain bumps the refcount of each certificate, so each one
+            // is owned by the returned vector and only the stack is freed here
+            let certs = (0..ffi::sk_num(chain)).map(|i| {
+                X509::new(ffi::sk_value(chain, i) as *mut ffi::X509)
+            }).collect();
+            ffi::sk_free(chain);
+            Some(certs)
+        }
+    }
+}
+
+/// A borrowed store of trusted certificates, such as the one used by an
+/// `SslContext` to verify peers.
+pub struct X509StoreRef<'a> {
+    store: *mut ffi::X509_STORE,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> X509StoreRef<'a> {
+    /// Creates a new `X509StoreRef` wrapping the provided handle. The handle
+    /// is not freed when the `X509StoreRef` is dropped.
+    ///
+    /// The handle must be valid for the lifetime `'a`.
+    pub unsafe fn new(store: *mut ffi::X509_STORE) -> X509StoreRef<'a> {
+        X509StoreRef {
+            store: store,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying `X509_STORE`, which is only borrowed.
+    pub unsafe fn get_handle(&self) -> *mut ffi::X509_STORE {
+        self.store
+    }
+}
+
+/// A mutably borrowed store of trusted certificates.
+pub struct X509StoreRefMut<'a>(X509StoreRef<'a>, PhantomData<&'a mut ()>);
+
+impl<'a> X509StoreRefMut<'a> {
+    /// Creates a new `X509StoreRefMut` wrapping the provided handle. The
+    /// handle is not freed when the `X509StoreRefMut` is dropped.
+    ///
+    /// The handle must be valid, and not used elsewhere, for the lifetime
+    /// `'a`.
+    pub unsafe fn new(store: *mut ffi::X509_STORE) -> X509StoreRefMut<'a> {
+        X509StoreRefMut(X509StoreRef::new(store), PhantomData)
+    }
+
+    /// Adds a trusted certificate to the store.
+    pub fn add_cert(&mut self, cert: &X509Ref) -> Result<(), SslError> {
+        unsafe {
+            try_ssl!(ffi::X509_STORE_add_cert(self.0.store, cert.get_handle()));
+        }
+        Ok(())
+    }
+
+    /// Sets the flags used when verifying certificates against the store.
+    pub fn set_flags(&mut self, flags: X509VerifyFlags) -> Result<(), SslError> {
+        unsafe {
+            try_ssl!(ffi::X509_STORE_set_flags(self.0.store, flags.bits()));
+        }
+        Ok(())
+    }
+
+    /// Verifies certificates as of `unix_time`, in seconds since the epoch,
+    /// rather than the current time.
+    pub fn set_time(&mut self, unix_time: i64) {
+        unsafe {
+            let param = ffi::X509_STORE_get0_param(self.0.store);
+            ffi::X509_VERIFY_PARAM_set_time(param, unix_time as time_t);
+        }
+    }
+
+    /// Requires the leaf certificate to match `host`.
+    ///
+    /// The subject alternative names, or the common name if there are none,
+    /// are matched as described in RFC 6125. Any host set previously is
+    /// replaced, and an empty `host` removes it.
+    pub fn set_host(&mut self, host: &str) -> Result<(), SslError> {
+        // OpenSSL takes a length of 0 to mean a NUL-terminated string, which
+        // `host` isn't
+        let ptr = if host.is_empty() { ptr::null() } else { host.as_ptr() as *const c_char };
+        unsafe {
+            let param = ffi::X509_STORE_get0_param(self.0.store);
+            try_ssl!(ffi::X509_VERIFY_PARAM_set1_host(param, ptr, host.len() as size_t));
+        }
+        Ok(())
+    }
+
+    /// Requires the leaf certificate to have a subject alternative name
+    /// matching `ip`.
+    ///
+    /// Any address set previously is replaced.
+    pub fn set_ip(&mut self, ip: IpAddr) -> Result<(), SslError> {
+        let octets = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        unsafe {
+            let param = ffi::X509_STORE_get0_param(self.0.store);
+            try_ssl!(ffi::X509_VERIFY_PARAM_set1_ip(param, octets.as_ptr(),
+                                                    octets.len() as size_t));
+        }
+        Ok(())
+    }
+
+    /// Requires the leaf certificate to match the email address `email`,
+    /// through an rfc822Name subject alternative name or the emailAddress
+    /// attribute of its subject.
+    ///
+    /// Any address set previously is replaced, and an empty `email` removes
+    /// it.
+    pub fn set_email(&mut self, email: &str) -> Result<(), SslError> {
+        // As with `set_host`, a length of 0 would make OpenSSL look for a NUL
+        let ptr = if email.is_empty() { ptr::null() } else { email.as_ptr() as *const c_char };
+        unsafe {
+            let param = ffi::X509_STORE_get0_param(self.0.store);
+            try_ssl!(ffi::X509_VERIFY_PARAM_set1_email(param, ptr, email.len() as size_t));
+        }
+        Ok(())
+    }
+
+    /// Adds the system's default certificate locations to the store.
+    pub fn set_default_paths(&mut self) -> Result<(), SslError> {
+        unsafe {
+            try_ssl!(ffi::X509_STORE_set_default_paths(self.0.store));
         }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for X509StoreRefMut<'a> {
+    type Target = X509StoreRef<'a>;
+
+    fn deref(&self) -> &X509StoreRef<'a> {
+        &self.0
+    }
+}
+
+bitflags! {
+    /// The bits of a certificate's key usage extension.
+    flags KeyUsageFlags: u32 {
+        const KU_DIGITAL_SIGNATURE = 0x0080,
+        const KU_NON_REPUDIATION   = 0x0040,
+        const KU_KEY_ENCIPHERMENT  = 0x0020,
+        const KU_DATA_ENCIPHERMENT = 0x0010,
+        const KU_KEY_AGREEMENT     = 0x0008,
+        const KU_KEY_CERT_SIGN     = 0x0004,
+        const KU_CRL_SIGN          = 0x0002,
+        const KU_ENCIPHER_ONLY     = 0x0001,
+        const KU_DECIPHER_ONLY     = 0x8000,
+    }
+}
+
+bitflags! {
+    /// Flags controlling how certificate chains are verified.
+    flags X509VerifyFlags: c_ulong {
+        /// Check the revocation status of the leaf certificate against the CRLs in
+        /// the store.
+        const X509_V_FLAG_CRL_CHECK = ffi::X509_V_FLAG_CRL_CHECK,
+        /// Check the revocation status of every certificate in the chain. Use
+        /// together with `X509_V_FLAG_CRL_CHECK`.
+        const X509_V_FLAG_CRL_CHECK_ALL = ffi::X509_V_FLAG_CRL_CHECK_ALL,
+        /// Don't reject certificates with unhandled critical extensions.
+        const X509_V_FLAG_IGNORE_CRITICAL = ffi::X509_V_FLAG_IGNORE_CRITICAL,
+        /// Reject certificates which don't strictly follow the X.509 rules.
+        const X509_V_FLAG_X509_STRICT = ffi::X509_V_FLAG_X509_STRICT,
+        /// Allow proxy certificates.
+        const X509_V_FLAG_ALLOW_PROXY_CERTS = ffi::X509_V_FLAG_ALLOW_PROXY_CERTS,
+        /// Enable certificate policy checking.
+        const X509_V_FLAG_POLICY_CHECK = ffi::X509_V_FLAG_POLICY_CHECK,
+        /// Require an explicit policy.
+        const X509_V_FLAG_EXPLICIT_POLICY = ffi::X509_V_FLAG_EXPLICIT_POLICY,
+        /// Don't let the any policy match other policies.
+        const X509_V_FLAG_INHIBIT_ANY = ffi::X509_V_FLAG_INHIBIT_ANY,
+        /// Disable policy mapping.
+        const X509_V_FLAG_INHIBIT_MAP = ffi::X509_V_FLAG_INHIBIT_MAP,
+        /// Support indirect CRLs and CRLs with alternate reasons.
+        const X509_V_FLAG_EXTENDED_CRL_SUPPORT = ffi::X509_V_FLAG_EXTENDED_CRL_SUPPORT,
+        /// Use delta CRLs where available.
+        const X509_V_FLAG_USE_DELTAS = ffi::X509_V_FLAG_USE_DELTAS,
+        /// Verify the signature of self-signed root certificates.
+        const X509_V_FLAG_CHECK_SS_SIGNATURE = ffi::X509_V_FLAG_CHECK_SS_SIGNATURE,
+        /// Prefer certificates from the store when building the chain.
+        ///
+        /// This allows a chain to be completed through a trusted root even if
+        /// the peer sends an expired cross-signed certificate in its place.
+        const X509_V_FLAG_TRUSTED_FIRST = ffi::X509_V_FLAG_TRUSTED_FIRST,
+        /// Accept chains that end in any trusted certificate, rather than
+        /// only in a self-signed root.
+        const X509_V_FLAG_PARTIAL_CHAIN = ffi::X509_V_FLAG_PARTIAL_CHAIN,
     }
 }
 
@@ -328,7 +585,7 @@ impl X509Generator {
     }
 
     /// Generates a private key and a self-signed certificate and returns them
-    pub fn generate<'a>(&self) -> Result<(X509<'a>, PKey), SslError> {
+    pub fn generate(&self) -> Result<(X509, PKey), SslError> {
         ffi::init();
 
         let mut p_key = PKey::new();
@@ -340,14 +597,14 @@ impl X509Generator {
 
     /// Sets the certificate public-key, then self-sign and return it
     /// Note: That the bit-length of the private key is used (set_bitlength is ignored)
-    pub fn sign<'a>(&self, p_key: &PKey) -> Result<X509<'a>, SslError> {
+    pub fn sign(&self, p_key: &PKey) -> Result<X509, SslError> {
         ffi::init();
 
         unsafe {
             let x509 = ffi::X509_new();
             try_ssl_null!(x509);
 
-            let x509 = X509 { handle: x509, ctx: None, owned: true};
+            let x509 = X509::new(x509);
 
             try_ssl!(ffi::X509_set_version(x509.handle, 2));
             try_ssl!(ffi::ASN1_INTEGER_set(ffi::X509_get_serialNumber(x509.handle), X509Generator::random_serial()));
@@ -405,56 +662,74 @@ impl X509Generator {
 }
 
 
-#[allow(dead_code)]
-/// A public key certificate
-pub struct X509<'ctx> {
-    ctx: Option<&'ctx X509StoreContext>,
+/// A borrowed public key certificate
+pub struct X509Ref<'a> {
     handle: *mut ffi::X509,
-    owned: bool
+    _marker: PhantomData<&'a ()>,
 }
 
-impl<'ctx> X509<'ctx> {
-    /// Creates new from handle with desired ownership.
-    pub fn new(handle: *mut ffi::X509, owned: bool) -> X509<'ctx> {
-        X509 {
-            ctx: None,
+impl<'a> X509Ref<'a> {
+    /// Creates a new `X509Ref` wrapping the provided handle. The handle is
+    /// not freed when the `X509Ref` is dropped.
+    pub fn new(handle: *mut ffi::X509) -> X509Ref<'a> {
+        X509Ref {
             handle: handle,
-            owned: owned,
+            _marker: PhantomData,
         }
     }
 
-    /// Creates a new certificate from context. Doesn't take ownership
-    /// of handle.
-    pub fn new_in_ctx(handle: *mut ffi::X509, ctx: &'ctx X509StoreContext) -> X509<'ctx> {
-        X509 {
-            ctx: Some(ctx),
-            handle: handle,
-            owned: false
-        }
+    pub fn get_handle(&self) -> *mut ffi::X509 {
+        self.handle
     }
 
-    /// Reads certificate from PEM, takes ownership of handle
-    pub fn from_pem<R>(reader: &mut R) -> Result<X509<'ctx>, SslError> where R: Read {
-        let mut mem_bio = try!(MemBio::new());
-        try!(io::copy(reader, &mut mem_bio).map_err(StreamError));
+    /// Returns an owned handle to the same certificate.
+    ///
+    /// This allows a certificate borrowed from a verify callback to be kept
+    /// after the callback returns.
+    pub fn to_owned(&self) -> X509 {
+        unsafe { ffi::X509_up_ref(self.handle) };
+        X509::new(self.handle)
+    }
+
+    /// Returns the version of the certificate.
+    ///
+    /// The version is zero-indexed, so X509v3 certificates return 2.
+    pub fn version(&self) -> i32 {
+        unsafe { ffi::X509_get_version(self.handle) as i32 }
+    }
 
+    /// Returns the long name of the algorithm used to sign the certificate,
+    /// e.g. `sha256WithRSAEncryption`.
+    pub fn signature_algorithm(&self) -> String {
         unsafe {
-            let handle = try_ssl_null!(ffi::PEM_read_bio_X509(mem_bio.get_handle(),
-                                                              ptr::null_mut(),
-                                                              None, ptr::null_mut()));
-            Ok(X509::new(handle, true))
+            let nid = ffi::X509_get_signature_nid(self.handle);
+            let name = ffi::OBJ_nid2ln(nid);
+            if name.is_null() {
+                return String::new();
+            }
+            String::from_utf8_lossy(CStr::from_ptr(name).to_bytes()).into_owned()
         }
     }
 
-    pub fn get_handle(&self) -> *mut ffi::X509 {
-        self.handle
+    /// Returns whether the certificate's basicConstraints extension marks it
+    /// as a certificate authority.
+    ///
+    /// A key usage extension which doesn't permit certificate signing also
+    /// causes this to return `false`.
+    pub fn is_ca(&self) -> bool {
+        unsafe { ffi::X509_check_ca(self.handle) == 1 }
     }
 
-    pub fn subject_name<'a>(&'a self) -> X509Name<'a> {
+    pub fn subject_name<'b>(&'b self) -> X509Name<'b> {
         let name = unsafe { ffi::X509_get_subject_name(self.handle) };
         X509Name { x509: self, name: name }
     }
 
+    pub fn issuer_name<'b>(&'b self) -> X509Name<'b> {
+        let name = unsafe { ffi::X509_get_issuer_name(self.handle) };
+        X509Name { x509: self, name: name }
+    }
+
     pub fn public_key(&self) -> PKey {
         let pkey = unsafe { ffi::X509_get_pubkey(self.handle) };
         assert!(!pkey.is_null());
@@ -462,6 +737,106 @@ impl<'ctx> X509<'ctx> {
         PKey::from_handle(pkey, Parts::Public)
     }
 
+    /// Returns the entries of the certificate's subject alternative name
+    /// extension, or `None` if it has none.
+    pub fn subject_alt_names(&self) -> Option<Vec<GeneralName>> {
+        unsafe {
+            let stack = ffi::X509_get_ext_d2i(self.handle, nid::Nid::SubjectAltName as c_int,
+                                              ptr::null_mut(), ptr::null_mut());
+            if stack.is_null() {
+                return None;
+            }
+
+            let names = (0..ffi::sk_num(stack)).map(|i| {
+                let name = ffi::sk_value(stack, i) as *mut ffi::GENERAL_NAME;
+                let value = GeneralName::from_raw(&*name);
+                ffi::GENERAL_NAME_free(name);
+                value
+            }).collect();
+            ffi::sk_free(stack);
+            Some(names)
+        }
+    }
+
+    /// Returns the certificate's key usage extension, or `None` if it has
+    /// none, in which case the key is not restricted.
+    pub fn key_usage(&self) -> Option<KeyUsageFlags> {
+        unsafe {
+            let usage = ffi::X509_get_ext_d2i(self.handle, nid::Nid::KeyUsage as c_int,
+                                              ptr::null_mut(), ptr::null_mut());
+            if usage.is_null() {
+                return None;
+            }
+
+            // The bit string holds the first eight bits in its first byte,
+            // and decipherOnly in the high bit of the second
+            let usage = usage as *mut ffi::ASN1_STRING;
+            let bytes = slice::from_raw_parts(ffi::ASN1_STRING_data(usage),
+                                              ffi::ASN1_STRING_length(usage) as usize);
+            let mut bits = 0;
+            if bytes.len() > 0 {
+                bits |= bytes[0] as u32;
+            }
+            if bytes.len() > 1 {
+                bits |= (bytes[1] as u32) << 8;
+            }
+            ffi::ASN1_STRING_free(usage);
+            Some(KeyUsageFlags::from_bits_truncate(bits))
+        }
+    }
+
+    /// Returns the purposes listed in the certificate's extended key usage
+    /// extension, or `None` if it has none.
+    ///
+    /// Purposes without a corresponding `Nid` are left out.
+    pub fn extended_key_usage(&self) -> Option<Vec<nid::Nid>> {
+        unsafe {
+            let stack = ffi::X509_get_ext_d2i(self.handle, nid::Nid::ExtendedKeyUsage as c_int,
+                                              ptr::null_mut(), ptr::null_mut());
+            if stack.is_null() {
+                return None;
+            }
+
+            let purposes = (0..ffi::sk_num(stack)).filter_map(|i| {
+                let obj = ffi::sk_value(stack, i) as *mut ffi::ASN1_OBJECT;
+                let nid = nid::Nid::from_raw(ffi::OBJ_obj2nid(obj));
+                ffi::ASN1_OBJECT_free(obj);
+                nid
+            }).collect();
+            ffi::sk_free(stack);
+            Some(purposes)
+        }
+    }
+
+    /// Checks whether the certificate is valid for `host`, using OpenSSL's
+    /// implementation of the rules of RFC 6125.
+    ///
+    /// If `host` is an IP address it is compared against the iPAddress
+    /// entries of the subject alternative name extension. Otherwise it is
+    /// compared against the dNSName entries, or against the subject's common
+    /// name if there are none. A wildcard is only honored when it makes up
+    /// the entire leftmost label, and then matches exactly one label.
+    pub fn verify_hostname(&self, host: &str) -> bool {
+        // A fully qualified name's trailing dot isn't part of the names in
+        // certificates
+        let host = host.trim_right_matches('.');
+        if host.is_empty() {
+            return false;
+        }
+
+        unsafe {
+            if host.parse::<IpAddr>().is_ok() {
+                let host = CString::new(host).unwrap();
+                return ffi::X509_check_ip_asc(self.handle, host.as_ptr(), 0) == 1;
+            }
+
+            ffi::X509_check_host(self.handle, host.as_ptr() as *const c_char,
+                                 host.len() as size_t,
+                                 ffi::X509_CHECK_FLAG_NO_PARTIAL_WILDCARDS,
+                                 ptr::null_mut()) == 1
+        }
+    }
+
     /// Returns certificate fingerprint calculated using provided hash
     pub fn fingerprint(&self, hash_type: hash::Type) -> Option<Vec<u8>> {
         let evp = hash_type.evp_md();
@@ -495,19 +870,212 @@ impl<'ctx> X509<'ctx> {
         }
         io::copy(&mut mem_bio, writer).map_err(StreamError).map(|_| ())
     }
+
+    /// Returns a human readable description of the certificate, in the
+    /// format of `openssl x509 -text`.
+    pub fn to_text(&self) -> Result<String, SslError> {
+        let mut mem_bio = try!(MemBio::new());
+        unsafe {
+            try_ssl!(ffi::X509_print(mem_bio.get_handle(), self.handle));
+        }
+        let mut text = String::new();
+        try!(mem_bio.read_to_string(&mut text).map_err(StreamError));
+        Ok(text)
+    }
+
+    /// Returns the DER encoding of this certificate.
+    pub fn to_der(&self) -> Vec<u8> {
+        unsafe {
+            let len = ffi::i2d_X509(self.handle, ptr::null_mut());
+            if len < 0 {
+                return vec![];
+            }
+            let mut buf = vec![0u8; len as usize];
+            let len = ffi::i2d_X509(self.handle, &mut buf.as_mut_ptr());
+            buf.truncate(len as usize);
+            buf
+        }
+    }
+
+    /// Returns the time before which the certificate is not valid
+    pub fn not_before<'b>(&'b self) -> Asn1TimeRef<'b> {
+        Asn1TimeRef::new(unsafe { ffi::X509_get_notBefore(self.handle) })
+    }
+
+    /// Returns the time after which the certificate is not valid
+    pub fn not_after<'b>(&'b self) -> Asn1TimeRef<'b> {
+        Asn1TimeRef::new(unsafe { ffi::X509_get_notAfter(self.handle) })
+    }
+
+    /// Returns the number of whole days left until the certificate expires.
+    ///
+    /// The result is negative once the certificate has expired, and is
+    /// measured against the local clock, so a skewed clock skews the result
+    /// by the same amount. `None` is returned if the expiry time cannot be
+    /// parsed.
+    pub fn days_until_expiry(&self) -> Option<i32> {
+        const SECS_PER_DAY: i64 = 60 * 60 * 24;
+        // Validity periods beyond a century either way are clamped
+        const MAX_DAYS: i32 = 100 * 366;
+
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(_) => return None,
+        };
+
+        unsafe {
+            let not_after = ffi::X509_get_notAfter(self.handle);
+            // X509_cmp_time returns 1 if not_after is later than the given
+            // time, -1 if it is not and 0 on error
+            let cmp = |days: i32| {
+                let mut t = (now + days as i64 * SECS_PER_DAY) as time_t;
+                ffi::X509_cmp_time(not_after, &mut t)
+            };
+
+            // Find the largest day count which still lies before the expiry
+            let (mut lo, mut hi) = (-MAX_DAYS, MAX_DAYS);
+            match (cmp(lo), cmp(hi)) {
+                (0, _) | (_, 0) => return None,
+                (-1, _) => return Some(lo),
+                (_, 1) => return Some(hi),
+                _ => {}
+            }
+            while hi - lo > 1 {
+                let mid = lo + (hi - lo) / 2;
+                match cmp(mid) {
+                    0 => return None,
+                    1 => lo = mid,
+                    _ => hi = mid,
+                }
+            }
+            Some(lo)
+        }
+    }
 }
 
-impl<'ctx> Drop for X509<'ctx> {
+/// An owned public key certificate
+pub struct X509(X509Ref<'static>);
+
+impl X509 {
+    /// Creates a new `X509` from a handle, taking ownership of it.
+    pub fn new(handle: *mut ffi::X509) -> X509 {
+        X509(X509Ref::new(handle))
+    }
+
+    /// Reads certificate from PEM, takes ownership of handle
+    pub fn from_pem<R>(reader: &mut R) -> Result<X509, SslError> where R: Read {
+        let mut mem_bio = try!(MemBio::new());
+        try!(io::copy(reader, &mut mem_bio).map_err(StreamError));
+
+        unsafe {
+            let handle = try_ssl_null!(ffi::PEM_read_bio_X509(mem_bio.get_handle(),
+                                                              ptr::null_mut(),
+                                                              None, ptr::null_mut()));
+            Ok(X509::new(handle))
+        }
+    }
+
+    /// Reads every certificate in a PEM bundle, in order
+    pub fn stack_from_pem(pem: &[u8]) -> Result<Vec<X509>, SslError> {
+        let mut mem_bio = try!(MemBio::new());
+        try!(mem_bio.write_all(pem).map_err(StreamError));
+
+        let mut certs = vec![];
+        unsafe {
+            loop {
+                let handle = ffi::PEM_read_bio_X509(mem_bio.get_handle(), ptr::null_mut(),
+                                                    None, ptr::null_mut());
+                if handle.is_null() {
+                    // Running out of input is reported as a missing PEM header
+                    let err = ffi::ERR_peek_last_error();
+                    if ffi::ERR_GET_LIB(err) == ffi::ERR_LIB_PEM &&
+                       ffi::ERR_GET_REASON(err) == ffi::PEM_R_NO_START_LINE {
+                        ffi::ERR_clear_error();
+                        break;
+                    }
+                    return Err(SslError::get());
+                }
+                certs.push(X509::new(handle));
+            }
+        }
+        Ok(certs)
+    }
+
+    /// Reads a DER-encoded certificate
+    pub fn from_der(der: &[u8]) -> Result<X509, SslError> {
+        unsafe {
+            let mut ptr = der.as_ptr();
+            let handle = try_ssl_null!(ffi::d2i_X509(ptr::null_mut(), &mut ptr,
+                                                     der.len() as c_long));
+            Ok(X509::new(handle))
+        }
+    }
+}
+
+impl Deref for X509 {
+    type Target = X509Ref<'static>;
+
+    fn deref(&self) -> &X509Ref<'static> {
+        &self.0
+    }
+}
+
+impl Drop for X509 {
     fn drop(&mut self) {
-        if self.owned {
-            unsafe { ffi::X509_free(self.handle) };
+        unsafe { ffi::X509_free(self.handle) };
+    }
+}
+
+/// An entry of an alternative name extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeneralName {
+    Email(String),
+    DNS(String),
+    URI(String),
+    IPAddress(IpAddr),
+    /// A kind of name which is not decoded
+    Other,
+}
+
+impl GeneralName {
+    unsafe fn from_raw(name: &ffi::GENERAL_NAME) -> GeneralName {
+        match name.type_ {
+            ffi::GEN_EMAIL | ffi::GEN_DNS | ffi::GEN_URI | ffi::GEN_IPADD => {}
+            _ => return GeneralName::Other,
+        }
+
+        // All of the decoded kinds of names are stored as an ASN1_STRING
+        let data = name.d as *mut ffi::ASN1_STRING;
+        let bytes = slice::from_raw_parts(ffi::ASN1_STRING_data(data),
+                                          ffi::ASN1_STRING_length(data) as usize);
+        let string = || String::from_utf8_lossy(bytes).into_owned();
+
+        match name.type_ {
+            ffi::GEN_EMAIL => GeneralName::Email(string()),
+            ffi::GEN_DNS => GeneralName::DNS(string()),
+            ffi::GEN_URI => GeneralName::URI(string()),
+            // iPAddress entries hold the address in network byte order
+            _ if bytes.len() == 4 => {
+                let addr = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+                GeneralName::IPAddress(IpAddr::V4(addr))
+            }
+            _ if bytes.len() == 16 => {
+                let mut segments = [0u16; 8];
+                for (i, segment) in segments.iter_mut().enumerate() {
+                    *segment = (bytes[2 * i] as u16) << 8 | bytes[2 * i + 1] as u16;
+                }
+                let addr = Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                         segments[4], segments[5], segments[6], segments[7]);
+                GeneralName::IPAddress(IpAddr::V6(addr))
+            }
+            _ => GeneralName::Other,
         }
     }
 }
 
 #[allow(dead_code)]
 pub struct X509Name<'x> {
-    x509: &'x X509<'x>,
+    x509: &'x X509Ref<'x>,
     name: *mut ffi::X509_NAME
 }
 
@@ -530,21 +1098,139 @@ impl <'x> X509Name<'x> {
                 return None;
             }
 
-            let asn1_str = ffi::X509_NAME_ENTRY_get_data(ne);
-            if asn1_str.is_null() {
-                return None;
-            }
+            entry_text(ne)
+        }
+    }
 
-            let mut str_from_asn1 : *mut c_char = ptr::null_mut();
-            let len = ffi::ASN1_STRING_to_UTF8(&mut str_from_asn1, asn1_str);
+    /// Returns every entry of the name in order, including repeated
+    /// attributes.
+    ///
+    /// Attributes whose NID is not known to this crate are reported as
+    /// `Nid::Undefined`.
+    pub fn entries(&self) -> Vec<(nid::Nid, String)> {
+        unsafe {
+            (0..ffi::X509_NAME_entry_count(self.name)).filter_map(|loc| {
+                let ne = ffi::X509_NAME_get_entry(self.name, loc);
+                if ne.is_null() {
+                    return None;
+                }
+
+                let raw = ffi::OBJ_obj2nid(ffi::X509_NAME_ENTRY_get_object(ne));
+                let nid = nid::Nid::from_raw(raw).unwrap_or(nid::Nid::Undefined);
+                entry_text(ne).map(|text| (nid, text.to_string()))
+            }).collect()
+        }
+    }
 
+    /// Returns the DER encoding of this name.
+    ///
+    /// Unlike the printed form, the encoding can be compared byte for byte to
+    /// match a certificate's issuer against another certificate's subject.
+    pub fn to_der(&self) -> Vec<u8> {
+        unsafe {
+            let len = ffi::i2d_X509_NAME(self.name, ptr::null_mut());
             if len < 0 {
-                return None
+                return vec![];
             }
+            let mut buf = vec![0u8; len as usize];
+            let len = ffi::i2d_X509_NAME(self.name, &mut buf.as_mut_ptr());
+            buf.truncate(len as usize);
+            buf
+        }
+    }
+}
+
+unsafe fn entry_text(ne: *mut ffi::X509_NAME_ENTRY) -> Option<SslString> {
+    let asn1_str = ffi::X509_NAME_ENTRY_get_data(ne);
+    if asn1_str.is_null() {
+        return None;
+    }
+
+    let mut str_from_asn1 : *mut c_char = ptr::null_mut();
+    let len = ffi::ASN1_STRING_to_UTF8(&mut str_from_asn1, asn1_str);
+
+    if len < 0 {
+        return None
+    }
+
+    assert!(!str_from_asn1.is_null());
+
+    Some(SslString::new(str_from_asn1))
+}
+
+/// Builds certificate signing requests (CSR).
+///
+/// Unlike `X509Generator::request`, the request is created directly rather
+/// than from a self-signed certificate, so the key can come from anywhere.
+///
+/// ```
+/// # use openssl::crypto::pkey::PKey;
+/// # use openssl::x509::X509ReqBuilder;
+/// let pkey = PKey::generate_rsa(2048).unwrap();
+/// let req = X509ReqBuilder::new()
+///     .add_name("CN".to_string(), "example.com".to_string())
+///     .sign(&pkey)
+///     .unwrap();
+/// ```
+pub struct X509ReqBuilder {
+    names: Vec<(String,String)>,
+    hash_type: HashType,
+}
+
+impl X509ReqBuilder {
+    /// Creates a new builder with an empty subject, signing with SHA256.
+    pub fn new() -> X509ReqBuilder {
+        X509ReqBuilder {
+            names: vec![],
+            hash_type: HashType::SHA256,
+        }
+    }
+
+    /// Add attribute to the subject of the request
+    pub fn add_name(mut self, attr_type: String, attr_value: String) -> X509ReqBuilder {
+        self.names.push((attr_type,attr_value));
+        self
+    }
 
-            assert!(!str_from_asn1.is_null());
+    /// Add multiple attributes to the subject of the request
+    pub fn add_names<I>(mut self, attrs: I) -> X509ReqBuilder
+        where I: IntoIterator<Item=(String,String)> {
+        self.names.extend(attrs);
+        self
+    }
+
+    /// Sets the hash used to sign the request
+    pub fn set_sign_hash(mut self, hash_type: hash::Type) -> X509ReqBuilder {
+        self.hash_type = hash_type;
+        self
+    }
 
-            Some(SslString::new(str_from_asn1))
+    /// Creates a request for the public half of `p_key`, signed with its
+    /// private half.
+    pub fn sign(&self, p_key: &PKey) -> Result<X509Req, SslError> {
+        ffi::init();
+
+        unsafe {
+            let req = X509Req::new(try_ssl_null!(ffi::X509_REQ_new()));
+            // Version 1, which is the only one defined
+            try_ssl!(ffi::X509_REQ_set_version(req.handle, 0));
+
+            let name = try_ssl_null!(ffi::X509_NAME_new());
+            for &(ref key, ref val) in self.names.iter() {
+                if let Err(e) = X509Generator::add_name_internal(name, key, val) {
+                    ffi::X509_NAME_free(name);
+                    return Err(e);
+                }
+            }
+            // The name is copied into the request
+            let set = ffi::X509_REQ_set_subject_name(req.handle, name);
+            ffi::X509_NAME_free(name);
+            try_ssl!(set);
+
+            try_ssl!(ffi::X509_REQ_set_pubkey(req.handle, p_key.get_handle()));
+            try_ssl!(ffi::X509_REQ_sign(req.handle, p_key.get_handle(),
+                                        self.hash_type.evp_md()));
+            Ok(req)
         }
     }
 }
@@ -584,6 +1270,24 @@ impl X509Req {
         }
         io::copy(&mut mem_bio, writer).map_err(StreamError).map(|_| ())
     }
+
+    /// Returns the public key the request is for.
+    pub fn public_key(&self) -> Result<PKey, SslError> {
+        unsafe {
+            let pkey = try_ssl_null!(ffi::X509_REQ_get_pubkey(self.handle));
+            Ok(PKey::from_handle(pkey, Parts::Public))
+        }
+    }
+
+    /// Checks whether the request is signed by the private half of `key`.
+    pub fn verify(&self, key: &PKey) -> bool {
+        let ret = unsafe { ffi::X509_REQ_verify(self.handle, key.get_handle()) };
+        if ret <= 0 {
+            // Clear the reason for the failure from the error queue
+            SslError::get();
+        }
+        ret == 1
+    }
 }
 
 impl Drop for X509Req {
@@ -609,6 +1313,14 @@ macro_rules! make_validation_error(
                     err => Some(X509ValidationError::X509UnknownError(err))
                 }
             }
+
+            #[doc(hidden)]
+            pub fn to_raw(&self) -> c_int {
+                match *self {
+                    $(X509ValidationError::$name => ffi::$val,)+
+                    X509ValidationError::X509UnknownError(err) => err
+                }
+            }
         }
     )
 );
@@ -666,6 +1378,9 @@ make_validation_error!(X509_V_OK,
     X509UnsupportedConstraintSyntax = X509_V_ERR_UNSUPPORTED_CONSTRAINT_SYNTAX,
     X509UnsupportedNameSyntax = X509_V_ERR_UNSUPPORTED_NAME_SYNTAX,
     X509CrlPathValidationError= X509_V_ERR_CRL_PATH_VALIDATION_ERROR,
+    X509HostnameMismatch = X509_V_ERR_HOSTNAME_MISMATCH,
+    X509IpAddressMismatch = X509_V_ERR_IP_ADDRESS_MISMATCH,
+    X509EmailMismatch = X509_V_ERR_EMAIL_MISMATCH,
     X509ApplicationVerification = X509_V_ERR_APPLICATION_VERIFICATION,
 );
 
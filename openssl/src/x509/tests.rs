@@ -1,10 +1,14 @@
 use serialize::hex::FromHex;
 use std::io;
+use std::io::prelude::*;
 use std::path::Path;
 use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+use asn1::Tm;
 use crypto::hash::Type::{SHA256};
-use x509::{X509, X509Generator};
+use crypto::pkey::PKey;
+use x509::{X509, X509Generator, X509Req, X509ReqBuilder, GeneralName, KU_DIGITAL_SIGNATURE, KU_KEY_ENCIPHERMENT};
 use x509::extension::Extension::{KeyUsage,ExtKeyUsage,SubjectAltName,OtherNid,OtherStr};
 use x509::extension::AltNameOption as SAN;
 use x509::extension::KeyUsageOption::{DigitalSignature, KeyEncipherment};
@@ -34,6 +38,129 @@ fn test_cert_gen() {
     assert_eq!(pkey.save_pub(), cert.public_key().save_pub());
 }
 
+#[test]
+fn test_req_builder() {
+    let pkey = PKey::generate_rsa(2048).unwrap();
+    let req = X509ReqBuilder::new()
+        .add_name("CN".to_string(), "test_me".to_string())
+        .add_name("O".to_string(), "rust-openssl".to_string())
+        .sign(&pkey)
+        .unwrap();
+
+    let mut pem = vec![];
+    req.write_pem(&mut pem).unwrap();
+    assert!(pem.starts_with(b"-----BEGIN CERTIFICATE REQUEST-----"));
+
+    let req = X509Req::from_pem(&mut &pem[..]).unwrap();
+    assert!(req.public_key().unwrap().public_eq(&pkey));
+    assert!(req.verify(&pkey));
+    assert!(!req.verify(&PKey::generate_rsa(2048).unwrap()));
+
+    assert!(X509ReqBuilder::new()
+        .add_name("not-an-attribute".to_string(), "test_me".to_string())
+        .sign(&pkey)
+        .is_err());
+}
+
+#[test]
+fn test_days_until_expiry() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(),"test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+    let days = cert.days_until_expiry().unwrap();
+    assert!(days == 364 || days == 365);
+
+    let cert_path = Path::new("test/nid_test_cert.pem");
+    let mut file = File::open(&cert_path)
+        .ok()
+        .expect("Failed to open `test/nid_test_cert.pem`");
+    let cert = X509::from_pem(&mut file).ok().expect("Failed to load PEM");
+    assert!(cert.days_until_expiry().unwrap() < 0);
+}
+
+#[test]
+fn test_subject_alt_names() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(),"test_me".to_string())
+        .add_extension(SubjectAltName(vec![(SAN::DNS,"example.com".to_owned()),
+                                           (SAN::IPAddress,"10.0.0.1".to_owned()),
+                                           (SAN::IPAddress,"::1".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+
+    let names = cert.subject_alt_names().unwrap();
+    assert_eq!(names, vec![GeneralName::DNS("example.com".to_owned()),
+                           GeneralName::IPAddress(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+                           GeneralName::IPAddress(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)))]);
+}
+
+#[test]
+fn test_verify_hostname() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(),"test_me".to_string())
+        .add_extension(SubjectAltName(vec![(SAN::DNS,"*.example.com".to_owned()),
+                                           (SAN::DNS,"foo.bar.org".to_owned()),
+                                           (SAN::IPAddress,"10.0.0.1".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+
+    assert!(cert.verify_hostname("www.example.com"));
+    assert!(cert.verify_hostname("WWW.Example.COM."));
+    assert!(cert.verify_hostname("foo.bar.org"));
+    assert!(cert.verify_hostname("10.0.0.1"));
+    assert!(!cert.verify_hostname("example.com"));
+    assert!(!cert.verify_hostname("a.b.example.com"));
+    assert!(!cert.verify_hostname("bar.org"));
+    assert!(!cert.verify_hostname("10.0.0.2"));
+    // The common name is ignored when DNS names are present
+    assert!(!cert.verify_hostname("test_me"));
+
+    // Certificates without alternative names fall back to the common name
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    assert!(cert.verify_hostname("test_cert"));
+    assert!(!cert.verify_hostname("example.com"));
+}
+
+#[test]
+fn test_is_ca() {
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    assert!(cert.is_ca());
+
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(),"test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+    assert!(!cert.is_ca());
+
+    let gen = gen.add_extension(OtherNid(Nid::BasicConstraints,"critical,CA:FALSE".to_owned()));
+    let (cert, _) = gen.generate().unwrap();
+    assert!(!cert.is_ca());
+}
+
+#[test]
+fn test_key_usage() {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(365)
+        .add_name("CN".to_string(),"test_me".to_string());
+    let (cert, _) = gen.generate().unwrap();
+    assert!(cert.key_usage().is_none());
+    assert!(cert.extended_key_usage().is_none());
+
+    let gen = gen
+        .add_extension(KeyUsage(vec![DigitalSignature, KeyEncipherment]))
+        .add_extension(ExtKeyUsage(vec![ServerAuth, ClientAuth,
+                                        ExtKeyUsageOption::Other("2.999.1".to_owned())]));
+    let (cert, _) = gen.generate().unwrap();
+    assert_eq!(cert.key_usage(), Some(KU_DIGITAL_SIGNATURE | KU_KEY_ENCIPHERMENT));
+    assert!(cert.extended_key_usage() == Some(vec![Nid::ServerAuth, Nid::ClientAuth]));
+}
+
 #[test]
 fn test_cert_loading() {
     let cert_path = Path::new("test/cert.pem");
@@ -54,6 +181,85 @@ fn test_cert_loading() {
     assert_eq!(fingerprint, hash_vec);
 }
 
+#[test]
+fn test_cert_loading_der() {
+    let mut der = vec![];
+    File::open("test/cert.der").unwrap().read_to_end(&mut der).unwrap();
+    let cert = X509::from_der(&der).unwrap();
+
+    let pem = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    assert_eq!(cert.fingerprint(SHA256).unwrap(), pem.fingerprint(SHA256).unwrap());
+}
+
+#[test]
+fn test_cert_to_der() {
+    let mut der = vec![];
+    File::open("test/cert.der").unwrap().read_to_end(&mut der).unwrap();
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    assert_eq!(cert.to_der(), der);
+}
+
+#[test]
+fn test_cert_to_text() {
+    let cert = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    let text = cert.to_text().unwrap();
+    assert!(text.starts_with("Certificate:\n"));
+    assert!(text.contains("Subject: C=AU, ST=Some-State, O=Internet Widgits Pty Ltd, CN=test_cert"));
+    assert!(text.contains("Not After : May 10 17:24:18 2016 GMT"));
+}
+
+#[test]
+fn test_stack_from_pem() {
+    let mut pem = vec![];
+    File::open("test/cert.pem").unwrap().read_to_end(&mut pem).unwrap();
+    File::open("test/nid_test_cert.pem").unwrap().read_to_end(&mut pem).unwrap();
+
+    let certs = X509::stack_from_pem(&pem).unwrap();
+    assert_eq!(certs.len(), 2);
+    let first = X509::from_pem(&mut File::open("test/cert.pem").unwrap()).unwrap();
+    let second = X509::from_pem(&mut File::open("test/nid_test_cert.pem").unwrap()).unwrap();
+    assert_eq!(certs[0].fingerprint(SHA256).unwrap(), first.fingerprint(SHA256).unwrap());
+    assert_eq!(certs[1].fingerprint(SHA256).unwrap(), second.fingerprint(SHA256).unwrap());
+
+    assert_eq!(X509::stack_from_pem(b"").unwrap().len(), 0);
+    assert!(X509::stack_from_pem(b"-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n")
+        .is_err());
+}
+
+#[test]
+fn test_validity_period() {
+    let mut file = File::open("test/cert.pem").unwrap();
+    let cert = X509::from_pem(&mut file).unwrap();
+
+    let not_before = cert.not_before().to_tm().unwrap();
+    let not_after = cert.not_after().to_tm().unwrap();
+    assert_eq!(not_before, Tm { year: 2015, month: 5, day: 11, hour: 17, minute: 24, second: 18 });
+    assert_eq!(not_after, Tm { year: 2016, month: 5, day: 10, hour: 17, minute: 24, second: 18 });
+    assert!(not_before < not_after);
+}
+
+#[test]
+fn test_version_and_signature_algorithm() {
+    let mut file = File::open("test/cert.pem").unwrap();
+    let cert = X509::from_pem(&mut file).unwrap();
+    assert_eq!(cert.version(), 2);
+    assert_eq!(cert.signature_algorithm(), "sha256WithRSAEncryption");
+}
+
+#[test]
+fn test_to_owned() {
+    let cert_path = Path::new("test/cert.pem");
+    let mut file = File::open(&cert_path)
+        .ok()
+        .expect("Failed to open `test/cert.pem`");
+
+    let cert = X509::from_pem(&mut file).ok().expect("Failed to load PEM");
+    let fingerprint = cert.fingerprint(SHA256).unwrap();
+    let owned: X509 = cert.to_owned();
+    drop(cert);
+    assert_eq!(owned.fingerprint(SHA256).unwrap(), fingerprint);
+}
+
 #[test]
 fn test_subject_read_cn() {
     let cert_path = Path::new("test/cert.pem");
@@ -71,6 +277,36 @@ fn test_subject_read_cn() {
     assert_eq!(&cn as &str, "test_cert")
 }
 
+#[test]
+fn test_name_to_der() {
+    let cert_path = Path::new("test/cert.pem");
+    let mut file = File::open(&cert_path)
+        .ok()
+        .expect("Failed to open `test/cert.pem`");
+
+    let cert = X509::from_pem(&mut file).ok().expect("Failed to load PEM");
+    let subject = cert.subject_name().to_der();
+    // A name is encoded as a DER SEQUENCE
+    assert_eq!(subject[0], 0x30);
+    // The certificate is self-signed
+    assert_eq!(subject, cert.issuer_name().to_der());
+}
+
+#[test]
+fn test_name_entries() {
+    let cert_path = Path::new("test/cert.pem");
+    let mut file = File::open(&cert_path)
+        .ok()
+        .expect("Failed to open `test/cert.pem`");
+
+    let cert = X509::from_pem(&mut file).ok().expect("Failed to load PEM");
+    let entries = cert.subject_name().entries();
+    let nids: Vec<Nid> = entries.iter().map(|&(nid, _)| nid).collect();
+    assert!(nids == vec![Nid::C, Nid::ST, Nid::O, Nid::CN]);
+    assert_eq!(entries[0].1, "AU");
+    assert_eq!(entries[3].1, "test_cert");
+}
+
 #[test]
 fn test_nid_values() {
     let cert_path = Path::new("test/nid_test_cert.pem");